@@ -1,32 +1,34 @@
-use std::vec;
-
-use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::channel;
 
 use crate::config::get_db_path;
+use crate::db::entity::annotation::{AnnotateVO, Annotation};
 use crate::db::entity::basket::{Basket, BasketData, BasketVO};
+use crate::db::entity::duplicate::{
+    list_duplicates, DuplicateGroupVO, DuplicateLog, DuplicateLogVO, DuplicateQuery,
+};
 use crate::db::entity::folder::{Folder, FolderVO};
+use crate::db::entity::image_hash::{self, SimilarGroupVO, SimilarQuery};
 use crate::db::entity::metadata::{Metadata, MetadataVO};
-use crate::db::sqlite::Session;
-use crate::file::image_scanner::ImageScanner;
-use crate::file::model_scanner::ModelScanner;
-use crate::file::psd_scanner::PsdScanner;
-use crate::file::raw_scanner::RawScanner;
+use crate::db::entity::palette::{self, ColorFilter, ColorSearchVO};
+use crate::db::entity::query::{MetadataQuery, Page, PageVO};
+use crate::db::entity::search::{self, SearchVO};
+use crate::db::entity::tag::{Tag, TagData, TagVO};
+use crate::db::entity::task::{Task, TaskVO};
+use crate::db::entity::video::{VideoInfo, VideoPreviewVO};
+use crate::db::sqlite::{like_contains, like_prefix, Session};
+use crate::file::dedupe::{self, DuplicateResolution, ResolveVO};
+use crate::file::job::{self, JobInfo, JobState};
+use crate::file::preview::preview_path;
 use crate::file::scan::{ScanJob, ScanMsg};
-use crate::file::video_scanner::VideoScanner;
+use crate::file::registry::scanners;
+use crate::file::watch;
 use crate::util::error::ErrorHandle;
 
 #[tauri::command]
 pub fn create_basket(basket: BasketData) -> &'static str {
     let (tx, rx) = channel::<ScanMsg>(16);
     let mut scan = ScanJob::new(tx);
-    scan.add_scanners(vec![
-        ImageScanner::wrap(),
-        ModelScanner::wrap(),
-        VideoScanner::wrap(),
-        RawScanner::wrap(),
-        PsdScanner::wrap(),
-    ]);
+    scan.add_scanners(scanners());
     scan.monitor_async(rx);
     scan.run_async(basket);
     "OK"
@@ -36,45 +38,232 @@ pub fn create_basket(basket: BasketData) -> &'static str {
 pub fn run_task() -> &'static str {
     let (tx, rx) = channel::<ScanMsg>(16);
     let mut scan = ScanJob::new(tx);
-    scan.add_scanners(vec![
-        ImageScanner::wrap(),
-        ModelScanner::wrap(),
-        VideoScanner::wrap(),
-        RawScanner::wrap(),
-        PsdScanner::wrap(),
-    ]);
+    scan.add_scanners(scanners());
     scan.monitor_async(rx);
     scan.run_task_async();
     "OK"
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Page {
-    size: usize,
-    current: usize,
+#[tauri::command]
+pub fn rescan_basket(id: String) -> &'static str {
+    let (tx, rx) = channel::<ScanMsg>(16);
+    let mut scan = ScanJob::new(tx);
+    scan.add_scanners(scanners());
+    scan.monitor_async(rx);
+    scan.rescan_async(id);
+    "OK"
+}
+
+/// 获取多次重试后仍然失败的任务
+#[tauri::command]
+pub async fn get_failed_tasks() -> Vec<TaskVO> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    Task::list_failed(&session)
+        .await
+        .into_iter()
+        .map(TaskVO::from)
+        .collect()
+}
+
+/// 手动重试任务，包括已标记为失败的任务
+#[tauri::command]
+pub async fn retry_tasks(ids: Vec<String>) -> bool {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    Task::retry(&session, &parse_ids(&ids)).await
 }
 
+#[tauri::command]
+pub fn list_jobs() -> Vec<JobInfo> {
+    job::list()
+}
+
+#[tauri::command]
+pub fn pause_job(id: String) -> bool {
+    job::set_state(&id, JobState::Paused)
+}
+
+#[tauri::command]
+pub fn resume_job(id: String) -> bool {
+    job::set_state(&id, JobState::Running)
+}
+
+#[tauri::command]
+pub fn cancel_job(id: String) -> bool {
+    job::set_state(&id, JobState::Canceled)
+}
+
+/// 按拍摄时间排序，没有EXIF时使用文件创建时间
+const ORDER_BY_CAPTURED: &str = "COALESCE(NULLIF(e.captured, ''), m.created) DESC";
+
 #[tauri::command]
 pub async fn get_metadata() -> Vec<MetadataVO> {
     let mut session = Session::new(get_db_path());
     session.connect().await;
     if let Some(metadata) = session
-        .select_as::<Metadata>("SELECT * FROM metadata WHERE is_del = 0")
+        .select_as::<Metadata>(&format!(
+            "SELECT m.* FROM metadata m LEFT JOIN exif e ON e.metadata_id = m.id WHERE m.is_del = 0 ORDER BY {}",
+            ORDER_BY_CAPTURED
+        ))
         .await
         .print_error()
     {
-        return metadata.into_iter().map(|v| MetadataVO::from(v)).collect();
+        return MetadataVO::list(&session, metadata).await;
     }
     Vec::new()
 }
 
+/// 分页查询，支持排序和筛选，大量数据时使用
+#[tauri::command]
+pub async fn query_metadata(query: MetadataQuery) -> PageVO<MetadataVO> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    query.execute(&session).await
+}
+
+/// 全文搜索文件名、路径、标签、备注和EXIF关键词
+#[tauri::command]
+pub async fn search_metadata(keyword: String, page: Option<Page>) -> SearchVO {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    search::search(&session, &keyword, page).await
+}
+
+/// 按主题色搜索，按颜色接近程度和占比排序
+#[tauri::command]
+pub async fn search_by_color(filter: ColorFilter, page: Option<Page>) -> ColorSearchVO {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    palette::search_by_color(&session, &filter, page).await
+}
+
+/// 查找缩放、重新编码或轻微裁剪后的相似图片，每组推荐保留分辨率最高的文件
+#[tauri::command]
+pub async fn find_similar(query: SimilarQuery) -> Vec<SimilarGroupVO> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    image_hash::find_similar(&session, &query).await
+}
+
+/// 按sha1列出内容相同的文件
+#[tauri::command]
+pub async fn get_duplicates(query: DuplicateQuery) -> PageVO<DuplicateGroupVO> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    list_duplicates(&session, &query).await
+}
+
+/// 每组保留一个文件，其余副本移到回收站或隔离目录
+#[tauri::command]
+pub async fn resolve_duplicates(resolutions: Vec<DuplicateResolution>) -> ResolveVO {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    dedupe::resolve(&session, &resolutions).await
+}
+
+/// 重复文件的处理记录
+#[tauri::command]
+pub async fn get_duplicate_log(page: Option<Page>) -> PageVO<DuplicateLogVO> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    DuplicateLog::list(&session, page).await
+}
+
+/// 所有标签，前端按`pid`组织层级
+#[tauri::command]
+pub async fn get_tags() -> Vec<TagVO> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    Tag::list(&session).await
+}
+
+/// 创建标签，`path`可以包含上级，如`people/alice`
+#[tauri::command]
+pub async fn create_tag(path: String, color: Option<String>) -> Option<TagVO> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    Tag::create(&session, &path, color).await
+}
+
+#[tauri::command]
+pub async fn update_tag(id: String, data: TagData) -> Option<TagVO> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    Tag::update(&session, id.parse().ok()?, &data).await
+}
+
+/// 合并标签，返回被其他软件修改过、没有写入的sidecar
+#[tauri::command]
+pub async fn merge_tags(sources: Vec<String>, target: String) -> Result<Vec<String>, String> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    let target = target.parse().map_err(|_| format!("无效的标签id：{target}"))?;
+    Tag::merge(&session, &parse_ids(&sources), target)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 删除标签和所有子标签，返回被其他软件修改过、没有写入的sidecar
+#[tauri::command]
+pub async fn delete_tags(ids: Vec<String>) -> Result<Vec<String>, String> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    Tag::delete(&session, &parse_ids(&ids))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 批量修改选中文件的标签，`add`和`remove`为标签的完整名称或别名
+///
+/// 返回被其他软件修改过、没有写入的sidecar
+#[tauri::command]
+pub async fn tag_metadata(
+    ids: Vec<String>,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Vec<String>, String> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    Tag::assign(&session, &parse_ids(&ids), &add, &remove)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 批量修改选中文件的评分、颜色标记、标题、备注和来源网址，返回修改后的文件
+///
+/// 标注无效时返回错误
+#[tauri::command]
+pub async fn annotate_metadata(
+    ids: Vec<String>,
+    annotation: Annotation,
+) -> Result<AnnotateVO, String> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    annotation
+        .apply(&session, &parse_ids(&ids))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn parse_ids(ids: &[String]) -> Vec<i64> {
+    ids.iter().filter_map(|v| v.parse().ok()).collect()
+}
+
 #[tauri::command]
 pub async fn get_metadata_by_id(id: String) -> MetadataVO {
     let mut session = Session::new(get_db_path());
     session.connect().await;
-    let sql = format!("SELECT * FROM metadata WHERE id = {}", id);
-    if let Some(metadata) = session.select_one_as::<Metadata>(&sql).await.print_error() {
-        return MetadataVO::from(metadata);
+    if let Some(metadata) = session
+        .sql("SELECT * FROM metadata WHERE id = ?")
+        .bind(id)
+        .select_one_as::<Metadata>()
+        .await
+        .print_error()
+    {
+        if let Some(metadata) = MetadataVO::list(&session, vec![metadata]).await.pop() {
+            return metadata;
+        }
     }
     MetadataVO::empty()
 }
@@ -83,23 +272,74 @@ pub async fn get_metadata_by_id(id: String) -> MetadataVO {
 pub async fn get_metadata_like_path(path: String, like: bool) -> Vec<MetadataVO> {
     let mut session = Session::new(get_db_path());
     session.connect().await;
-    let sql = format!(
-        "SELECT * FROM metadata WHERE file_path {} '{}%'",
-        if like { "LIKE" } else { "=" },
-        path
-    );
-    if let Some(metadata) = session.select_as::<Metadata>(&sql).await.print_error() {
-        return metadata.into_iter().map(|v| MetadataVO::from(v)).collect();
+    let statement = if like {
+        session
+            .sql(&format!("SELECT m.* FROM metadata m LEFT JOIN exif e ON e.metadata_id = m.id WHERE m.is_del = 0 AND m.file_path LIKE ? ESCAPE '\\' ORDER BY {}", ORDER_BY_CAPTURED))
+            .bind(like_prefix(&path))
+    } else {
+        session
+            .sql(&format!("SELECT m.* FROM metadata m LEFT JOIN exif e ON e.metadata_id = m.id WHERE m.is_del = 0 AND m.file_path = ? ORDER BY {}", ORDER_BY_CAPTURED))
+            .bind(path)
+    };
+    if let Some(metadata) = statement.select_as::<Metadata>().await.print_error() {
+        return MetadataVO::list(&session, metadata).await;
     }
     Vec::new()
 }
 
+/// 按PSD图层或分组名称搜索
+#[tauri::command]
+pub async fn get_metadata_by_layer(name: String) -> Vec<MetadataVO> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    if let Some(metadata) = session
+        .sql(&format!("SELECT m.* FROM metadata m LEFT JOIN exif e ON e.metadata_id = m.id WHERE m.is_del = 0 AND m.id IN (SELECT metadata_id FROM psd_layer WHERE name LIKE ? ESCAPE '\\') ORDER BY {}", ORDER_BY_CAPTURED))
+        .bind(like_contains(&name))
+        .select_as::<Metadata>()
+        .await
+        .print_error()
+    {
+        return MetadataVO::list(&session, metadata).await;
+    }
+    Vec::new()
+}
+
+/// 查询视频的预览文件，没有预览或文件已被清理时返回`None`
+#[tauri::command]
+pub async fn get_video_preview(id: String) -> Option<VideoPreviewVO> {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    let metadata = session
+        .sql("SELECT * FROM metadata WHERE id = ?")
+        .bind(id)
+        .select_optional_as::<Metadata>()
+        .await
+        .print_error()??;
+    let video = VideoInfo::map_by_ids(&session, &[metadata.id])
+        .await
+        .remove(&metadata.id)?;
+    if video.preview_format.is_empty() {
+        return None;
+    }
+    let path = preview_path(&metadata.sha1, &video.preview_format);
+    if !path.is_file() {
+        return None;
+    }
+    Some(VideoPreviewVO {
+        path: path.to_string_lossy().to_string(),
+        format: video.preview_format,
+        frames: video.preview_frames,
+    })
+}
+
 #[tauri::command]
 pub async fn del_metadata(id: String) -> bool {
     let mut session = Session::new(get_db_path());
     session.connect().await;
     session
-        .execute(&format!("UPDATE metadata SET is_del = 1 WHERE id = {}", id))
+        .sql("UPDATE metadata SET is_del = 1 WHERE id = ?")
+        .bind(id)
+        .execute()
         .await
         .print_error()
         .is_some()
@@ -121,16 +361,21 @@ pub async fn get_basket() -> Vec<BasketVO> {
 
 #[tauri::command]
 pub async fn del_basket(id: String) -> bool {
+    watch::stop(&id);
     let mut session = Session::new(get_db_path());
     session.connect().await;
     if session
-        .execute(&format!("DELETE FROM basket WHERE id = {id}"))
+        .sql("DELETE FROM basket WHERE id = ?")
+        .bind(&id)
+        .execute()
         .await
         .print_error()
         .is_some()
     {
         return session
-            .execute(&format!("DELETE FROM basket_folder WHERE basket_id = {id}"))
+            .sql("DELETE FROM basket_folder WHERE basket_id = ?")
+            .bind(id)
+            .execute()
             .await
             .print_error()
             .is_some();
@@ -143,14 +388,14 @@ pub async fn get_folder(id: String) -> Vec<FolderVO> {
     let mut session = Session::new(get_db_path());
     session.connect().await;
     if let Some(folder) = session
-        .select_as::<Folder>(&format!(
+        .sql(
             r#"
             WITH RECURSIVE descendants AS (SELECT *
                                            FROM folder
                                            WHERE id IN (SELECT bf.folder_id
                                                         FROM basket b
                                                                  LEFT JOIN basket_folder bf ON bf.basket_id = b.id
-                                                        WHERE b.id = {id})
+                                                        WHERE b.id = ?)
                                            UNION ALL
                                            SELECT child.*
                                            FROM folder AS child
@@ -159,8 +404,10 @@ pub async fn get_folder(id: String) -> Vec<FolderVO> {
             FROM descendants
             GROUP BY id
             ORDER BY path;
-            "#
-        ))
+            "#,
+        )
+        .bind(id)
+        .select_as::<Folder>()
         .await
         .print_error()
     {
@@ -1,15 +1,240 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
 use tauri::AppHandle;
 
+use crate::util::error::ErrorHandle;
+use crate::{info, Result};
+
 pub static mut DB: String = String::new();
 
+/// 程序配置，只在启动时加载一次
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// 缓存目录，保存视频预览等可以重新生成的文件
+static CACHE_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+/// 对应`config.toml`，缺少的配置项使用默认值
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub task: TaskConfig,
+    /// 以扫描器名称为键，见`file::registry`
+    pub scanner: HashMap<String, ScannerConfig>,
+    pub preview: PreviewConfig,
+    pub duplicate: DuplicateConfig,
+    pub sidecar: SidecarConfig,
+}
+
+/// 扫描器配置，未配置的项使用扫描器的默认值
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ScannerConfig {
+    pub enabled: Option<bool>,
+    /// 支持的文件后缀，不区分大小写
+    pub suffix: Option<Vec<String>>,
+    /// 缩略图宽度
+    pub thumbnail_size: Option<u32>,
+    /// 同时执行的任务数，0表示不限制
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TaskConfig {
+    /// 单个任务最多尝试次数，超过后标记为失败
+    pub max_attempts: i64,
+    /// 第一次重试的间隔秒数，之后每次翻倍
+    pub retry_backoff: i64,
+    /// 重试间隔上限秒数
+    pub retry_backoff_max: i64,
+}
+
+impl Default for TaskConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            retry_backoff: 60,
+            retry_backoff_max: 86400,
+        }
+    }
+}
+
+/// 视频预览配置
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PreviewConfig {
+    pub enabled: bool,
+    /// `sprite`：横向拼接的雪碧图，`webp`：动画WebP
+    pub format: String,
+    /// 均匀截取的帧数
+    pub frames: u32,
+    /// 每帧的宽度
+    pub width: u32,
+    /// 超过该时长（秒）的视频不生成预览，0表示不限制
+    pub max_duration: i64,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            format: String::from("sprite"),
+            frames: 10,
+            width: 160,
+            max_duration: 1800,
+        }
+    }
+}
+
+/// 重复文件处理配置
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct DuplicateConfig {
+    /// 隔离目录，为空时使用数据库所在目录下的`quarantine`，不能位于资源库的目录中
+    pub quarantine: String,
+}
+
+impl DuplicateConfig {
+    pub fn quarantine_dir(&self) -> PathBuf {
+        if !self.quarantine.is_empty() {
+            return PathBuf::from(&self.quarantine);
+        }
+        Path::new(get_db_path())
+            .parent()
+            .map(|dir| dir.join("quarantine"))
+            .unwrap_or_else(|| get_cache_dir().join("quarantine"))
+    }
+}
+
+/// XMP sidecar配置
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SidecarConfig {
+    /// 扫描时读取sidecar，合并评分、颜色标记、关键词、标题和描述
+    pub read: bool,
+    /// 修改标注时同步写入sidecar，sidecar被其他软件修改过时不覆盖
+    ///
+    /// 是否被修改过根据读取时记录的同步状态判断，需要同时开启`read`
+    pub write: bool,
+}
+
+impl SidecarConfig {
+    /// 是否写入sidecar，没有开启`read`时已有的sidecar没有同步状态，无法判断是否被修改过
+    pub fn writable(&self) -> bool {
+        self.read && self.write
+    }
+}
+
+impl Default for SidecarConfig {
+    fn default() -> Self {
+        Self {
+            read: true,
+            write: false,
+        }
+    }
+}
+
+impl TaskConfig {
+    /// 第`attempts`次失败后的重试间隔
+    pub fn retry_delay(&self, attempts: i64) -> i64 {
+        let exp = attempts.saturating_sub(1).clamp(0, 32) as u32;
+        self.retry_backoff
+            .saturating_mul(2i64.saturating_pow(exp))
+            .min(self.retry_backoff_max)
+    }
+}
+
 pub fn get_db_path() -> &'static str {
     unsafe { DB.as_str() }
 }
 
 pub fn set_db_path(app_handle: AppHandle) {
-    if let Some(resource) = app_handle.path_resolver().resolve_resource("db/main.db") {
-        if let Some(str) = resource.to_str() {
+    let resolver = app_handle.path_resolver();
+    // 优先使用资源目录中的数据库，全新安装时在应用数据目录中创建
+    let path = match resolver.resolve_resource("db/main.db") {
+        Some(resource) if resource.exists() => Some(resource),
+        _ => resolver.app_data_dir().map(|dir| {
+            let _ = std::fs::create_dir_all(&dir);
+            dir.join("main.db")
+        }),
+    };
+    if let Some(path) = path {
+        if let Some(str) = path.to_str() {
             unsafe { DB = str.to_string() }
         }
     }
 }
+
+/// 使用应用缓存目录
+pub fn set_cache_dir(app_handle: AppHandle) {
+    if let Some(dir) = app_handle.path_resolver().app_cache_dir() {
+        let _ = CACHE_DIR.set(dir);
+    }
+}
+
+/// 获取缓存目录，未设置时使用系统临时目录
+pub fn get_cache_dir() -> &'static Path {
+    CACHE_DIR.get_or_init(|| std::env::temp_dir().join("pixel-basket"))
+}
+
+/// 获取程序配置，未加载时使用默认值
+pub fn get_config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// 加载配置文件，用户配置目录中的优先，其次是资源目录中的默认配置
+pub fn set_config(app_handle: AppHandle) {
+    let resolver = app_handle.path_resolver();
+    let path = resolver
+        .app_config_dir()
+        .map(|dir| dir.join("config.toml"))
+        .filter(|path| path.exists())
+        .or_else(|| resolver.resolve_resource("config.toml"))
+        .filter(|path| path.exists());
+    if let Some(path) = path {
+        if let Some(config) = load_config(&path).print_error() {
+            info!("加载配置文件：{}", path.display());
+            let _ = CONFIG.set(config);
+        }
+    }
+}
+
+pub fn load_config(path: &Path) -> Result<Config> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(toml::from_str(&content)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, TaskConfig};
+
+    #[test]
+    fn test_config() {
+        let config: Config = toml::from_str(include_str!("../../config.toml")).unwrap();
+        let raw = config.scanner["raw"].suffix.clone().unwrap();
+        assert!(raw.contains(&String::from("nef")));
+        assert!(raw.contains(&String::from("cr3")));
+        let config: Config = toml::from_str("[task]\nmax_attempts = 5").unwrap();
+        assert_eq!(config.task.max_attempts, 5);
+        assert_eq!(
+            config.task.retry_backoff,
+            TaskConfig::default().retry_backoff
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let config = TaskConfig {
+            max_attempts: 3,
+            retry_backoff: 60,
+            retry_backoff_max: 200,
+        };
+        assert_eq!(config.retry_delay(1), 60);
+        assert_eq!(config.retry_delay(2), 120);
+        assert_eq!(config.retry_delay(3), 200);
+        assert_eq!(config.retry_delay(100), 200);
+    }
+}
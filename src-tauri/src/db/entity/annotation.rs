@@ -0,0 +1,180 @@
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::config::get_config;
+use crate::db::entity::metadata::{Metadata, MetadataVO};
+use crate::db::entity::search::index;
+use crate::db::entity::sidecar::write_back;
+use crate::db::sqlite::{placeholders, Arg, Session, Transaction, QUERY_CHUNK};
+use crate::{info, Result};
+
+/// 颜色标记，与Lightroom的`xmp:Label`对应，空字符串表示没有标记
+pub const LABELS: [&str; 5] = ["red", "yellow", "green", "blue", "purple"];
+/// 最高评分
+pub const MAX_SCORE: f32 = 5.0;
+
+/// 批量修改的标注，为空的项不修改
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Annotation {
+    /// 星级评分，0到`MAX_SCORE`，0表示未评分
+    pub score: Option<f32>,
+    /// 颜色标记，见`LABELS`，空字符串表示清除
+    pub label: Option<String>,
+    pub title: Option<String>,
+    /// 备注，保存在`exegesis`
+    pub note: Option<String>,
+    pub source_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AnnotateVO {
+    /// 修改后的文件
+    pub items: Vec<MetadataVO>,
+    /// 在上次同步后被其他软件修改过、没有写入的sidecar
+    pub conflicts: Vec<String>,
+}
+
+impl Annotation {
+    /// 检查并转换为要修改的列
+    fn assignments(&self) -> Result<Vec<(&'static str, Arg)>> {
+        let mut assignments = Vec::new();
+        if let Some(score) = self.score {
+            if !score.is_finite() {
+                return Err(format!("无效的评分：{score}").into());
+            }
+            assignments.push(("score", Arg::from(score.round().clamp(0.0, MAX_SCORE))));
+        }
+        if let Some(label) = &self.label {
+            let label = label.trim().to_lowercase();
+            if !label.is_empty() && !LABELS.contains(&label.as_str()) {
+                return Err(format!("无效的颜色标记：{label}").into());
+            }
+            assignments.push(("label", Arg::from(label)));
+        }
+        if let Some(title) = &self.title {
+            assignments.push(("title", Arg::from(title.trim())));
+        }
+        if let Some(note) = &self.note {
+            assignments.push(("exegesis", Arg::from(note.trim())));
+        }
+        if let Some(source_url) = &self.source_url {
+            assignments.push(("source_url", Arg::from(source_url.trim())));
+        }
+        Ok(assignments)
+    }
+
+    /// 在事务中修改文件的标注并记录修改时间，没有要修改的项时返回`false`
+    pub async fn update(&self, tx: &mut Transaction, ids: &[i64]) -> Result<bool> {
+        let assignments = self.assignments()?;
+        if assignments.is_empty() || ids.is_empty() {
+            return Ok(false);
+        }
+        let columns = assignments
+            .iter()
+            .map(|(column, _)| format!("{column} = ?"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let args: Vec<Arg> = assignments.into_iter().map(|(_, arg)| arg).collect();
+        let annotated = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        for chunk in ids.chunks(QUERY_CHUNK) {
+            let sql = format!(
+                "UPDATE metadata SET {columns}, annotated = ? WHERE id IN ({})",
+                placeholders(chunk.len())
+            );
+            tx.sql(&sql)
+                .bind_all(args.clone())
+                .bind(&annotated)
+                .bind_all(chunk)
+                .execute()
+                .await?;
+        }
+        // 标题和备注参与全文搜索
+        if self.title.is_some() || self.note.is_some() {
+            index(tx.connection(), ids).await?;
+        }
+        Ok(true)
+    }
+
+    /// 修改文件的标注，返回修改后的文件
+    ///
+    /// 开启`sidecar.read`和`sidecar.write`时同步写入XMP sidecar，写入失败不影响目录中的修改
+    pub async fn apply(&self, session: &Session, ids: &[i64]) -> Result<AnnotateVO> {
+        let mut tx = session.begin().await?;
+        if !self.update(&mut tx, ids).await? {
+            return Ok(AnnotateVO::default());
+        }
+        tx.commit().await?;
+        info!("已修改 {} 个文件的标注", ids.len());
+
+        let list = Metadata::list_by_ids(session, ids).await;
+        let conflicts = if get_config().sidecar.writable() {
+            write_back(session, &list).await
+        } else {
+            Vec::new()
+        };
+        Ok(AnnotateVO {
+            items: MetadataVO::list(session, list).await,
+            conflicts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::entity::annotation::Annotation;
+    use crate::db::entity::search::search;
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+
+    #[tokio::test]
+    async fn test_apply() {
+        let session = temp_session("annotation").await;
+        migrate(&session).await.unwrap();
+        for id in 1..=3 {
+            session
+                .sql("INSERT INTO metadata (id, exegesis) VALUES (?, ?)")
+                .bind(id)
+                .bind("old")
+                .execute()
+                .await
+                .unwrap();
+        }
+        let annotation = Annotation {
+            score: Some(7.0),
+            label: Some(String::from(" Red ")),
+            ..Default::default()
+        };
+        let list = annotation.apply(&session, &[1, 2]).await.unwrap().items;
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].score, 5.0);
+        assert_eq!(list[0].label, "red");
+        // 没有设置的项保持不变
+        assert_eq!(list[0].exegesis, "old");
+        assert!(!list[1].annotated.is_empty());
+
+        let invalid = Annotation {
+            label: Some(String::from("pink")),
+            ..Default::default()
+        };
+        assert!(invalid.apply(&session, &[3]).await.is_err());
+
+        let note = Annotation {
+            note: Some(String::from("sunset")),
+            source_url: Some(String::from("https://example.com/1")),
+            ..Default::default()
+        };
+        let list = note.apply(&session, &[3]).await.unwrap().items;
+        assert_eq!(list[0].exegesis, "sunset");
+        assert_eq!(list[0].source_url, "https://example.com/1");
+        assert!(list[0].label.is_empty());
+
+        // 修改标题后可以搜索到
+        let title = Annotation {
+            title: Some(String::from("lighthouse")),
+            ..Default::default()
+        };
+        title.apply(&session, &[2]).await.unwrap();
+        assert_eq!(search(&session, "lighthouse", None).await.total, 1);
+    }
+}
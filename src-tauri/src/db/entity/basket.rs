@@ -1,8 +1,7 @@
 use serde::{Deserialize, Serialize};
-use sqlx::query;
 
 use crate::db::entity::folder::Folder;
-use crate::db::sqlite::Session;
+use crate::db::sqlite::{placeholders, Session};
 use crate::util::error::ErrorHandle;
 use crate::util::snowflake::id;
 
@@ -38,13 +37,9 @@ impl Basket {
 
     pub async fn exist(&self, session: &Session) -> bool {
         if let Ok(result) = session
-            .count(
-                format!(
-                    "SELECT COUNT(*) AS count FROM basket WHERE name = '{}'",
-                    &self.name
-                )
-                .as_str(),
-            )
+            .sql("SELECT COUNT(*) AS count FROM basket WHERE name = ?")
+            .bind(&self.name)
+            .count()
             .await
         {
             return result.count > 0;
@@ -53,49 +48,59 @@ impl Basket {
     }
 
     pub async fn save(&self, session: &Session) {
-        if let Some(pool) = session.as_pool().print_error() {
-            query("INSERT INTO basket (id, name) VALUES (?, ?)")
-                .bind(&self.id)
-                .bind(&self.name)
-                .execute(pool)
-                .await
-                .print_error();
-        }
+        session
+            .sql("INSERT INTO basket (id, name) VALUES (?, ?)")
+            .bind(self.id)
+            .bind(&self.name)
+            .execute()
+            .await
+            .print_error();
+    }
+
+    pub async fn get_by_name(session: &Session, name: &str) -> Option<Self> {
+        session
+            .sql("SELECT * FROM basket WHERE name = ?")
+            .bind(name)
+            .select_optional_as::<Self>()
+            .await
+            .print_error()
+            .flatten()
+    }
+
+    /// 获取篮子关联的根目录
+    pub async fn directories(session: &Session, id: &str) -> Vec<String> {
+        session
+            .sql("SELECT f.* FROM folder f JOIN basket_folder bf ON bf.folder_id = f.id WHERE bf.basket_id = ?")
+            .bind(id)
+            .select_as::<Folder>()
+            .await
+            .print_error()
+            .map(|folders| folders.into_iter().map(|v| v.path).collect())
+            .unwrap_or_default()
     }
 
     pub async fn save_folder(&self, directories: &Vec<String>, session: &Session) {
         if let Some(basket) = session
-            .select_one_as::<Basket>(&format!(
-                "SELECT * FROM basket WHERE name = '{}'",
-                self.name
-            ))
+            .sql("SELECT * FROM basket WHERE name = ?")
+            .bind(&self.name)
+            .select_one_as::<Basket>()
             .await
             .print_error()
         {
-            let str = directories
-                .iter()
-                .map(|v| format!("'{v}'"))
-                .collect::<Vec<String>>()
-                .join(",");
             if let Some(folders) = session
-                .select_as::<Folder>(&format!("SELECT * FROM folder WHERE path IN ({str})"))
+                .sql(&format!(
+                    "SELECT * FROM folder WHERE path IN ({})",
+                    placeholders(directories.len())
+                ))
+                .bind_all(directories)
+                .select_as::<Folder>()
                 .await
                 .print_error()
             {
-                if let Some(pool) = session.as_pool().print_error() {
-                    for folder in folders {
-                        let basket_folder = BasketFolder::new(basket.id, folder.id);
-                        if !basket_folder.exist(&session).await {
-                            query(
-                            "INSERT INTO basket_folder (id, basket_id, folder_id) VALUES (?, ?, ?)",
-                        )
-                        .bind(&basket_folder.id)
-                        .bind(&basket_folder.basket_id)
-                        .bind(&basket_folder.folder_id)
-                        .execute(pool)
-                        .await
-                        .print_error();
-                        }
+                for folder in folders {
+                    let basket_folder = BasketFolder::new(basket.id, folder.id);
+                    if !basket_folder.exist(session).await {
+                        basket_folder.save(session).await;
                     }
                 }
             }
@@ -114,19 +119,27 @@ impl BasketFolder {
 
     pub async fn exist(&self, session: &Session) -> bool {
         if let Ok(result) = session
-            .count(
-                format!(
-                    "SELECT COUNT(*) AS count FROM basket_folder WHERE basket_id = {} AND folder_id = {}",
-                    &self.basket_id, &self.folder_id
-                )
-                .as_str(),
-            )
+            .sql("SELECT COUNT(*) AS count FROM basket_folder WHERE basket_id = ? AND folder_id = ?")
+            .bind(self.basket_id)
+            .bind(self.folder_id)
+            .count()
             .await
         {
             return result.count > 0;
         }
         false
     }
+
+    pub async fn save(&self, session: &Session) {
+        session
+            .sql("INSERT INTO basket_folder (id, basket_id, folder_id) VALUES (?, ?, ?)")
+            .bind(self.id)
+            .bind(self.basket_id)
+            .bind(self.folder_id)
+            .execute()
+            .await
+            .print_error();
+    }
 }
 
 impl BasketVO {
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::db::entity::metadata::{directory_prefix, NOT_DELETED};
+use crate::db::entity::query::{Conditions, Page, PageVO};
+use crate::db::sqlite::{placeholders, Session};
+use crate::file::thumbnail::{thumbnail_url, ThumbnailSize};
+use crate::util::error::ErrorHandle;
+use crate::util::snowflake::id;
+
+/// 重复文件查询
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DuplicateQuery {
+    /// 只统计资源库中的文件，为空时统计所有文件
+    pub basket_id: Option<String>,
+    pub page: Option<Page>,
+}
+
+/// 重复文件的处理方式
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateAction {
+    /// 移到系统回收站
+    Trash,
+    /// 移到隔离目录，保留原来的目录结构
+    Quarantine,
+}
+
+impl DuplicateAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Trash => "trash",
+            Self::Quarantine => "quarantine",
+        }
+    }
+}
+
+/// 重复文件中的一个副本
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct DuplicateFile {
+    pub id: i64,
+    pub full_path: String,
+    pub file_path: String,
+    pub file_name: String,
+    pub file_suffix: String,
+    pub file_size: i64,
+    pub added: String,
+    pub modified: String,
+    pub sha1: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DuplicateFileVO {
+    pub id: String,
+    pub full_path: String,
+    /// 所在文件夹
+    pub file_path: String,
+    pub file_name: String,
+    pub file_suffix: String,
+    pub file_size: i64,
+    pub added: String,
+    pub modified: String,
+    /// 所属资源库的名称
+    pub baskets: Vec<String>,
+}
+
+/// 内容相同的一组文件
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DuplicateGroupVO {
+    pub sha1: String,
+    pub thumbnail: String,
+    pub file_size: i64,
+    pub count: i64,
+    /// 只保留一个副本时可以释放的空间
+    pub wasted: i64,
+    /// 按添加时间排列，第一个为最早添加的文件
+    pub files: Vec<DuplicateFileVO>,
+}
+
+/// 重复文件处理记录
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+pub struct DuplicateLog {
+    pub id: i64,
+    pub sha1: String,
+    /// 被移走的文件
+    pub metadata_id: i64,
+    /// 保留的文件
+    pub kept_id: i64,
+    pub action: String,
+    pub source: String,
+    /// 隔离后的路径，移到回收站时为空
+    pub target: String,
+    pub created: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DuplicateLogVO {
+    pub id: String,
+    pub sha1: String,
+    pub metadata_id: String,
+    pub kept_id: String,
+    pub action: String,
+    pub source: String,
+    pub target: String,
+    pub created: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct DuplicateSummary {
+    sha1: String,
+    count: i64,
+    file_size: i64,
+}
+
+impl DuplicateFileVO {
+    fn from(file: DuplicateFile, baskets: Vec<String>) -> Self {
+        Self {
+            id: file.id.to_string(),
+            full_path: file.full_path,
+            file_path: file.file_path,
+            file_name: file.file_name,
+            file_suffix: file.file_suffix,
+            file_size: file.file_size,
+            added: file.added,
+            modified: file.modified,
+            baskets,
+        }
+    }
+}
+
+impl DuplicateFile {
+    /// 获取内容相同的所有未删除文件
+    pub async fn list_by_sha1(session: &Session, sha1: &str) -> Vec<Self> {
+        session
+            .sql("SELECT id, full_path, file_path, file_name, file_suffix, file_size, added, modified, sha1 FROM metadata WHERE sha1 = ? AND is_del = ? ORDER BY added, id")
+            .bind(sha1)
+            .bind(NOT_DELETED)
+            .select_as::<Self>()
+            .await
+            .print_error()
+            .unwrap_or_default()
+    }
+}
+
+impl DuplicateLog {
+    pub fn new(
+        action: DuplicateAction,
+        removed: &DuplicateFile,
+        kept: &DuplicateFile,
+        target: String,
+    ) -> Self {
+        Self {
+            id: id(),
+            sha1: removed.sha1.clone(),
+            metadata_id: removed.id,
+            kept_id: kept.id,
+            action: action.name().to_string(),
+            source: removed.full_path.clone(),
+            target,
+            created: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        }
+    }
+
+    pub async fn save(&self, session: &Session) {
+        session
+            .sql("INSERT INTO duplicate_log (id, sha1, metadata_id, kept_id, action, source, target, created) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(self.id)
+            .bind(&self.sha1)
+            .bind(self.metadata_id)
+            .bind(self.kept_id)
+            .bind(&self.action)
+            .bind(&self.source)
+            .bind(&self.target)
+            .bind(&self.created)
+            .execute()
+            .await
+            .print_error();
+    }
+
+    /// 最近的处理记录
+    pub async fn list(session: &Session, page: Option<Page>) -> PageVO<DuplicateLogVO> {
+        let page = page.unwrap_or_default();
+        let total = session
+            .count("SELECT COUNT(*) AS count FROM duplicate_log")
+            .await
+            .print_error()
+            .map_or(0, |v| v.count);
+        let items = session
+            .sql("SELECT * FROM duplicate_log ORDER BY created DESC, id DESC LIMIT ? OFFSET ?")
+            .bind(page.limit() as i64)
+            .bind(page.offset() as i64)
+            .select_as::<Self>()
+            .await
+            .print_error()
+            .unwrap_or_default()
+            .into_iter()
+            .map(DuplicateLogVO::from)
+            .collect();
+        PageVO {
+            total,
+            items,
+            next: None,
+        }
+    }
+}
+
+impl DuplicateLogVO {
+    pub fn from(log: DuplicateLog) -> Self {
+        Self {
+            id: log.id.to_string(),
+            sha1: log.sha1,
+            metadata_id: log.metadata_id.to_string(),
+            kept_id: log.kept_id.to_string(),
+            action: log.action,
+            source: log.source,
+            target: log.target,
+            created: log.created,
+        }
+    }
+}
+
+/// 资源库名称和目录，用于标记文件所属的资源库
+pub(crate) async fn basket_directories(session: &Session) -> Vec<(String, String)> {
+    session
+        .sql("SELECT b.name, f.path FROM basket b JOIN basket_folder bf ON bf.basket_id = b.id JOIN folder f ON f.id = bf.folder_id")
+        .select()
+        .await
+        .print_error()
+        .map(|rows| {
+            rows.iter()
+                .filter_map(|row| {
+                    let name: String = row.try_get("name").ok()?;
+                    let path: String = row.try_get("path").ok()?;
+                    Some((name, directory_prefix(&path)))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// 按sha1列出重复文件，可以释放空间多的排在前面
+pub async fn list_duplicates(
+    session: &Session,
+    query: &DuplicateQuery,
+) -> PageVO<DuplicateGroupVO> {
+    let mut result = PageVO {
+        total: 0,
+        items: Vec::new(),
+        next: None,
+    };
+    let mut conditions = Conditions::default();
+    conditions.push("m.is_del = ? AND m.sha1 != ''", [NOT_DELETED]);
+    if let Some(basket_id) = &query.basket_id {
+        conditions.basket(session, basket_id).await;
+    }
+    let scope = conditions.to_sql();
+    let args = conditions.args;
+    let groups = format!(
+        "SELECT m.sha1, COUNT(*) AS count, MAX(m.file_size) AS file_size FROM metadata m WHERE {scope} GROUP BY m.sha1 HAVING COUNT(*) > 1"
+    );
+    result.total = session
+        .sql(&format!("SELECT COUNT(*) AS count FROM ({groups})"))
+        .bind_all(args.clone())
+        .count()
+        .await
+        .print_error()
+        .map_or(0, |v| v.count);
+
+    let page = query.page.clone().unwrap_or_default();
+    let summaries = session
+        .sql(&format!(
+            "{groups} ORDER BY (COUNT(*) - 1) * MAX(m.file_size) DESC, m.sha1 LIMIT ? OFFSET ?"
+        ))
+        .bind_all(args.clone())
+        .bind(page.limit() as i64)
+        .bind(page.offset() as i64)
+        .select_as::<DuplicateSummary>()
+        .await
+        .print_error()
+        .unwrap_or_default();
+    if summaries.is_empty() {
+        return result;
+    }
+
+    let sql = format!(
+        "SELECT m.id, m.full_path, m.file_path, m.file_name, m.file_suffix, m.file_size, m.added, m.modified, m.sha1 FROM metadata m WHERE m.sha1 IN ({}) AND {scope} ORDER BY m.added, m.id",
+        placeholders(summaries.len())
+    );
+    let files = session
+        .sql(&sql)
+        .bind_all(summaries.iter().map(|v| v.sha1.as_str()))
+        .bind_all(args)
+        .select_as::<DuplicateFile>()
+        .await
+        .print_error()
+        .unwrap_or_default();
+    let baskets = basket_directories(session).await;
+    let mut files_by_sha1: HashMap<String, Vec<DuplicateFileVO>> = HashMap::new();
+    for file in files {
+        let names = baskets
+            .iter()
+            .filter(|(_, directory)| file.full_path.starts_with(directory.as_str()))
+            .map(|(name, _)| name.clone())
+            .collect();
+        files_by_sha1
+            .entry(file.sha1.clone())
+            .or_default()
+            .push(DuplicateFileVO::from(file, names));
+    }
+    result.items = summaries
+        .into_iter()
+        .map(|v| DuplicateGroupVO {
+            thumbnail: thumbnail_url(&v.sha1, ThumbnailSize::Grid),
+            files: files_by_sha1.remove(&v.sha1).unwrap_or_default(),
+            wasted: (v.count - 1) * v.file_size,
+            sha1: v.sha1,
+            file_size: v.file_size,
+            count: v.count,
+        })
+        .collect();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::db::entity::duplicate::{list_duplicates, DuplicateQuery};
+    use crate::db::entity::metadata::Metadata;
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+
+    #[tokio::test]
+    async fn test_list_duplicates() {
+        let session = temp_session("duplicate").await;
+        migrate(&session).await.unwrap();
+        let files = [
+            ("/data/a/1.jpg", "aaa", 100),
+            ("/data/b/1.jpg", "aaa", 100),
+            ("/data/c/1.jpg", "aaa", 100),
+            ("/data/a/2.jpg", "bbb", 1000),
+            ("/data/b/2.jpg", "bbb", 1000),
+            ("/data/a/3.jpg", "ccc", 10),
+        ];
+        let mut tx = session.begin().await.unwrap();
+        for (path, sha1, size) in files {
+            let mut metadata = Metadata::load(Path::new(path));
+            metadata.sha1 = sha1.to_string();
+            metadata.file_size = size;
+            metadata.save(&mut tx).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+
+        let result = list_duplicates(&session, &DuplicateQuery::default()).await;
+        assert_eq!(result.total, 2);
+        // 可以释放的空间多的排在前面
+        assert_eq!(result.items[0].sha1, "bbb");
+        assert_eq!(result.items[0].wasted, 1000);
+        assert_eq!(result.items[1].count, 3);
+        assert_eq!(result.items[1].files.len(), 3);
+        assert_eq!(result.items[1].files[0].file_path, "/data/a/");
+    }
+}
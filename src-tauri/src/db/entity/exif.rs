@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::sqlite::{Session, Transaction};
+use crate::util::error::ErrorHandle;
+
+/// 图片的EXIF、XMP和IPTC信息，与`metadata.id`一一对应
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct Exif {
+    pub metadata_id: i64,
+    /// 相机厂商
+    pub make: String,
+    /// 相机型号
+    pub model: String,
+    pub lens: String,
+    pub iso: i64,
+    /// 光圈值
+    pub aperture: f64,
+    /// 快门速度，如`1/250`
+    pub shutter: String,
+    /// 焦距（毫米）
+    pub focal_length: f64,
+    /// 拍摄时间，格式为`%Y-%m-%d %H:%M:%S`
+    pub captured: String,
+    /// 方向，1-8，0表示未知
+    pub orientation: i64,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<f64>,
+    pub copyright: String,
+    /// 关键字，JSON数组
+    pub keywords: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExifVO {
+    pub make: String,
+    pub model: String,
+    pub lens: String,
+    pub iso: i64,
+    pub aperture: f64,
+    pub shutter: String,
+    pub focal_length: f64,
+    pub captured: String,
+    pub orientation: i64,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub altitude: Option<f64>,
+    pub copyright: String,
+    pub keywords: Vec<String>,
+}
+
+impl Exif {
+    /// 在事务中保存，已存在时覆盖
+    pub async fn save(&self, tx: &mut Transaction, metadata_id: i64) -> sqlx::Result<()> {
+        tx.sql("INSERT OR REPLACE INTO exif (metadata_id, make, model, lens, iso, aperture, shutter, focal_length, captured, orientation, latitude, longitude, altitude, copyright, keywords) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(metadata_id)
+            .bind(&self.make)
+            .bind(&self.model)
+            .bind(&self.lens)
+            .bind(self.iso)
+            .bind(self.aperture)
+            .bind(&self.shutter)
+            .bind(self.focal_length)
+            .bind(&self.captured)
+            .bind(self.orientation)
+            .bind(self.latitude)
+            .bind(self.longitude)
+            .bind(self.altitude)
+            .bind(&self.copyright)
+            .bind(&self.keywords)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// 按`metadata.id`批量查询
+    pub async fn map_by_ids(session: &Session, ids: &[i64]) -> HashMap<i64, Exif> {
+        session
+            .select_in::<Exif>(ids, |v| {
+                format!("SELECT * FROM exif WHERE metadata_id IN ({v})")
+            })
+            .await
+            .print_error()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| (v.metadata_id, v))
+            .collect()
+    }
+
+    pub fn set_keywords(&mut self, keywords: &[String]) {
+        self.keywords = serde_json::to_string(keywords).unwrap_or_else(|_| String::from("[]"));
+    }
+
+    pub fn get_keywords(&self) -> Vec<String> {
+        serde_json::from_str(&self.keywords).unwrap_or_default()
+    }
+}
+
+impl ExifVO {
+    pub fn from(exif: Exif) -> Self {
+        let keywords = exif.get_keywords();
+        Self {
+            make: exif.make,
+            model: exif.model,
+            lens: exif.lens,
+            iso: exif.iso,
+            aperture: exif.aperture,
+            shutter: exif.shutter,
+            focal_length: exif.focal_length,
+            captured: exif.captured,
+            orientation: exif.orientation,
+            latitude: exif.latitude,
+            longitude: exif.longitude,
+            altitude: exif.altitude,
+            copyright: exif.copyright,
+            keywords,
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use std::path::Path;
 
 use serde::{Deserialize, Serialize};
-use sqlx::query;
 
-use crate::db::sqlite::Session;
+use crate::db::entity::metadata::directory_prefix;
+use crate::db::sqlite::{like_prefix, Session};
 use crate::util::error::ErrorHandle;
 use crate::util::snowflake::id;
 
@@ -44,13 +44,9 @@ impl Folder {
 
     pub async fn exist(&self, session: &Session) -> bool {
         if let Ok(result) = session
-            .count(
-                format!(
-                    "SELECT COUNT(*) AS count FROM folder WHERE path = '{}'",
-                    &self.path
-                )
-                .as_str(),
-            )
+            .sql("SELECT COUNT(*) AS count FROM folder WHERE path = ?")
+            .bind(&self.path)
+            .count()
             .await
         {
             return result.count > 0;
@@ -59,50 +55,57 @@ impl Folder {
     }
 
     pub async fn save(&self, session: &Session) {
-        if let Some(pool) = session.as_pool().print_error() {
-            query("INSERT INTO folder (id, pid, name, path) VALUES (?, ?, ?, ?)")
-                .bind(&self.id)
-                .bind(&self.pid)
-                .bind(&self.name)
-                .bind(&self.path)
-                .execute(pool)
-                .await
-                .print_error();
-        }
+        session
+            .sql("INSERT INTO folder (id, pid, name, path) VALUES (?, ?, ?, ?)")
+            .bind(self.id)
+            .bind(self.pid)
+            .bind(&self.name)
+            .bind(&self.path)
+            .execute()
+            .await
+            .print_error();
     }
 
     pub async fn update(&self, session: &Session) {
-        if let Some(pool) = session.as_pool().print_error() {
-            query("UPDATE folder SET pid = ?, name = ?, path = ? WHERE id = ?")
-                .bind(&self.pid)
-                .bind(&self.name)
-                .bind(&self.path)
-                .bind(&self.id)
-                .execute(pool)
-                .await
-                .print_error();
-        }
+        session
+            .sql("UPDATE folder SET pid = ?, name = ?, path = ? WHERE id = ?")
+            .bind(self.pid)
+            .bind(&self.name)
+            .bind(&self.path)
+            .bind(self.id)
+            .execute()
+            .await
+            .print_error();
+    }
+
+    pub async fn delete(&self, session: &Session) {
+        session
+            .sql("DELETE FROM folder WHERE id = ?")
+            .bind(self.id)
+            .execute()
+            .await
+            .print_error();
+    }
+
+    /// 获取目录下所有子文件夹，不包含目录本身
+    pub async fn list_by_directory(session: &Session, directory: &str) -> Vec<Self> {
+        session
+            .sql("SELECT * FROM folder WHERE path LIKE ? ESCAPE '\\'")
+            .bind(like_prefix(&directory_prefix(directory)))
+            .select_as::<Self>()
+            .await
+            .print_error()
+            .unwrap_or_default()
     }
 
     pub async fn get_by_path(session: &Session, path: String) -> Option<Self> {
-        if let Ok(result) = session
-            .count(
-                format!(
-                    "SELECT COUNT(*) AS count FROM folder WHERE path = '{}'",
-                    path
-                )
-                .as_str(),
-            )
+        session
+            .sql("SELECT * FROM folder WHERE path = ?")
+            .bind(path)
+            .select_optional_as::<Self>()
             .await
-        {
-            if result.count > 0 {
-                return session
-                    .select_one_as::<Self>(&format!("SELECT * FROM folder WHERE path = '{path}'"))
-                    .await
-                    .print_error();
-            }
-        };
-        None
+            .print_error()
+            .flatten()
     }
 }
 
@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::entity::metadata::{Metadata, MetadataVO, NOT_DELETED};
+use crate::db::entity::query::Conditions;
+use crate::db::sqlite::{Session, Transaction, QUERY_CHUNK};
+use crate::file::phash::{hamming, perceptual_hash};
+use crate::file::thumbnail::{thumbnail_path, ThumbnailSize};
+use crate::util::error::ErrorHandle;
+use crate::{info, Result};
+
+/// 默认的汉明距离阈值，64位中不超过该值视为相似
+pub const DEFAULT_THRESHOLD: u32 = 10;
+
+/// 感知哈希，与`metadata.id`一一对应，64位哈希按`i64`保存
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct ImageHash {
+    pub metadata_id: i64,
+    pub ahash: i64,
+    pub dhash: i64,
+    pub phash: i64,
+}
+
+/// 比较使用的哈希
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashKind {
+    /// 平均哈希，速度快，对亮度和对比度变化敏感
+    Ahash,
+    /// 差异哈希，对缩放和压缩较稳定
+    Dhash,
+    /// DCT哈希，对缩放、重新编码和轻微裁剪最稳定
+    #[default]
+    Phash,
+}
+
+impl HashKind {
+    fn column(&self) -> &'static str {
+        match self {
+            Self::Ahash => "ahash",
+            Self::Dhash => "dhash",
+            Self::Phash => "phash",
+        }
+    }
+}
+
+/// 相似图片查询
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SimilarQuery {
+    /// 只在资源库中查找，为空时查找所有文件
+    pub basket_id: Option<String>,
+    pub kind: HashKind,
+    /// 汉明距离阈值，默认为`DEFAULT_THRESHOLD`
+    pub threshold: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SimilarItemVO {
+    pub metadata: MetadataVO,
+    /// 与推荐保留文件的汉明距离
+    pub distance: u32,
+}
+
+/// 一组相似图片，第一个为推荐保留的文件
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SimilarGroupVO {
+    /// 推荐保留的文件id，分辨率最高，其次文件最大
+    pub best: String,
+    pub items: Vec<SimilarItemVO>,
+}
+
+/// 参与比较的文件
+#[derive(sqlx::FromRow)]
+struct Candidate {
+    id: i64,
+    hash: i64,
+    pixels: i64,
+    file_size: i64,
+}
+
+impl ImageHash {
+    /// 在事务中保存，已存在时覆盖
+    pub async fn save(&self, tx: &mut Transaction, metadata_id: i64) -> sqlx::Result<()> {
+        tx.sql("INSERT OR REPLACE INTO image_hash (metadata_id, ahash, dhash, phash) VALUES (?, ?, ?, ?)")
+            .bind(metadata_id)
+            .bind(self.ahash)
+            .bind(self.dhash)
+            .bind(self.phash)
+            .execute()
+            .await?;
+        Ok(())
+    }
+}
+
+/// BK树，按汉明距离查找相近的哈希
+#[derive(Default)]
+struct BkTree {
+    /// 哈希、在候选列表中的位置、按距离索引的子节点
+    nodes: Vec<(i64, usize, HashMap<u32, usize>)>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: i64, index: usize) {
+        let mut current = 0;
+        while current < self.nodes.len() {
+            let distance = hamming(self.nodes[current].0, hash);
+            match self.nodes[current].2.get(&distance) {
+                Some(child) => current = *child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes[current].2.insert(distance, child);
+                    break;
+                }
+            }
+        }
+        self.nodes.push((hash, index, HashMap::new()));
+    }
+
+    fn find(&self, hash: i64, threshold: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut stack = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(current) = stack.pop() {
+            let (node, index, children) = &self.nodes[current];
+            let distance = hamming(*node, hash);
+            if distance <= threshold {
+                found.push(*index);
+            }
+            let range = distance.saturating_sub(threshold)..=distance + threshold;
+            stack.extend(
+                children
+                    .iter()
+                    .filter(|(d, _)| range.contains(d))
+                    .map(|(_, child)| *child),
+            );
+        }
+        found
+    }
+}
+
+fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// 按感知哈希的汉明距离把相似图片分组，距离传递的图片也归为一组
+///
+/// 组内按推荐程度排序，分组按文件数量从多到少排序
+pub async fn find_similar(session: &Session, query: &SimilarQuery) -> Vec<SimilarGroupVO> {
+    let threshold = query.threshold.unwrap_or(DEFAULT_THRESHOLD).min(64);
+    let mut conditions = Conditions::default();
+    conditions.push("m.is_del = ?", [NOT_DELETED]);
+    if let Some(basket_id) = &query.basket_id {
+        conditions.basket(session, basket_id).await;
+    }
+    let sql = format!(
+        "SELECT m.id, h.{} AS hash, m.image_width * m.image_height AS pixels, m.file_size FROM image_hash h JOIN metadata m ON m.id = h.metadata_id WHERE {}",
+        query.kind.column(),
+        conditions.to_sql()
+    );
+    let candidates = session
+        .sql(&sql)
+        .bind_all(conditions.args)
+        .select_as::<Candidate>()
+        .await
+        .print_error()
+        .unwrap_or_default();
+
+    let mut tree = BkTree::default();
+    let mut parent: Vec<usize> = (0..candidates.len()).collect();
+    for (i, candidate) in candidates.iter().enumerate() {
+        for j in tree.find(candidate.hash, threshold) {
+            let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+            parent[a.max(b)] = a.min(b);
+        }
+        tree.insert(candidate.hash, i);
+    }
+    let mut groups: HashMap<usize, Vec<&Candidate>> = HashMap::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        groups
+            .entry(root(&mut parent, i))
+            .or_default()
+            .push(candidate);
+    }
+    let mut groups: Vec<Vec<&Candidate>> = groups.into_values().filter(|v| v.len() > 1).collect();
+    for group in groups.iter_mut() {
+        group.sort_by(|x, y| {
+            y.pixels
+                .cmp(&x.pixels)
+                .then(y.file_size.cmp(&x.file_size))
+                .then(x.id.cmp(&y.id))
+        });
+    }
+    groups.sort_by(|x, y| y.len().cmp(&x.len()).then(x[0].id.cmp(&y[0].id)));
+
+    let ids: Vec<i64> = groups.iter().flatten().map(|v| v.id).collect();
+    let list = Metadata::list_by_ids(session, &ids).await;
+    let mut metadata: HashMap<String, MetadataVO> = MetadataVO::list(session, list)
+        .await
+        .into_iter()
+        .map(|v| (v.id.clone(), v))
+        .collect();
+    groups
+        .into_iter()
+        .filter_map(|group| {
+            let best = group[0].hash;
+            let items: Vec<SimilarItemVO> = group
+                .iter()
+                .filter_map(|v| {
+                    Some(SimilarItemVO {
+                        metadata: metadata.remove(&v.id.to_string())?,
+                        distance: hamming(best, v.hash),
+                    })
+                })
+                .collect();
+            Some(SimilarGroupVO {
+                best: items.first()?.metadata.id.clone(),
+                items,
+            })
+        })
+        .collect()
+}
+
+/// 用已缓存的列表缩略图为旧数据计算感知哈希，跳过视频、模型和已有哈希的文件
+///
+/// 需要在旧缩略图迁移到缓存目录之后执行，返回计算的数量
+pub async fn backfill_hash(session: &Session) -> Result<usize> {
+    let mut last = i64::MIN;
+    let mut count = 0;
+    loop {
+        let rows = session
+            .sql("SELECT id, sha1 FROM metadata WHERE id > ? AND sha1 != '' AND image_width > 0 AND duration = 0 AND id NOT IN (SELECT metadata_id FROM image_hash) AND id NOT IN (SELECT metadata_id FROM video) AND id NOT IN (SELECT metadata_id FROM model) ORDER BY id LIMIT ?")
+            .bind(last)
+            .bind(QUERY_CHUNK as i64)
+            .select_as::<(i64, String)>()
+            .await?;
+        let Some(row) = rows.last() else {
+            break;
+        };
+        last = row.0;
+        // 解码图片会阻塞线程
+        let hashes = tokio::task::spawn_blocking(move || {
+            rows.into_iter()
+                .filter_map(|(id, sha1)| {
+                    let image = image::open(thumbnail_path(&sha1, ThumbnailSize::Grid)).ok()?;
+                    Some((id, perceptual_hash(&image.to_rgb8())))
+                })
+                .collect::<Vec<_>>()
+        })
+        .await?;
+        let mut tx = session.begin().await?;
+        for (id, hash) in &hashes {
+            hash.save(&mut tx, *id).await?;
+        }
+        tx.commit().await?;
+        count += hashes.len();
+    }
+    if count > 0 {
+        info!("已为 {count} 个文件计算感知哈希");
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use crate::db::entity::image_hash::{backfill_hash, find_similar, ImageHash, SimilarQuery};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+    use crate::file::thumbnail::{thumbnail_path, ThumbnailSize};
+
+    #[tokio::test]
+    async fn test_find_similar() {
+        let session = temp_session("image_hash").await;
+        migrate(&session).await.unwrap();
+        // 1、2、3相互接近，2分辨率最高；4与其他都不相似
+        let files = [
+            (1, 0b0000_i64, 100, 100, 500),
+            (2, 0b0001, 200, 200, 400),
+            (3, 0b0011, 200, 200, 300),
+            (4, -1, 100, 100, 500),
+        ];
+        let mut tx = session.begin().await.unwrap();
+        for (id, hash, w, h, size) in files {
+            tx.sql("INSERT INTO metadata (id, image_width, image_height, file_size) VALUES (?, ?, ?, ?)")
+                .bind(id)
+                .bind(w)
+                .bind(h)
+                .bind(size)
+                .execute()
+                .await
+                .unwrap();
+            let hash = ImageHash {
+                metadata_id: id,
+                phash: hash,
+                ..Default::default()
+            };
+            hash.save(&mut tx, id).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+
+        let query = SimilarQuery {
+            threshold: Some(1),
+            ..Default::default()
+        };
+        let groups = find_similar(&session, &query).await;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].best, "2");
+        let ids: Vec<&str> = groups[0]
+            .items
+            .iter()
+            .map(|v| v.metadata.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["2", "3", "1"]);
+        assert_eq!(groups[0].items[2].distance, 1);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_hash() {
+        let session = temp_session("backfill_hash").await;
+        migrate(&session).await.unwrap();
+        let path = thumbnail_path("fe10", ThumbnailSize::Grid);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        RgbImage::new(8, 8).save(&path).unwrap();
+        // 1：有缩略图，2：没有缩略图，3：已有哈希
+        for (id, sha1) in [(1, "fe10"), (2, "fe11"), (3, "fe10")] {
+            session
+                .sql("INSERT INTO metadata (id, sha1, image_width) VALUES (?, ?, 8)")
+                .bind(id)
+                .bind(sha1)
+                .execute()
+                .await
+                .unwrap();
+        }
+        session
+            .execute("INSERT INTO image_hash (metadata_id, phash) VALUES (3, 1)")
+            .await
+            .unwrap();
+        assert_eq!(backfill_hash(&session).await.unwrap(), 1);
+        // 已计算的文件不再重复计算
+        assert_eq!(backfill_hash(&session).await.unwrap(), 0);
+        let result = session
+            .count("SELECT COUNT(*) AS count FROM image_hash WHERE phash = 1")
+            .await
+            .unwrap();
+        assert_eq!(result.count, 1);
+    }
+}
@@ -1,20 +1,50 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::Error as IoError;
 use std::ops::Add;
-use std::path::Path;
+use std::path::{Path, MAIN_SEPARATOR};
 
 use chrono::{DateTime, Local};
 use file_hashing::get_hash_file;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
-use sqlx::query;
 
-use crate::config::get_db_path;
-use crate::db::sqlite::Session;
+use crate::config::get_config;
+use crate::db::entity::exif::{Exif, ExifVO};
+use crate::db::entity::image_hash::ImageHash;
+use crate::db::entity::model::{ModelInfo, ModelVO};
+use crate::db::entity::palette::PaletteColor;
+use crate::db::entity::psd::{PsdInfo, PsdVO};
+use crate::db::entity::search::{index, reindex};
+use crate::db::entity::sidecar::import;
+use crate::db::entity::task::{Task, PENDING};
+use crate::db::entity::video::{VideoInfo, VideoVO};
+use crate::db::sqlite::{like_prefix, Session, Transaction};
+use crate::db::writer::writer;
+use crate::file::sidecar::{read as read_sidecar, Sidecar};
+use crate::file::thumbnail::{thumbnail_url, ThumbnailSize};
 use crate::util::error::ErrorHandle;
 use crate::util::snowflake::id;
 
+/// `is_del`：正常
+pub const NOT_DELETED: u8 = 0;
+/// `is_del`：用户删除
+pub const DELETED: u8 = 1;
+/// `is_del`：文件在磁盘上已不存在
+pub const MISSING: u8 = 2;
+
+/// 扫描生成的附加信息所在的表
+const DETAIL_TABLES: [&str; 7] = [
+    "exif",
+    "psd",
+    "psd_layer",
+    "model",
+    "video",
+    "palette",
+    "image_hash",
+];
+
 #[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
 pub struct Metadata {
     pub id: i64,
@@ -29,16 +59,49 @@ pub struct Metadata {
     pub tags: String,
     pub exegesis: String,
     pub score: f32,
+    pub title: String,
+    /// 颜色标记，见`annotation::LABELS`
+    pub label: String,
+    pub source_url: String,
+    /// 最后一次修改标注的时间
+    pub annotated: String,
     pub is_del: u8,
     pub sha1: String,
     // image
     pub image_width: u32,
     pub image_height: u32,
-    pub thumbnail: String,
     pub colors: String,
     pub shape: String,
     // video
     pub duration: i64,
+    /// 扫描时解析的EXIF信息，保存在`exif`表
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub exif: Option<Exif>,
+    /// 扫描时解析的PSD图层信息，保存在`psd`和`psd_layer`表
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub psd: Option<PsdInfo>,
+    /// 扫描时解析的模型信息，保存在`model`表
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub model: Option<ModelInfo>,
+    /// 扫描时解析的视频信息，保存在`video`表
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub video: Option<VideoInfo>,
+    /// 扫描时提取的主题色，保存在`palette`表
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub palette: Vec<PaletteColor>,
+    /// 扫描时计算的感知哈希，保存在`image_hash`表
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub hash: Option<ImageHash>,
+    /// 扫描时读取的XMP sidecar，标注合并到目录中
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub sidecar: Option<Sidecar>,
 }
 
 impl Metadata {
@@ -56,19 +119,39 @@ impl Metadata {
             tags: String::new(),
             exegesis: String::new(),
             score: 0.0,
+            title: String::new(),
+            label: String::new(),
+            source_url: String::new(),
+            annotated: String::new(),
             is_del: 0,
             sha1: String::new(),
             // image
             image_width: 0,
             image_height: 0,
-            thumbnail: String::new(),
             colors: String::new(),
             shape: String::new(),
             // video
             duration: 0,
+            exif: None,
+            psd: None,
+            model: None,
+            video: None,
+            palette: Vec::new(),
+            hash: None,
+            sidecar: None,
         }
     }
 
+    /// 设置主题色，同时更新`colors`
+    pub fn set_palette(&mut self, palette: Vec<PaletteColor>) {
+        self.colors = palette
+            .iter()
+            .map(|v| v.color.as_str())
+            .collect::<Vec<&str>>()
+            .join(",");
+        self.palette = palette;
+    }
+
     pub fn load(path: &Path) -> Self {
         let mut metadata = Self::empty();
         let mut suffix = String::new();
@@ -101,93 +184,266 @@ impl Metadata {
     /// 解析文件元数据
     pub fn analyze_metadata(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
         let file_metadata = path.metadata()?;
-        self.file_size = file_metadata.len() as i64;
         let datetime: DateTime<Local> = file_metadata.created()?.into();
         self.created = datetime.format("%Y-%m-%d %H:%M:%S").to_string();
-        let datetime: DateTime<Local> = file_metadata.modified()?.into();
-        self.modified = datetime.format("%Y-%m-%d %H:%M:%S").to_string();
+        (self.file_size, self.modified) = file_stat(path)?;
         self.sha1 = sha1(path)?;
+        if get_config().sidecar.read {
+            self.sidecar = read_sidecar(path).print_error().flatten();
+        }
         Ok(())
     }
 
-    pub async fn save_to_db(&self) {
-        let mut session = Session::new(get_db_path());
-        session.connect().await;
-        if let Ok(pool) = &session.get_pool() {
-            if let Ok(result) = session
-                .count(
-                    format!(
-                        "SELECT COUNT(*) AS count FROM metadata WHERE sha1 = '{}'",
-                        &self.sha1
-                    )
-                    .as_str(),
-                )
-                .await
-            {
-                if result.count == 0 {
-                    let _ = query("INSERT INTO metadata (id, full_path, file_name, file_path, file_size, file_suffix, added, created, modified, image_width, image_height, thumbnail, tags, exegesis, score, colors, shape, duration, is_del, sha1) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
-                        .bind(id::<i64>())
-                        .bind(&self.full_path)
-                        .bind(&self.file_name)
-                        .bind(&self.file_path)
-                        .bind(&self.file_size)
-                        .bind(&self.file_suffix)
-                        .bind(Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
-                        .bind(&self.created)
-                        .bind(&self.modified)
-                        .bind(&self.image_width)
-                        .bind(&self.image_height)
-                        .bind(&self.thumbnail)
-                        .bind(&self.tags)
-                        .bind(&self.exegesis)
-                        .bind(&self.score)
-                        .bind(&self.colors)
-                        .bind(&self.shape)
-                        .bind(&self.duration)
-                        .bind(&self.is_del)
-                        .bind(&self.sha1)
-                        .execute(pool)
-                        .await;
-                }
-            }
-        } else {
-            println!("save to db fail");
+    /// 交给写入线程批量保存
+    pub async fn save_to_db(self) {
+        writer().save_metadata(self).await;
+    }
+
+    /// 在事务中保存，由写入线程调用
+    ///
+    /// 元数据保存失败时返回错误，附加信息保存失败只记录日志
+    pub async fn save(&self, tx: &mut Transaction) -> Result<(), Box<dyn Error>> {
+        // 路径已存在时更新原记录，保留标签、评分等用户数据
+        if let Some(stat) = tx
+            .sql("SELECT id, full_path, file_size, modified, sha1, is_del FROM metadata WHERE full_path = ?")
+            .bind(&self.full_path)
+            .select_optional_as::<MetadataStat>()
+            .await?
+        {
+            return self.update_to_db(tx, stat.id).await;
+        }
+        // 内容相同的文件在不同路径下分别记录，通过sha1关联
+        let id = id::<i64>();
+        tx.sql("INSERT INTO metadata (id, full_path, file_name, file_path, file_size, file_suffix, added, created, modified, image_width, image_height, tags, exegesis, score, colors, shape, duration, is_del, sha1) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(id)
+            .bind(&self.full_path)
+            .bind(&self.file_name)
+            .bind(&self.file_path)
+            .bind(self.file_size)
+            .bind(&self.file_suffix)
+            .bind(Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
+            .bind(&self.created)
+            .bind(&self.modified)
+            .bind(self.image_width)
+            .bind(self.image_height)
+            .bind(&self.tags)
+            .bind(&self.exegesis)
+            .bind(self.score)
+            .bind(&self.colors)
+            .bind(&self.shape)
+            .bind(self.duration)
+            .bind(self.is_del)
+            .bind(&self.sha1)
+            .execute()
+            .await?;
+        self.save_details(tx, id).await
+    }
+
+    /// 保存EXIF、PSD、模型、视频、主题色、感知哈希等附加信息，导入sidecar，并更新搜索索引
+    async fn save_details(&self, tx: &mut Transaction, id: i64) -> Result<(), Box<dyn Error>> {
+        if let Some(exif) = &self.exif {
+            exif.save(tx, id).await?;
         }
+        if let Some(psd) = &self.psd {
+            psd.save(tx, id).await?;
+        }
+        if let Some(model) = &self.model {
+            model.save(tx, id).await?;
+        }
+        if let Some(video) = &self.video {
+            video.save(tx, id).await?;
+        }
+        if !self.palette.is_empty() {
+            PaletteColor::save_all(tx, id, &self.palette).await?;
+        }
+        if let Some(hash) = &self.hash {
+            hash.save(tx, id).await?;
+        }
+        if let Some(sidecar) = &self.sidecar {
+            import(tx, id, sidecar).await?;
+        }
+        index(tx.connection(), &[id]).await?;
+        Ok(())
     }
 
+    async fn update_to_db(&self, tx: &mut Transaction, id: i64) -> Result<(), Box<dyn Error>> {
+        tx.sql("UPDATE metadata SET file_name = ?, file_path = ?, file_size = ?, file_suffix = ?, created = ?, modified = ?, image_width = ?, image_height = ?, colors = ?, shape = ?, duration = ?, sha1 = ?, is_del = CASE WHEN is_del = ? THEN ? ELSE is_del END WHERE id = ?")
+            .bind(&self.file_name)
+            .bind(&self.file_path)
+            .bind(self.file_size)
+            .bind(&self.file_suffix)
+            .bind(&self.created)
+            .bind(&self.modified)
+            .bind(self.image_width)
+            .bind(self.image_height)
+            .bind(&self.colors)
+            .bind(&self.shape)
+            .bind(self.duration)
+            .bind(&self.sha1)
+            .bind(MISSING)
+            .bind(NOT_DELETED)
+            .bind(id)
+            .execute()
+            .await?;
+        // 重新扫描后没有的附加信息不保留旧记录，如PSD被替换为同名的普通图片
+        for table in DETAIL_TABLES {
+            tx.sql(&format!("DELETE FROM {table} WHERE metadata_id = ?"))
+                .bind(id)
+                .execute()
+                .await?;
+        }
+        self.save_details(tx, id).await
+    }
+
+    /// 按id批量查询，保持`ids`的顺序
+    pub async fn list_by_ids(session: &Session, ids: &[i64]) -> Vec<Metadata> {
+        let mut map: HashMap<i64, Metadata> = session
+            .select_in::<Metadata>(ids, |v| format!("SELECT * FROM metadata WHERE id IN ({v})"))
+            .await
+            .print_error()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| (v.id, v))
+            .collect();
+        ids.iter().filter_map(|v| map.remove(v)).collect()
+    }
+
+    /// 生成扫描任务，任务已存在时只在文件变化后重新执行
+    ///
+    /// 多次重试后失败的任务在文件没有变化时保持失败，需要手动重试
     pub async fn save_task_to_db(&self, session: &Session) {
-        if let Ok(pool) = session.as_pool() {
-            if let Ok(result) = session
-                .count(
-                    format!(
-                        "SELECT COUNT(*) AS count FROM task WHERE file_path = '{}'",
-                        &self.full_path
-                    )
-                    .as_str(),
-                )
-                .await
-            {
-                if result.count == 0 {
-                    let _ = query(
-                        "INSERT INTO task (id, file_path, file_suffix, status) VALUES (?, ?, ?, ?)",
-                    )
+        let (file_size, modified) = file_stat(Path::new(&self.full_path)).unwrap_or_default();
+        let Some(task) = session
+            .sql("SELECT * FROM task WHERE file_path = ?")
+            .bind(&self.full_path)
+            .select_optional_as::<Task>()
+            .await
+            .print_error()
+        else {
+            return;
+        };
+        match task {
+            None => {
+                session
+                    .sql("INSERT INTO task (id, file_path, file_suffix, status, file_size, modified) VALUES (?, ?, ?, ?, ?, ?)")
                     .bind(id::<i64>())
                     .bind(&self.full_path)
                     .bind(&self.file_suffix)
-                    .bind(0)
-                    .execute(pool)
-                    .await;
-                }
+                    .bind(PENDING)
+                    .bind(file_size)
+                    .bind(&modified)
+                    .execute()
+                    .await
+                    .print_error();
+            }
+            Some(task) if task.file_size != file_size || task.modified != modified => {
+                Task::reset(session, &self.full_path, file_size, &modified).await;
             }
+            Some(_) => {}
         }
     }
 }
 
-fn sha1<P: AsRef<Path>>(path: P) -> Result<String, IoError> {
+pub fn sha1<P: AsRef<Path>>(path: P) -> Result<String, IoError> {
     let mut hasher = Sha1::new();
     get_hash_file(path, &mut hasher)
 }
 
+/// 获取文件大小和修改时间，用于判断文件是否变化
+pub fn file_stat(path: &Path) -> Result<(i64, String), IoError> {
+    let file_metadata = path.metadata()?;
+    let datetime: DateTime<Local> = file_metadata.modified()?.into();
+    Ok((
+        file_metadata.len() as i64,
+        datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+    ))
+}
+
+/// 文件状态，增量扫描时只加载比对需要的字段
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct MetadataStat {
+    pub id: i64,
+    pub full_path: String,
+    pub file_size: i64,
+    pub modified: String,
+    pub sha1: String,
+    pub is_del: u8,
+}
+
+impl MetadataStat {
+    pub async fn get_by_path(session: &Session, full_path: &str) -> sqlx::Result<Option<Self>> {
+        session
+            .sql("SELECT id, full_path, file_size, modified, sha1, is_del FROM metadata WHERE full_path = ?")
+            .bind(full_path)
+            .select_optional_as::<Self>()
+            .await
+    }
+
+    /// 获取目录下所有文件状态
+    pub async fn list_by_directory(session: &Session, directory: &str) -> Vec<Self> {
+        session
+            .sql("SELECT id, full_path, file_size, modified, sha1, is_del FROM metadata WHERE full_path LIKE ? ESCAPE '\\'")
+            .bind(like_prefix(&directory_prefix(directory)))
+            .select_as::<Self>()
+            .await
+            .print_error()
+            .unwrap_or_default()
+    }
+
+    /// 内容未变化，只更新文件状态
+    pub async fn touch(&self, session: &Session, file_size: i64, modified: &str) {
+        session
+            .sql("UPDATE metadata SET file_size = ?, modified = ?, is_del = CASE WHEN is_del = ? THEN ? ELSE is_del END WHERE id = ?")
+            .bind(file_size)
+            .bind(modified)
+            .bind(MISSING)
+            .bind(NOT_DELETED)
+            .bind(self.id)
+            .execute()
+            .await
+            .print_error();
+    }
+
+    /// 文件被移动，更新路径
+    pub async fn relocate(&self, session: &Session, path: &Path) {
+        let metadata = Metadata::load(path);
+        session
+            .sql("UPDATE metadata SET full_path = ?, file_path = ?, file_name = ?, file_suffix = ?, is_del = CASE WHEN is_del = ? THEN ? ELSE is_del END WHERE id = ?")
+            .bind(&metadata.full_path)
+            .bind(&metadata.file_path)
+            .bind(&metadata.file_name)
+            .bind(&metadata.file_suffix)
+            .bind(MISSING)
+            .bind(NOT_DELETED)
+            .bind(self.id)
+            .execute()
+            .await
+            .print_error();
+        reindex(session, &[self.id]).await;
+    }
+
+    /// 文件已不存在，标记为丢失
+    pub async fn tombstone(&self, session: &Session) {
+        session
+            .sql("UPDATE metadata SET is_del = ? WHERE id = ? AND is_del = ?")
+            .bind(MISSING)
+            .bind(self.id)
+            .bind(NOT_DELETED)
+            .execute()
+            .await
+            .print_error();
+    }
+}
+
+/// 目录路径统一以分隔符结尾，避免前缀匹配到同名前缀的兄弟目录
+pub fn directory_prefix(directory: &str) -> String {
+    if directory.ends_with(MAIN_SEPARATOR) {
+        directory.to_string()
+    } else {
+        format!("{directory}{MAIN_SEPARATOR}")
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct MetadataVO {
@@ -203,18 +459,34 @@ pub struct MetadataVO {
     pub tags: String,
     pub exegesis: String,
     pub score: f32,
+    pub title: String,
+    pub label: String,
+    pub source_url: String,
+    pub annotated: String,
     pub is_del: u8,
     pub sha1: String,
     pub image_width: u32,
     pub image_height: u32,
+    /// 列表缩略图地址
     pub thumbnail: String,
+    /// 高分屏列表缩略图地址
+    pub thumbnail_retina: String,
+    /// 详情预览图地址
+    pub thumbnail_preview: String,
     pub colors: String,
     pub shape: String,
     pub duration: i64,
+    pub exif: Option<ExifVO>,
+    pub psd: Option<PsdVO>,
+    pub model: Option<ModelVO>,
+    pub video: Option<VideoVO>,
 }
 
 impl MetadataVO {
     pub fn from(metadata: Metadata) -> Self {
+        let thumbnail = thumbnail_url(&metadata.sha1, ThumbnailSize::Grid);
+        let thumbnail_retina = thumbnail_url(&metadata.sha1, ThumbnailSize::Retina);
+        let thumbnail_preview = thumbnail_url(&metadata.sha1, ThumbnailSize::Preview);
         Self {
             id: metadata.id.to_string(),
             full_path: metadata.full_path,
@@ -228,17 +500,46 @@ impl MetadataVO {
             tags: metadata.tags,
             exegesis: metadata.exegesis,
             score: metadata.score,
+            title: metadata.title,
+            label: metadata.label,
+            source_url: metadata.source_url,
+            annotated: metadata.annotated,
             is_del: metadata.is_del,
             sha1: metadata.sha1,
             image_width: metadata.image_width,
             image_height: metadata.image_height,
-            thumbnail: metadata.thumbnail,
+            thumbnail,
+            thumbnail_retina,
+            thumbnail_preview,
             colors: metadata.colors,
             shape: metadata.shape,
             duration: metadata.duration,
+            exif: metadata.exif.map(ExifVO::from),
+            psd: metadata.psd.map(PsdVO::from),
+            model: metadata.model.map(ModelVO::from),
+            video: metadata.video.map(VideoVO::from),
         }
     }
 
+    /// 转换列表，同时查询EXIF、PSD、模型和视频信息
+    pub async fn list(session: &Session, metadata: Vec<Metadata>) -> Vec<Self> {
+        let ids: Vec<i64> = metadata.iter().map(|v| v.id).collect();
+        let mut exif = Exif::map_by_ids(session, &ids).await;
+        let mut psd = PsdInfo::map_by_ids(session, &ids).await;
+        let mut model = ModelInfo::map_by_ids(session, &ids).await;
+        let mut video = VideoInfo::map_by_ids(session, &ids).await;
+        metadata
+            .into_iter()
+            .map(|mut v| {
+                v.exif = exif.remove(&v.id);
+                v.psd = psd.remove(&v.id);
+                v.model = model.remove(&v.id);
+                v.video = video.remove(&v.id);
+                MetadataVO::from(v)
+            })
+            .collect()
+    }
+
     pub fn empty() -> Self {
         Self {
             id: "-1".to_string(),
@@ -253,14 +554,24 @@ impl MetadataVO {
             tags: String::new(),
             exegesis: String::new(),
             score: 0.0,
+            title: String::new(),
+            label: String::new(),
+            source_url: String::new(),
+            annotated: String::new(),
             is_del: 0,
             sha1: String::new(),
             image_width: 0,
             image_height: 0,
             thumbnail: String::new(),
+            thumbnail_retina: String::new(),
+            thumbnail_preview: String::new(),
             colors: String::new(),
             shape: String::new(),
             duration: 0,
+            exif: None,
+            psd: None,
+            model: None,
+            video: None,
         }
     }
 }
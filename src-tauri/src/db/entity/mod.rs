@@ -1,4 +1,16 @@
 pub mod basket;
+pub mod annotation;
+pub mod duplicate;
+pub mod exif;
 pub mod folder;
+pub mod image_hash;
 pub mod metadata;
+pub mod model;
+pub mod palette;
+pub mod psd;
+pub mod query;
+pub mod search;
+pub mod sidecar;
+pub mod tag;
 pub mod task;
+pub mod video;
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::sqlite::{Session, Transaction};
+use crate::util::error::ErrorHandle;
+
+/// 3D模型信息，与`metadata.id`一一对应
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct ModelInfo {
+    pub metadata_id: i64,
+    pub vertex_count: i64,
+    /// 三角化后的面数
+    pub face_count: i64,
+    /// 包围盒尺寸，单位与模型文件相同
+    pub size_x: f64,
+    pub size_y: f64,
+    pub size_z: f64,
+    /// 材质名称，JSON数组
+    pub materials: String,
+    /// 引用的贴图路径，JSON数组
+    pub textures: String,
+    pub animated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ModelVO {
+    pub vertex_count: i64,
+    pub face_count: i64,
+    pub size_x: f64,
+    pub size_y: f64,
+    pub size_z: f64,
+    pub materials: Vec<String>,
+    pub textures: Vec<String>,
+    pub animated: bool,
+}
+
+impl ModelInfo {
+    /// 在事务中保存，已存在时覆盖
+    pub async fn save(&self, tx: &mut Transaction, metadata_id: i64) -> sqlx::Result<()> {
+        tx.sql("INSERT OR REPLACE INTO model (metadata_id, vertex_count, face_count, size_x, size_y, size_z, materials, textures, animated) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(metadata_id)
+            .bind(self.vertex_count)
+            .bind(self.face_count)
+            .bind(self.size_x)
+            .bind(self.size_y)
+            .bind(self.size_z)
+            .bind(&self.materials)
+            .bind(&self.textures)
+            .bind(self.animated)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// 按`metadata.id`批量查询
+    pub async fn map_by_ids(session: &Session, ids: &[i64]) -> HashMap<i64, ModelInfo> {
+        session
+            .select_in::<ModelInfo>(ids, |v| {
+                format!("SELECT * FROM model WHERE metadata_id IN ({v})")
+            })
+            .await
+            .print_error()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| (v.metadata_id, v))
+            .collect()
+    }
+
+    pub fn set_materials(&mut self, materials: &[String]) {
+        self.materials = serde_json::to_string(materials).unwrap_or_else(|_| String::from("[]"));
+    }
+
+    pub fn get_materials(&self) -> Vec<String> {
+        serde_json::from_str(&self.materials).unwrap_or_default()
+    }
+
+    pub fn set_textures(&mut self, textures: &[String]) {
+        self.textures = serde_json::to_string(textures).unwrap_or_else(|_| String::from("[]"));
+    }
+
+    pub fn get_textures(&self) -> Vec<String> {
+        serde_json::from_str(&self.textures).unwrap_or_default()
+    }
+}
+
+impl ModelVO {
+    pub fn from(model: ModelInfo) -> Self {
+        let materials = model.get_materials();
+        let textures = model.get_textures();
+        Self {
+            vertex_count: model.vertex_count,
+            face_count: model.face_count,
+            size_x: model.size_x,
+            size_y: model.size_y,
+            size_z: model.size_z,
+            materials,
+            textures,
+            animated: model.animated,
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+use palette::{FromColor, Oklab, Srgb};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+use crate::db::entity::metadata::{Metadata, MetadataVO, NOT_DELETED};
+use crate::db::entity::query::Page;
+use crate::db::sqlite::{Arg, Session, Transaction};
+use crate::util::error::ErrorHandle;
+use crate::Result;
+
+/// 默认的颜色距离阈值（OKLab欧氏距离），约为肉眼能明显区分的差异
+pub const DEFAULT_THRESHOLD: f64 = 0.1;
+
+/// 主题色，按占比从大到小排列
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct PaletteColor {
+    pub metadata_id: i64,
+    pub position: i64,
+    /// `#rrggbb`
+    pub color: String,
+    /// 在图片中的占比，0-1
+    pub weight: f64,
+    /// OKLab坐标
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+/// 按颜色筛选
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ColorFilter {
+    /// `#rrggbb`或`#rgb`
+    pub color: String,
+    /// OKLab距离阈值，默认为`DEFAULT_THRESHOLD`
+    pub threshold: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ColorHitVO {
+    pub metadata: MetadataVO,
+    /// 最接近的主题色
+    pub color: String,
+    pub distance: f64,
+    /// 相似度得分，综合距离和占比，越大越相关
+    pub score: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ColorSearchVO {
+    pub total: i64,
+    pub items: Vec<ColorHitVO>,
+}
+
+impl PaletteColor {
+    pub fn new(position: usize, rgb: Srgb<u8>, weight: f64) -> Self {
+        let lab = oklab(rgb);
+        Self {
+            metadata_id: 0,
+            position: position as i64,
+            color: format!("#{:02x}{:02x}{:02x}", rgb.red, rgb.green, rgb.blue),
+            weight,
+            l: lab.l as f64,
+            a: lab.a as f64,
+            b: lab.b as f64,
+        }
+    }
+
+    /// 在事务中保存，覆盖原来的主题色
+    pub async fn save_all(
+        tx: &mut Transaction,
+        metadata_id: i64,
+        colors: &[PaletteColor],
+    ) -> sqlx::Result<()> {
+        tx.sql("DELETE FROM palette WHERE metadata_id = ?")
+            .bind(metadata_id)
+            .execute()
+            .await?;
+        for color in colors.iter() {
+            tx.sql("INSERT INTO palette (metadata_id, position, color, weight, l, a, b) VALUES (?, ?, ?, ?, ?, ?, ?)")
+                .bind(metadata_id)
+                .bind(color.position)
+                .bind(&color.color)
+                .bind(color.weight)
+                .bind(color.l)
+                .bind(color.a)
+                .bind(color.b)
+                .execute()
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn distance(&self, target: &Oklab) -> f64 {
+        let (l, a, b) = (target.l as f64, target.a as f64, target.b as f64);
+        ((self.l - l).powi(2) + (self.a - a).powi(2) + (self.b - b).powi(2)).sqrt()
+    }
+}
+
+impl ColorFilter {
+    /// `palette p`上的查询条件，先按坐标范围缩小候选再比较距离，SQLite没有开方函数所以比较平方
+    pub fn condition(&self) -> Option<(&'static str, Vec<Arg>)> {
+        let target = oklab(parse_hex(&self.color)?);
+        let threshold = self.threshold.unwrap_or(DEFAULT_THRESHOLD).max(0.0);
+        let (l, a, b) = (target.l as f64, target.a as f64, target.b as f64);
+        let args = vec![
+            Arg::Real(l - threshold),
+            Arg::Real(l + threshold),
+            Arg::Real(a - threshold),
+            Arg::Real(a + threshold),
+            Arg::Real(b - threshold),
+            Arg::Real(b + threshold),
+            Arg::Real(l),
+            Arg::Real(l),
+            Arg::Real(a),
+            Arg::Real(a),
+            Arg::Real(b),
+            Arg::Real(b),
+            Arg::Real(threshold * threshold),
+        ];
+        Some((
+            "p.l BETWEEN ? AND ? AND p.a BETWEEN ? AND ? AND p.b BETWEEN ? AND ? AND (p.l - ?) * (p.l - ?) + (p.a - ?) * (p.a - ?) + (p.b - ?) * (p.b - ?) <= ?",
+            args,
+        ))
+    }
+}
+
+/// 查找主题色中包含相近颜色的文件
+///
+/// 得分为所有相近主题色的`占比 * (1 - 距离 / 阈值)`之和，颜色越接近、面积越大越靠前
+pub async fn search_by_color(
+    session: &Session,
+    filter: &ColorFilter,
+    page: Option<Page>,
+) -> ColorSearchVO {
+    let mut result = ColorSearchVO {
+        total: 0,
+        items: Vec::new(),
+    };
+    let (Some((condition, args)), Some(rgb)) = (filter.condition(), parse_hex(&filter.color))
+    else {
+        return result;
+    };
+    let target = oklab(rgb);
+    let threshold = filter
+        .threshold
+        .unwrap_or(DEFAULT_THRESHOLD)
+        .max(f64::EPSILON);
+    let candidates = session
+        .sql(&format!("SELECT p.* FROM palette p JOIN metadata m ON m.id = p.metadata_id WHERE m.is_del = ? AND {condition}"))
+        .bind(NOT_DELETED)
+        .bind_all(args)
+        .select_as::<PaletteColor>()
+        .await
+        .print_error()
+        .unwrap_or_default();
+
+    // 每个文件的得分、最近的距离和颜色
+    let mut scores: HashMap<i64, (f64, f64, String)> = HashMap::new();
+    for color in candidates {
+        let distance = color.distance(&target);
+        let score = color.weight * (1.0 - distance / threshold).max(0.0);
+        let entry = scores
+            .entry(color.metadata_id)
+            .or_insert((0.0, f64::MAX, String::new()));
+        entry.0 += score;
+        if distance < entry.1 {
+            entry.1 = distance;
+            entry.2 = color.color;
+        }
+    }
+    let mut ranked: Vec<(i64, (f64, f64, String))> = scores.into_iter().collect();
+    ranked.sort_by(|x, y| {
+        y.1 .0
+            .total_cmp(&x.1 .0)
+            .then(x.1 .1.total_cmp(&y.1 .1))
+            .then(x.0.cmp(&y.0))
+    });
+    result.total = ranked.len() as i64;
+
+    let page = page.unwrap_or_default();
+    let ranked: Vec<(i64, (f64, f64, String))> = ranked
+        .into_iter()
+        .skip(page.offset())
+        .take(page.limit())
+        .collect();
+    let ids: Vec<i64> = ranked.iter().map(|v| v.0).collect();
+    let list = Metadata::list_by_ids(session, &ids).await;
+    let mut hits: HashMap<i64, (f64, f64, String)> = ranked.into_iter().collect();
+    result.items = MetadataVO::list(session, list)
+        .await
+        .into_iter()
+        .filter_map(|v| {
+            let (score, distance, color) = hits.remove(&v.id.parse::<i64>().ok()?)?;
+            Some(ColorHitVO {
+                metadata: v,
+                color,
+                distance,
+                score,
+            })
+        })
+        .collect();
+    result
+}
+
+/// 从`metadata.colors`生成主题色，旧数据没有占比，按数量平均分配
+pub fn backfill_palette(
+    conn: &mut SqliteConnection,
+) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+    Box::pin(async move {
+        let rows: Vec<(i64, String)> =
+            sqlx::query_as("SELECT id, colors FROM metadata WHERE colors != ''")
+                .fetch_all(&mut *conn)
+                .await?;
+        for (id, colors) in rows {
+            let colors: Vec<Srgb<u8>> = colors.split(',').filter_map(parse_hex).collect();
+            let weight = 1.0 / colors.len().max(1) as f64;
+            for (position, rgb) in colors.into_iter().enumerate() {
+                let color = PaletteColor::new(position, rgb, weight);
+                sqlx::query("INSERT INTO palette (metadata_id, position, color, weight, l, a, b) VALUES (?, ?, ?, ?, ?, ?, ?)")
+                    .bind(id)
+                    .bind(color.position)
+                    .bind(&color.color)
+                    .bind(color.weight)
+                    .bind(color.l)
+                    .bind(color.a)
+                    .bind(color.b)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+        }
+        Ok(())
+    })
+}
+
+fn oklab(rgb: Srgb<u8>) -> Oklab {
+    Oklab::from_color(rgb.into_format::<f32>())
+}
+
+/// 解析`#rrggbb`或`#rgb`，`#`可以省略
+pub fn parse_hex(hex: &str) -> Option<Srgb<u8>> {
+    let hex = hex.trim().trim_start_matches('#');
+    let hex = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 => hex.to_string(),
+        _ => return None,
+    };
+    let value = u32::from_str_radix(&hex, 16).ok()?;
+    Some(Srgb::new(
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use palette::Srgb;
+
+    use crate::db::entity::palette::{parse_hex, search_by_color, ColorFilter, PaletteColor};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+
+    #[tokio::test]
+    async fn test_search_by_color() {
+        assert_eq!(parse_hex("#0f8"), Some(Srgb::new(0, 0xff, 0x88)));
+        assert_eq!(parse_hex("12345"), None);
+
+        let session = temp_session("palette").await;
+        migrate(&session).await.unwrap();
+        // 1：大面积青色，2：小面积青色，3：没有青色
+        let palettes = [
+            (1, vec![(0x00, 0x80, 0x80, 0.6), (0xff, 0xff, 0xff, 0.4)]),
+            (2, vec![(0xff, 0x00, 0x00, 0.9), (0x00, 0x80, 0x80, 0.1)]),
+            (3, vec![(0xff, 0x00, 0x00, 1.0)]),
+        ];
+        let mut tx = session.begin().await.unwrap();
+        for (id, colors) in palettes {
+            tx.sql("INSERT INTO metadata (id) VALUES (?)")
+                .bind(id)
+                .execute()
+                .await
+                .unwrap();
+            let colors: Vec<PaletteColor> = colors
+                .into_iter()
+                .enumerate()
+                .map(|(i, (r, g, b, w))| PaletteColor::new(i, Srgb::new(r, g, b), w))
+                .collect();
+            PaletteColor::save_all(&mut tx, id, &colors).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+
+        let filter = ColorFilter {
+            color: String::from("#058585"),
+            threshold: None,
+        };
+        let result = search_by_color(&session, &filter, None).await;
+        assert_eq!(result.total, 2);
+        assert_eq!(result.items[0].metadata.id, "1");
+        assert_eq!(result.items[0].color, "#008080");
+        assert!(result.items[0].score > result.items[1].score);
+    }
+}
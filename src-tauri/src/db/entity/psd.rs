@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::sqlite::{Session, Transaction};
+use crate::util::error::ErrorHandle;
+
+/// PSD文档信息，与`metadata.id`一一对应
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct PsdInfo {
+    pub metadata_id: i64,
+    /// 颜色模式，如`rgb`、`cmyk`
+    pub color_mode: String,
+    /// 每个通道的位深
+    pub depth: i64,
+    pub layer_count: i64,
+    pub group_count: i64,
+    /// 文件中没有合并图像，缩略图由可见图层合成
+    pub composited: bool,
+    /// 图层和分组，保存在`psd_layer`表
+    #[sqlx(skip)]
+    pub layers: Vec<PsdLayerInfo>,
+}
+
+/// 图层或分组
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct PsdLayerInfo {
+    pub metadata_id: i64,
+    /// 在文件中的顺序，分组排在图层之后
+    pub position: i64,
+    pub name: String,
+    pub is_group: bool,
+    /// 所在分组的名称，不在分组中时为空
+    pub parent: String,
+    pub visible: bool,
+    /// 不透明度，0-255
+    pub opacity: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PsdVO {
+    pub color_mode: String,
+    pub depth: i64,
+    pub layer_count: i64,
+    pub group_count: i64,
+    pub composited: bool,
+    pub layers: Vec<PsdLayerVO>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PsdLayerVO {
+    pub name: String,
+    pub is_group: bool,
+    pub parent: String,
+    pub visible: bool,
+    pub opacity: i64,
+}
+
+impl PsdInfo {
+    /// 在事务中保存，已存在时覆盖原来的图层列表
+    pub async fn save(&self, tx: &mut Transaction, metadata_id: i64) -> sqlx::Result<()> {
+        tx.sql("INSERT OR REPLACE INTO psd (metadata_id, color_mode, depth, layer_count, group_count, composited) VALUES (?, ?, ?, ?, ?, ?)")
+            .bind(metadata_id)
+            .bind(&self.color_mode)
+            .bind(self.depth)
+            .bind(self.layer_count)
+            .bind(self.group_count)
+            .bind(self.composited)
+            .execute()
+            .await?;
+        tx.sql("DELETE FROM psd_layer WHERE metadata_id = ?")
+            .bind(metadata_id)
+            .execute()
+            .await?;
+        for layer in self.layers.iter() {
+            tx.sql("INSERT INTO psd_layer (metadata_id, position, name, is_group, parent, visible, opacity) VALUES (?, ?, ?, ?, ?, ?, ?)")
+                .bind(metadata_id)
+                .bind(layer.position)
+                .bind(&layer.name)
+                .bind(layer.is_group)
+                .bind(&layer.parent)
+                .bind(layer.visible)
+                .bind(layer.opacity)
+                .execute()
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// 按`metadata.id`批量查询，包括图层列表
+    pub async fn map_by_ids(session: &Session, ids: &[i64]) -> HashMap<i64, PsdInfo> {
+        let mut map: HashMap<i64, PsdInfo> = session
+            .select_in::<PsdInfo>(ids, |v| {
+                format!("SELECT * FROM psd WHERE metadata_id IN ({v})")
+            })
+            .await
+            .print_error()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| (v.metadata_id, v))
+            .collect();
+        let layers = session
+            .select_in::<PsdLayerInfo>(ids, |v| {
+                format!("SELECT * FROM psd_layer WHERE metadata_id IN ({v}) ORDER BY metadata_id, position")
+            })
+            .await
+            .print_error()
+            .unwrap_or_default();
+        for layer in layers {
+            if let Some(psd) = map.get_mut(&layer.metadata_id) {
+                psd.layers.push(layer);
+            }
+        }
+        map
+    }
+}
+
+impl PsdVO {
+    pub fn from(psd: PsdInfo) -> Self {
+        Self {
+            color_mode: psd.color_mode,
+            depth: psd.depth,
+            layer_count: psd.layer_count,
+            group_count: psd.group_count,
+            composited: psd.composited,
+            layers: psd.layers.into_iter().map(PsdLayerVO::from).collect(),
+        }
+    }
+}
+
+impl PsdLayerVO {
+    pub fn from(layer: PsdLayerInfo) -> Self {
+        Self {
+            name: layer.name,
+            is_group: layer.is_group,
+            parent: layer.parent,
+            visible: layer.visible,
+            opacity: layer.opacity,
+        }
+    }
+}
@@ -0,0 +1,486 @@
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{FromRow, Row};
+
+use crate::db::entity::basket::Basket;
+use crate::db::entity::metadata::{directory_prefix, Metadata, MetadataVO, NOT_DELETED};
+use crate::db::entity::palette::ColorFilter;
+use crate::db::entity::tag::TagFilter;
+use crate::db::sqlite::{like_prefix, Arg, Session};
+use crate::util::error::ErrorHandle;
+
+/// 默认每页数量
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// 每页最大数量，避免一次返回过多数据
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// 分页，`current`从1开始
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Page {
+    pub size: usize,
+    pub current: usize,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Page {
+            size: DEFAULT_PAGE_SIZE,
+            current: 1,
+        }
+    }
+}
+
+impl Page {
+    /// 每页数量，限制在`MAX_PAGE_SIZE`以内
+    pub fn limit(&self) -> usize {
+        self.size.clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// 当前页之前的数量
+    pub fn offset(&self) -> usize {
+        self.current.max(1).saturating_sub(1) * self.limit()
+    }
+}
+
+/// 排序字段
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortField {
+    /// 拍摄时间，没有EXIF时使用文件创建时间
+    #[default]
+    Captured,
+    Name,
+    Size,
+    Added,
+    Created,
+    Modified,
+    Score,
+    /// 像素数
+    Dimensions,
+    Duration,
+}
+
+impl SortField {
+    fn expression(&self) -> &'static str {
+        match self {
+            Self::Captured => "COALESCE(NULLIF(e.captured, ''), m.created)",
+            Self::Name => "m.file_name COLLATE NOCASE",
+            Self::Size => "m.file_size",
+            Self::Added => "m.added",
+            Self::Created => "m.created",
+            Self::Modified => "m.modified",
+            Self::Score => "m.score",
+            Self::Dimensions => "m.image_width * m.image_height",
+            Self::Duration => "m.duration",
+        }
+    }
+
+    /// 读取排序值，用于生成下一页的游标
+    fn read(&self, row: &SqliteRow) -> Option<SortKey> {
+        match self {
+            Self::Name | Self::Captured | Self::Added | Self::Created | Self::Modified => {
+                row.try_get("sort_key").ok().map(SortKey::Text)
+            }
+            Self::Score => row.try_get("sort_key").ok().map(SortKey::Real),
+            Self::Size | Self::Dimensions | Self::Duration => {
+                row.try_get("sort_key").ok().map(SortKey::Integer)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// 按时间筛选时使用的字段
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DateField {
+    Added,
+    #[default]
+    Created,
+    Modified,
+}
+
+impl DateField {
+    fn column(&self) -> &'static str {
+        match self {
+            Self::Added => "m.added",
+            Self::Created => "m.created",
+            Self::Modified => "m.modified",
+        }
+    }
+}
+
+/// 画面方向
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+    Square,
+}
+
+/// 闭区间，未设置的一端不限制
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Range<T> {
+    pub min: Option<T>,
+    pub max: Option<T>,
+}
+
+/// 排序值
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum SortKey {
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl From<SortKey> for Arg {
+    fn from(value: SortKey) -> Self {
+        match value {
+            SortKey::Integer(v) => Arg::Integer(v),
+            SortKey::Real(v) => Arg::Real(v),
+            SortKey::Text(v) => Arg::Text(v),
+        }
+    }
+}
+
+/// 键集分页的游标，即上一页最后一条的排序值和id
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Cursor {
+    pub key: SortKey,
+    pub id: String,
+}
+
+/// 元数据查询，所有筛选条件之间为“且”的关系
+///
+/// 设置`after`时使用键集分页，忽略`page.current`，适合连续加载大量数据
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MetadataQuery {
+    pub basket_id: Option<String>,
+    pub folder: Option<String>,
+    /// 是否包含子目录，默认包含
+    pub recursive: Option<bool>,
+    /// 文件后缀，不区分大小写
+    pub suffixes: Vec<String>,
+    pub size: Range<i64>,
+    pub date_field: DateField,
+    /// 格式为`%Y-%m-%d %H:%M:%S`，只有日期时包含当天
+    pub date: Range<String>,
+    pub width: Range<u32>,
+    pub height: Range<u32>,
+    pub orientation: Option<Orientation>,
+    /// 宽高比，如`16:9`
+    pub shapes: Vec<String>,
+    /// 按标签筛选，支持“且”“或”“非”
+    pub tags: TagFilter,
+    pub score: Range<f32>,
+    /// 颜色标记，见`annotation::LABELS`
+    pub labels: Vec<String>,
+    /// 主题色中包含相近的颜色
+    pub color: Option<ColorFilter>,
+    pub sort: SortField,
+    pub order: Order,
+    pub page: Option<Page>,
+    pub after: Option<Cursor>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageVO<T> {
+    /// 符合筛选条件的总数
+    pub total: i64,
+    pub items: Vec<T>,
+    /// 下一页的游标，没有更多数据时为空
+    pub next: Option<Cursor>,
+}
+
+/// 以`AND`连接的查询条件
+#[derive(Default)]
+pub(crate) struct Conditions {
+    sql: Vec<String>,
+    pub args: Vec<Arg>,
+}
+
+impl Conditions {
+    pub fn push<T: Into<Arg>>(&mut self, sql: &str, args: impl IntoIterator<Item = T>) {
+        self.sql.push(sql.to_string());
+        self.args.extend(args.into_iter().map(Into::into));
+    }
+
+    fn range<T: Into<Arg> + Clone>(&mut self, column: &str, range: &Range<T>) {
+        if let Some(min) = &range.min {
+            self.push(&format!("{column} >= ?"), [min.clone()]);
+        }
+        if let Some(max) = &range.max {
+            self.push(&format!("{column} <= ?"), [max.clone()]);
+        }
+    }
+
+    /// 多个值中任意一个满足条件，没有值时不限制
+    pub fn any<T: Into<Arg> + Clone>(&mut self, sql: &str, values: &[T]) {
+        if values.is_empty() {
+            return;
+        }
+        let sql = vec![format!("({sql})"); values.len()].join(" OR ");
+        self.push(&format!("({sql})"), values.iter().cloned());
+    }
+
+    /// 文件在收藏夹的目录中，收藏夹没有目录时不匹配任何文件
+    pub async fn basket(&mut self, session: &Session, basket_id: &str) {
+        let directories: Vec<String> = Basket::directories(session, basket_id)
+            .await
+            .iter()
+            .map(|v| like_prefix(&directory_prefix(v)))
+            .collect();
+        if directories.is_empty() {
+            self.push::<Arg>("0", []);
+        }
+        self.any("m.file_path LIKE ? ESCAPE '\\'", &directories);
+    }
+
+    pub fn to_sql(&self) -> String {
+        self.sql.join(" AND ")
+    }
+}
+
+impl MetadataQuery {
+    /// 生成筛选条件，不需要关联其他表
+    async fn conditions(&self, session: &Session) -> Conditions {
+        let mut conditions = Conditions::default();
+        conditions.push("m.is_del = ?", [NOT_DELETED]);
+        if let Some(basket_id) = &self.basket_id {
+            conditions.basket(session, basket_id).await;
+        }
+        if let Some(folder) = &self.folder {
+            if self.recursive.unwrap_or(true) {
+                conditions.push(
+                    "m.file_path LIKE ? ESCAPE '\\'",
+                    [like_prefix(&directory_prefix(folder))],
+                );
+            } else {
+                conditions.push("m.file_path = ?", [directory_prefix(folder)]);
+            }
+        }
+        let suffixes: Vec<String> = self.suffixes.iter().map(|v| v.to_lowercase()).collect();
+        conditions.any("LOWER(m.file_suffix) = ?", &suffixes);
+        conditions.range("m.file_size", &self.size);
+        let date = Range {
+            min: self.date.min.clone(),
+            // 只有日期时包含当天的所有时间
+            max: self.date.max.as_ref().map(|v| match v.len() {
+                10 => format!("{v} 23:59:59"),
+                _ => v.clone(),
+            }),
+        };
+        conditions.range(self.date_field.column(), &date);
+        conditions.range("m.image_width", &self.width);
+        conditions.range("m.image_height", &self.height);
+        if let Some(orientation) = self.orientation {
+            let sql = match orientation {
+                Orientation::Landscape => "m.image_width > m.image_height",
+                Orientation::Portrait => "m.image_width < m.image_height",
+                Orientation::Square => "m.image_width = m.image_height AND m.image_width > 0",
+            };
+            conditions.push::<Arg>(sql, []);
+        }
+        conditions.any("m.shape = ?", &self.shapes);
+        for (sql, args) in self.tags.conditions(session).await {
+            conditions.push(&sql, args);
+        }
+        conditions.range("m.score", &self.score);
+        conditions.any("m.label = ?", &self.labels);
+        if let Some(color) = &self.color {
+            match color.condition() {
+                Some((sql, args)) => conditions.push(
+                    &format!(
+                        "EXISTS (SELECT 1 FROM palette p WHERE p.metadata_id = m.id AND {sql})"
+                    ),
+                    args,
+                ),
+                None => conditions.push::<Arg>("0", []),
+            }
+        }
+        conditions
+    }
+
+    pub async fn execute(&self, session: &Session) -> PageVO<MetadataVO> {
+        let conditions = self.conditions(session).await;
+        let filter = conditions.to_sql();
+        let total = session
+            .sql(&format!(
+                "SELECT COUNT(*) AS count FROM metadata m WHERE {filter}"
+            ))
+            .bind_all(conditions.args.clone())
+            .count()
+            .await
+            .print_error()
+            .map_or(0, |v| v.count);
+
+        let sort = self.sort.expression();
+        let (order, compare) = match self.order {
+            Order::Asc => ("ASC", ">"),
+            Order::Desc => ("DESC", "<"),
+        };
+        let mut sql = format!(
+            "SELECT m.*, {sort} AS sort_key FROM metadata m LEFT JOIN exif e ON e.metadata_id = m.id WHERE {filter}"
+        );
+        let mut args = conditions.args;
+        let page = self.page.clone().unwrap_or_default();
+        let size = page.limit();
+        let mut offset = 0;
+        if let Some(cursor) = &self.after {
+            // 排序值相同时按id区分，保证翻页不重复不遗漏
+            sql.push_str(&format!(
+                " AND (({sort}) {compare} ? OR (({sort}) = ? AND m.id {compare} ?))"
+            ));
+            args.push(cursor.key.clone().into());
+            args.push(cursor.key.clone().into());
+            args.push(cursor.id.parse::<i64>().unwrap_or_default().into());
+        } else {
+            offset = page.offset();
+        }
+        sql.push_str(&format!(
+            " ORDER BY {sort} {order}, m.id {order} LIMIT ? OFFSET ?"
+        ));
+        args.push(size.into());
+        args.push(offset.into());
+
+        let rows = session
+            .sql(&sql)
+            .bind_all(args)
+            .select()
+            .await
+            .print_error()
+            .unwrap_or_default();
+        let next = match rows.last() {
+            Some(row) if rows.len() == size => self.sort.read(row).and_then(|key| {
+                let id: i64 = row.try_get("id").ok()?;
+                Some(Cursor {
+                    key,
+                    id: id.to_string(),
+                })
+            }),
+            _ => None,
+        };
+        let metadata: Vec<Metadata> = rows
+            .iter()
+            .filter_map(|v| Metadata::from_row(v).print_error())
+            .collect();
+        PageVO {
+            total,
+            items: MetadataVO::list(session, metadata).await,
+            next,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::entity::query::{MetadataQuery, Order, Page, Range, SortField, MAX_PAGE_SIZE};
+    use crate::db::entity::tag::{Tag, TagFilter};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+
+    #[test]
+    fn test_page() {
+        let page = Page {
+            size: 20,
+            current: 3,
+        };
+        assert_eq!((page.limit(), page.offset()), (20, 40));
+        let page = Page {
+            size: usize::MAX,
+            current: 0,
+        };
+        assert_eq!((page.limit(), page.offset()), (MAX_PAGE_SIZE, 0));
+        assert_eq!(Page::default().offset(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let session = temp_session("query").await;
+        migrate(&session).await.unwrap();
+        for (i, suffix) in ["jpg", "PNG", "jpg", "mp4", "jpg"].iter().enumerate() {
+            session
+                .sql("INSERT INTO metadata (id, file_path, file_name, file_suffix, file_size, image_width, image_height, created) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+                .bind(i as i64 + 1)
+                .bind(if i < 3 { "/a/" } else { "/a/b/" })
+                .bind(format!("file{i}"))
+                .bind(*suffix)
+                .bind(i as i64 * 100)
+                .bind(200 + i as i64)
+                .bind(200)
+                .bind(format!("2024-01-0{} 12:00:00", i + 1))
+                .execute()
+                .await
+                .unwrap();
+        }
+        let tags = |names: &[&str]| names.iter().map(|v| v.to_string()).collect::<Vec<String>>();
+        Tag::assign(&session, &[1, 3, 5], &tags(&["cat", "dog"]), &[]).await.unwrap();
+        Tag::assign(&session, &[2, 4], &tags(&["catalog"]), &[]).await.unwrap();
+
+        let query = MetadataQuery {
+            folder: Some(String::from("/a")),
+            recursive: Some(false),
+            suffixes: vec![String::from("png"), String::from("JPG")],
+            ..MetadataQuery::default()
+        };
+        assert_eq!(query.execute(&session).await.total, 3);
+
+        let query = MetadataQuery {
+            tags: TagFilter {
+                all: tags(&["cat"]),
+                ..TagFilter::default()
+            },
+            size: Range {
+                min: Some(100),
+                max: None,
+            },
+            ..MetadataQuery::default()
+        };
+        let page = query.execute(&session).await;
+        assert_eq!(page.total, 2);
+        assert_eq!(page.items[0].file_name, "file4");
+
+        let query = MetadataQuery {
+            tags: TagFilter {
+                any: tags(&["dog", "catalog"]),
+                none: tags(&["cat"]),
+                ..TagFilter::default()
+            },
+            ..MetadataQuery::default()
+        };
+        assert_eq!(query.execute(&session).await.total, 2);
+
+        // 键集分页依次取出全部数据
+        let mut query = MetadataQuery {
+            sort: SortField::Size,
+            order: Order::Asc,
+            page: Some(crate::db::entity::query::Page {
+                size: 2,
+                current: 1,
+            }),
+            ..MetadataQuery::default()
+        };
+        let mut names = Vec::new();
+        loop {
+            let page = query.execute(&session).await;
+            assert_eq!(page.total, 5);
+            names.extend(page.items.into_iter().map(|v| v.file_name));
+            match page.next {
+                Some(next) => query.after = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(names, ["file0", "file1", "file2", "file3", "file4"]);
+    }
+}
@@ -0,0 +1,375 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Row, SqliteConnection};
+
+use crate::db::entity::metadata::{Metadata, MetadataVO, NOT_DELETED};
+use crate::db::entity::query::Page;
+use crate::db::sqlite::{placeholders, Session, QUERY_CHUNK};
+use crate::util::error::ErrorHandle;
+use crate::Result;
+
+/// 中日文字之间插入的零宽空格，使分词器把每个字作为一个词
+const SEPARATOR: char = '\u{200B}';
+/// 摘要中匹配内容的起止标记，使用私有区字符避免与文件名冲突
+const MARK_START: char = '\u{E000}';
+const MARK_END: char = '\u{E001}';
+
+/// 索引的内容，`rowid`为`metadata.id`
+#[derive(Debug, FromRow)]
+struct SearchDocument {
+    id: i64,
+    file_name: String,
+    file_path: String,
+    tags: String,
+    exegesis: String,
+    /// EXIF关键词，JSON数组
+    keywords: String,
+    title: String,
+}
+
+/// 摘要片段
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SnippetVO {
+    pub text: String,
+    /// 是否为匹配的内容
+    pub matched: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchHitVO {
+    pub metadata: MetadataVO,
+    pub snippet: Vec<SnippetVO>,
+    /// bm25得分，越小越相关
+    pub rank: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchVO {
+    pub total: i64,
+    pub items: Vec<SearchHitVO>,
+}
+
+/// 更新索引，在保存元数据的事务中调用，保证索引与`metadata`表一致
+pub async fn index(conn: &mut SqliteConnection, ids: &[i64]) -> sqlx::Result<()> {
+    for chunk in ids.chunks(QUERY_CHUNK) {
+        let sql = format!(
+            "SELECT m.id, m.file_name, m.file_path, m.tags, m.exegesis, IFNULL(e.keywords, '[]') AS keywords, m.title FROM metadata m LEFT JOIN exif e ON e.metadata_id = m.id WHERE m.id IN ({})",
+            placeholders(chunk.len())
+        );
+        let mut select = sqlx::query_as::<_, SearchDocument>(&sql);
+        let delete_sql = format!(
+            "DELETE FROM metadata_fts WHERE rowid IN ({})",
+            placeholders(chunk.len())
+        );
+        let mut delete = sqlx::query(&delete_sql);
+        for id in chunk {
+            select = select.bind(id);
+            delete = delete.bind(id);
+        }
+        let documents = select.fetch_all(&mut *conn).await?;
+        delete.execute(&mut *conn).await?;
+        for document in documents {
+            let keywords: Vec<String> =
+                serde_json::from_str(&document.keywords).unwrap_or_default();
+            sqlx::query("INSERT INTO metadata_fts (rowid, file_name, file_path, tags, exegesis, keywords, title) VALUES (?, ?, ?, ?, ?, ?, ?)")
+                .bind(document.id)
+                .bind(segment(&document.file_name))
+                .bind(segment(&document.file_path))
+                .bind(segment(&document.tags.replace(',', " ")))
+                .bind(segment(&document.exegesis))
+                .bind(segment(&keywords.join(" ")))
+                .bind(segment(&document.title))
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// 在单独的事务中更新索引，用于不经过写入线程的修改
+pub async fn reindex(session: &Session, ids: &[i64]) {
+    if let Some(mut tx) = session.begin().await.print_error() {
+        if index(tx.connection(), ids).await.print_error().is_some() {
+            tx.commit().await.print_error();
+        }
+    }
+}
+
+/// 重建全部索引，用于数据库迁移
+pub fn rebuild_index(
+    conn: &mut SqliteConnection,
+) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+    Box::pin(async move {
+        sqlx::query("DELETE FROM metadata_fts")
+            .execute(&mut *conn)
+            .await?;
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM metadata ORDER BY id")
+            .fetch_all(&mut *conn)
+            .await?;
+        index(conn, &ids).await?;
+        Ok(())
+    })
+}
+
+/// 全文搜索，按相关度排序
+///
+/// 支持的语法：
+/// - 普通词按前缀匹配，如`IMG`匹配`IMG_2034`
+/// - 双引号内为短语，按顺序完整匹配
+/// - `AND`、`OR`、`NOT`，以及以`-`开头的词表示排除
+pub async fn search(session: &Session, keyword: &str, page: Option<Page>) -> SearchVO {
+    let Some(expression) = to_match(keyword) else {
+        return SearchVO {
+            total: 0,
+            items: Vec::new(),
+        };
+    };
+    let total = session
+        .sql("SELECT COUNT(*) AS count FROM metadata_fts f JOIN metadata m ON m.id = f.rowid WHERE metadata_fts MATCH ? AND m.is_del = ?")
+        .bind(&expression)
+        .bind(NOT_DELETED)
+        .count()
+        .await
+        .print_error()
+        .map_or(0, |v| v.count);
+    let page = page.unwrap_or_default();
+    // 文件名和标签的权重高于路径
+    let rows = session
+        .sql("SELECT m.*, bm25(metadata_fts, 10.0, 1.0, 5.0, 2.0, 5.0, 8.0) AS rank, snippet(metadata_fts, -1, ?, ?, '…', 12) AS snippet FROM metadata_fts JOIN metadata m ON m.id = metadata_fts.rowid WHERE metadata_fts MATCH ? AND m.is_del = ? ORDER BY rank LIMIT ? OFFSET ?")
+        .bind(MARK_START.to_string())
+        .bind(MARK_END.to_string())
+        .bind(&expression)
+        .bind(NOT_DELETED)
+        .bind(page.limit())
+        .bind(page.offset())
+        .select()
+        .await
+        .print_error()
+        .unwrap_or_default();
+    let mut hits = Vec::new();
+    let mut metadata = Vec::new();
+    for row in rows.iter() {
+        if let Some(v) = Metadata::from_row(row).print_error() {
+            metadata.push(v);
+            let snippet: String = row.try_get("snippet").unwrap_or_default();
+            hits.push((parse_snippet(&snippet), row.try_get("rank").unwrap_or(0f64)));
+        }
+    }
+    let items = MetadataVO::list(session, metadata)
+        .await
+        .into_iter()
+        .zip(hits)
+        .map(|(metadata, (snippet, rank))| SearchHitVO {
+            metadata,
+            snippet,
+            rank,
+        })
+        .collect();
+    SearchVO { total, items }
+}
+
+/// 中文、日文假名逐字分词
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2FA1F}')
+}
+
+/// 在中日文字前后插入分隔符，索引和查询使用相同的规则
+fn segment(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut previous: Option<char> = None;
+    for c in text.chars() {
+        if let Some(p) = previous {
+            if (is_cjk(c) || is_cjk(p)) && !p.is_whitespace() && !c.is_whitespace() {
+                result.push(SEPARATOR);
+            }
+        }
+        result.push(c);
+        previous = Some(c);
+    }
+    result
+}
+
+/// 转换为FTS5查询表达式，没有可搜索的内容时返回`None`
+fn to_match(keyword: &str) -> Option<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut chars = keyword.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut negate = false;
+        if c == '-' {
+            negate = true;
+            chars.next();
+        }
+        let (term, phrase) = if chars.peek() == Some(&'"') {
+            chars.next();
+            let term: String = chars.by_ref().take_while(|v| *v != '"').collect();
+            (term, true)
+        } else {
+            let mut term = String::new();
+            while let Some(&v) = chars.peek() {
+                if v.is_whitespace() {
+                    break;
+                }
+                term.push(v);
+                chars.next();
+            }
+            (term, false)
+        };
+        if !phrase && matches!(term.as_str(), "AND" | "OR" | "NOT") {
+            // 运算符不能出现在开头或连续出现
+            if tokens.last().is_some_and(|v| !is_operator(v)) {
+                tokens.push(term);
+            }
+            continue;
+        }
+        let text = term.trim_end_matches('*');
+        // 只保留能被分词的内容，避免标点导致语法错误
+        if !text.chars().any(char::is_alphanumeric) {
+            continue;
+        }
+        let quoted = format!("\"{}\"", segment(text).replace('"', "\"\""));
+        // 普通词默认按前缀匹配
+        let quoted = if phrase && !term.ends_with('*') {
+            quoted
+        } else {
+            format!("{quoted}*")
+        };
+        if negate {
+            if tokens.is_empty() {
+                continue;
+            }
+            if tokens.last().is_some_and(|v| is_operator(v)) {
+                tokens.pop();
+            }
+            tokens.push(String::from("NOT"));
+        }
+        tokens.push(quoted);
+    }
+    while tokens.last().is_some_and(|v| is_operator(v)) {
+        tokens.pop();
+    }
+    if tokens.is_empty() {
+        None
+    } else {
+        Some(tokens.join(" "))
+    }
+}
+
+fn is_operator(token: &str) -> bool {
+    matches!(token, "AND" | "OR" | "NOT")
+}
+
+/// 按标记拆分摘要，同时去掉分词时插入的分隔符
+fn parse_snippet(snippet: &str) -> Vec<SnippetVO> {
+    let mut result: Vec<SnippetVO> = Vec::new();
+    let mut matched = false;
+    let mut text = String::new();
+    for c in snippet.chars() {
+        match c {
+            MARK_START | MARK_END => {
+                push_snippet(&mut result, std::mem::take(&mut text), matched);
+                matched = c == MARK_START;
+            }
+            SEPARATOR => {}
+            _ => text.push(c),
+        }
+    }
+    push_snippet(&mut result, text, matched);
+    result
+}
+
+/// 相邻的同类片段合并，逐字匹配的中文显示为一个整体
+fn push_snippet(result: &mut Vec<SnippetVO>, text: String, matched: bool) {
+    if text.is_empty() {
+        return;
+    }
+    match result.last_mut() {
+        Some(last) if last.matched == matched => last.text.push_str(&text),
+        _ => result.push(SnippetVO { text, matched }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::entity::search::{index, parse_snippet, search, segment, to_match, SEPARATOR};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+
+    #[test]
+    fn test_to_match() {
+        assert_eq!(to_match("img"), Some(String::from("\"img\"*")));
+        assert_eq!(
+            to_match("\"sunset beach\" OR -draft"),
+            Some(String::from("\"sunset beach\" NOT \"draft\"*"))
+        );
+        assert_eq!(
+            to_match("AND cat OR dog OR"),
+            Some(String::from("\"cat\"* OR \"dog\"*"))
+        );
+        assert_eq!(to_match("\"a\"\"b\""), Some(String::from("\"a\" \"b\"")));
+        assert_eq!(to_match("  -x ** ()"), None);
+        assert_eq!(segment("风景a"), format!("风{SEPARATOR}景{SEPARATOR}a"));
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let session = temp_session("search").await;
+        migrate(&session).await.unwrap();
+        let files = [
+            (1, "IMG_2034", "海边日落", "beach,sunset"),
+            (2, "IMG_2035", "", "city"),
+            (3, "海边风景", "", ""),
+        ];
+        for (id, name, exegesis, tags) in files {
+            session
+                .sql("INSERT INTO metadata (id, file_name, file_path, exegesis, tags) VALUES (?, ?, '/photos/', ?, ?)")
+                .bind(id)
+                .bind(name)
+                .bind(exegesis)
+                .bind(tags)
+                .execute()
+                .await
+                .unwrap();
+        }
+        session
+            .sql("UPDATE metadata SET title = ? WHERE id = ?")
+            .bind("灯塔")
+            .bind(2)
+            .execute()
+            .await
+            .unwrap();
+        let mut tx = session.begin().await.unwrap();
+        index(tx.connection(), &[1, 2, 3]).await.unwrap();
+        tx.commit().await.unwrap();
+
+        assert_eq!(search(&session, "img", None).await.total, 2);
+        assert_eq!(search(&session, "img -city", None).await.total, 1);
+        let result = search(&session, "海边", None).await;
+        assert_eq!(result.total, 2);
+        // 文件名的权重高于备注
+        assert_eq!(result.items[0].metadata.file_name, "海边风景");
+        let snippet = &result.items[0].snippet;
+        assert!(snippet.iter().any(|v| v.matched && v.text == "海边"));
+        assert_eq!(search(&session, "sunset OR city", None).await.total, 2);
+        assert_eq!(search(&session, "灯塔", None).await.items[0].metadata.id, "2");
+        assert_eq!(search(&session, "\"日落海边\"", None).await.total, 0);
+    }
+
+    #[test]
+    fn test_parse_snippet() {
+        let snippet = parse_snippet("a\u{E000}风\u{E001}\u{200B}\u{E000}景\u{E001}b");
+        assert_eq!(snippet.len(), 3);
+        assert_eq!(snippet[1].text, "风景");
+        assert!(snippet[1].matched);
+    }
+}
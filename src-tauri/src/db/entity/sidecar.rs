@@ -0,0 +1,174 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::entity::annotation::Annotation;
+use crate::db::entity::metadata::Metadata;
+use crate::db::entity::tag::add_tags;
+use crate::db::sqlite::{Session, Transaction};
+use crate::file::sidecar::{self, sidecar_path, Sidecar, XmpData};
+use crate::util::error::ErrorHandle;
+use crate::{info, Result};
+
+const SAVE: &str = "INSERT OR REPLACE INTO sidecar (metadata_id, path, modified) VALUES (?, ?, ?)";
+
+/// 上次与目录同步时sidecar的路径和修改时间，与`metadata.id`一一对应
+///
+/// sidecar的修改时间与记录不一致时说明被其他软件修改过
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct SidecarState {
+    pub metadata_id: i64,
+    pub path: String,
+    pub modified: String,
+}
+
+impl SidecarState {
+    pub async fn get(session: &Session, metadata_id: i64) -> Option<Self> {
+        session
+            .sql("SELECT * FROM sidecar WHERE metadata_id = ?")
+            .bind(metadata_id)
+            .select_optional_as::<Self>()
+            .await
+            .print_error()
+            .flatten()
+    }
+
+    /// sidecar在上次同步后没有变化
+    pub fn synced(&self, path: &str, modified: &str) -> bool {
+        self.path == path && self.modified == modified
+    }
+}
+
+/// 在事务中导入sidecar中的标注，sidecar在上次同步后没有变化时跳过，返回是否导入
+///
+/// sidecar中有的项覆盖目录中的值，关键词追加为标签，目录中已有的标签不会删除
+pub async fn import(tx: &mut Transaction, metadata_id: i64, sidecar: &Sidecar) -> Result<bool> {
+    let state = tx
+        .sql("SELECT * FROM sidecar WHERE metadata_id = ?")
+        .bind(metadata_id)
+        .select_optional_as::<SidecarState>()
+        .await?;
+    if state.is_some_and(|v| v.synced(&sidecar.path, &sidecar.modified)) {
+        return Ok(false);
+    }
+    let data = &sidecar.data;
+    let annotation = Annotation {
+        score: data.rating.map(|v| v as f32),
+        label: data.label.clone(),
+        title: data.title.clone(),
+        note: data.description.clone(),
+        source_url: data.source.clone(),
+    };
+    annotation.update(tx, &[metadata_id]).await?;
+    if !data.keywords.is_empty() {
+        add_tags(tx.connection(), metadata_id, &data.keywords).await?;
+    }
+    tx.sql(SAVE)
+        .bind(metadata_id)
+        .bind(&sidecar.path)
+        .bind(&sidecar.modified)
+        .execute()
+        .await?;
+    Ok(true)
+}
+
+/// 把文件的标注写入sidecar，返回在上次同步后被其他软件修改过、没有写入的sidecar
+///
+/// 这些sidecar需要重新扫描导入后再修改，避免覆盖其他软件中的修改
+pub async fn write_back(session: &Session, list: &[Metadata]) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    for metadata in list {
+        let path = sidecar_path(Path::new(&metadata.full_path));
+        let full_path = path.to_string_lossy().to_string();
+        if path.exists() {
+            let modified = sidecar::modified(&path).print_error().unwrap_or_default();
+            let state = SidecarState::get(session, metadata.id).await;
+            if !state.is_some_and(|v| v.synced(&full_path, &modified)) {
+                info!("sidecar已被其他软件修改，跳过写入：{full_path}");
+                conflicts.push(full_path);
+                continue;
+            }
+        }
+        if let Some(Some(modified)) = sidecar::write(&path, &XmpData::from(metadata)).print_error()
+        {
+            session
+                .sql(SAVE)
+                .bind(metadata.id)
+                .bind(&full_path)
+                .bind(&modified)
+                .execute()
+                .await
+                .print_error();
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::db::entity::metadata::Metadata;
+    use crate::db::entity::sidecar::{import, write_back};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+    use crate::file::sidecar::{parse, read};
+
+    #[tokio::test]
+    async fn test_sidecar() {
+        let dir = std::env::temp_dir().join("pixel-basket-sidecar");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("1.jpg");
+        fs::write(&file, b"image").unwrap();
+        fs::write(
+            dir.join("1.xmp"),
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description rdf:about="" xmp:Rating="2" xmp:Label="Blue">
+            <dc:subject><rdf:Bag><rdf:li>beach</rdf:li></rdf:Bag></dc:subject>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .unwrap();
+
+        let session = temp_session("sidecar").await;
+        migrate(&session).await.unwrap();
+        session
+            .sql("INSERT INTO metadata (id, full_path, exegesis) VALUES (?, ?, ?)")
+            .bind(1)
+            .bind(file.to_str().unwrap())
+            .bind("note")
+            .execute()
+            .await
+            .unwrap();
+        let sidecar = read(&file).unwrap().unwrap();
+        let mut tx = session.begin().await.unwrap();
+        assert!(import(&mut tx, 1, &sidecar).await.unwrap());
+        // 没有变化时不重复导入
+        assert!(!import(&mut tx, 1, &sidecar).await.unwrap());
+        tx.commit().await.unwrap();
+        let metadata = Metadata::list_by_ids(&session, &[1]).await.remove(0);
+        assert_eq!(metadata.score, 2.0);
+        assert_eq!(metadata.label, "blue");
+        assert_eq!(metadata.tags, "beach");
+        // sidecar中没有的项保持不变
+        assert_eq!(metadata.exegesis, "note");
+
+        // 写入已导入的file.xmp
+        assert!(write_back(&session, &[metadata]).await.is_empty());
+        let data = parse(&fs::read_to_string(dir.join("1.xmp")).unwrap()).unwrap();
+        assert_eq!(data.description.as_deref(), Some("note"));
+        assert!(!dir.join("1.jpg.xmp").exists());
+
+        // 其他软件修改后不覆盖
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(
+            dir.join("1.xmp"),
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description xmp:Rating="5"/></rdf:RDF></x:xmpmeta>"#,
+        )
+        .unwrap();
+        let metadata = Metadata::list_by_ids(&session, &[1]).await;
+        assert_eq!(write_back(&session, &metadata).await.len(), 1);
+        let data = parse(&fs::read_to_string(dir.join("1.xmp")).unwrap()).unwrap();
+        assert_eq!(data.rating, Some(5));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,773 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+use crate::config::get_config;
+use crate::db::entity::metadata::{Metadata, NOT_DELETED};
+use crate::db::entity::search::index;
+use crate::db::entity::sidecar::write_back;
+use crate::db::sqlite::{like_prefix, placeholders, Arg, Session, QUERY_CHUNK};
+use crate::util::error::ErrorHandle;
+use crate::util::snowflake::id;
+use crate::{info, Result};
+
+/// 层级分隔符，如`people/alice`
+pub const SEPARATOR: char = '/';
+const MATCH_PATH: &str = "t.path = ? OR t.path LIKE ? ESCAPE '\\'";
+
+/// 标签，`path`为包含所有上级的完整名称，不区分大小写
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct Tag {
+    pub id: i64,
+    pub pid: i64,
+    pub name: String,
+    pub path: String,
+    pub color: String,
+    pub created: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TagVO {
+    pub id: String,
+    pub pid: String,
+    pub name: String,
+    pub path: String,
+    pub color: String,
+    pub aliases: Vec<String>,
+    /// 直接使用该标签的文件数，不包括子标签
+    pub count: i64,
+}
+
+/// 修改标签，未设置的项保持不变
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TagData {
+    /// 新名称，不能包含层级分隔符
+    pub name: Option<String>,
+    pub color: Option<String>,
+    /// 替换原来的全部别名
+    pub aliases: Option<Vec<String>>,
+}
+
+/// 按标签筛选，标签可以使用完整名称或别名，同时匹配子标签
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TagFilter {
+    /// 需要同时包含的标签
+    pub all: Vec<String>,
+    /// 包含任意一个即可
+    pub any: Vec<String>,
+    /// 不能包含的标签
+    pub none: Vec<String>,
+}
+
+impl TagVO {
+    fn from(tag: Tag, aliases: Vec<String>, count: i64) -> Self {
+        Self {
+            id: tag.id.to_string(),
+            pid: tag.pid.to_string(),
+            name: tag.name,
+            path: tag.path,
+            color: tag.color,
+            aliases,
+            count,
+        }
+    }
+}
+
+/// 去掉多余的空白和分隔符，为空时返回`None`
+pub fn normalize(path: &str) -> Option<String> {
+    let path = path
+        .split(SEPARATOR)
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<&str>>()
+        .join("/");
+    (!path.is_empty()).then_some(path)
+}
+
+/// 文件包含满足`matches`的标签，`matches`中标签表的别名为`t`
+fn tagged_sql(matches: &str) -> String {
+    format!("EXISTS (SELECT 1 FROM metadata_tag mt JOIN tag t ON t.id = mt.tag_id WHERE mt.metadata_id = m.id AND ({matches}))")
+}
+
+fn now() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+impl Tag {
+    /// 所有标签，按完整名称排序
+    pub async fn list(session: &Session) -> Vec<TagVO> {
+        let tags = session
+            .sql("SELECT * FROM tag ORDER BY path")
+            .select_as::<Tag>()
+            .await
+            .print_error()
+            .unwrap_or_default();
+        let mut aliases: HashMap<i64, Vec<String>> = HashMap::new();
+        for (tag_id, alias) in session
+            .sql("SELECT tag_id, alias FROM tag_alias ORDER BY alias")
+            .select_as::<(i64, String)>()
+            .await
+            .print_error()
+            .unwrap_or_default()
+        {
+            aliases.entry(tag_id).or_default().push(alias);
+        }
+        let counts: HashMap<i64, i64> = session
+            .sql("SELECT mt.tag_id, COUNT(*) FROM metadata_tag mt JOIN metadata m ON m.id = mt.metadata_id WHERE m.is_del = ? GROUP BY mt.tag_id")
+            .bind(NOT_DELETED)
+            .select_as::<(i64, i64)>()
+            .await
+            .print_error()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        tags.into_iter()
+            .map(|v| {
+                let aliases = aliases.remove(&v.id).unwrap_or_default();
+                let count = counts.get(&v.id).copied().unwrap_or(0);
+                TagVO::from(v, aliases, count)
+            })
+            .collect()
+    }
+
+    pub async fn get(session: &Session, id: i64) -> Option<TagVO> {
+        let tag = session
+            .sql("SELECT * FROM tag WHERE id = ?")
+            .bind(id)
+            .select_optional_as::<Tag>()
+            .await
+            .print_error()??;
+        let aliases = session
+            .sql("SELECT alias FROM tag_alias WHERE tag_id = ? ORDER BY alias")
+            .bind(id)
+            .select_as::<(String,)>()
+            .await
+            .print_error()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| v.0)
+            .collect();
+        let count = session
+            .sql("SELECT COUNT(*) AS count FROM metadata_tag mt JOIN metadata m ON m.id = mt.metadata_id WHERE mt.tag_id = ? AND m.is_del = ?")
+            .bind(id)
+            .bind(NOT_DELETED)
+            .count()
+            .await
+            .print_error()
+            .map_or(0, |v| v.count);
+        Some(TagVO::from(tag, aliases, count))
+    }
+
+    /// 创建标签，缺少的上级标签一并创建，已存在时直接返回
+    pub async fn create(session: &Session, path: &str, color: Option<String>) -> Option<TagVO> {
+        let mut tx = session.begin().await.print_error()?;
+        let tag = find_or_create(tx.connection(), path)
+            .await
+            .print_error()??;
+        if let Some(color) = color {
+            set_color(tx.connection(), tag.id, &color)
+                .await
+                .print_error()?;
+        }
+        tx.commit().await.print_error()?;
+        Self::get(session, tag.id).await
+    }
+
+    /// 修改名称、颜色和别名，与同级标签重名时不修改，需要合并
+    pub async fn update(session: &Session, id: i64, data: &TagData) -> Option<TagVO> {
+        let mut tx = session.begin().await.print_error()?;
+        let conn = tx.connection();
+        let tag = get_by_id(conn, id).await.print_error()??;
+        let mut affected = Vec::new();
+        if let Some(name) = &data.name {
+            affected = rename(conn, &tag, name).await.print_error()??;
+            sync(conn, &affected).await.print_error()?;
+        }
+        if let Some(color) = &data.color {
+            set_color(conn, id, color).await.print_error()?;
+        }
+        if let Some(aliases) = &data.aliases {
+            set_aliases(conn, id, aliases).await.print_error()?;
+        }
+        tx.commit().await.print_error()?;
+        write_tags(session, &affected).await;
+        Self::get(session, id).await
+    }
+
+    /// 把`sources`合并到`target`，包括文件、别名和子标签，原名称保留为别名
+    ///
+    /// 返回被其他软件修改过、没有写入的sidecar
+    pub async fn merge(session: &Session, sources: &[i64], target: i64) -> Result<Vec<String>> {
+        let mut tx = session.begin().await?;
+        let conn = tx.connection();
+        let mut affected = Vec::new();
+        for source in sources.iter().filter(|v| **v != target) {
+            affected.extend(merge(conn, *source, target).await?);
+        }
+        sync(conn, &affected).await?;
+        tx.commit().await?;
+        Ok(write_tags(session, &affected).await)
+    }
+
+    /// 删除标签和所有子标签
+    ///
+    /// 返回被其他软件修改过、没有写入的sidecar
+    pub async fn delete(session: &Session, ids: &[i64]) -> Result<Vec<String>> {
+        let mut tx = session.begin().await?;
+        let conn = tx.connection();
+        let mut affected = Vec::new();
+        for id in ids {
+            affected.extend(delete(conn, *id).await?);
+        }
+        sync(conn, &affected).await?;
+        tx.commit().await?;
+        Ok(write_tags(session, &affected).await)
+    }
+
+    /// 批量添加和移除文件的标签，添加的标签不存在时自动创建
+    ///
+    /// 返回被其他软件修改过、没有写入的sidecar
+    pub async fn assign(
+        session: &Session,
+        metadata_ids: &[i64],
+        add: &[String],
+        remove: &[String],
+    ) -> Result<Vec<String>> {
+        let mut tx = session.begin().await?;
+        let conn = tx.connection();
+        assign(conn, metadata_ids, add, remove).await?;
+        sync(conn, metadata_ids).await?;
+        tx.commit().await?;
+        Ok(write_tags(session, metadata_ids).await)
+    }
+
+    async fn insert(&self, conn: &mut SqliteConnection) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT INTO tag (id, pid, name, path, color, created) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(self.id)
+        .bind(self.pid)
+        .bind(&self.name)
+        .bind(&self.path)
+        .bind(&self.color)
+        .bind(&self.created)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+impl TagFilter {
+    /// 生成筛选条件，表别名为`m`，找不到的标签在`all`中表示没有结果，在其他项中忽略
+    pub async fn conditions(&self, session: &Session) -> Vec<(String, Vec<Arg>)> {
+        let mut conditions = Vec::new();
+        if self.all.is_empty() && self.any.is_empty() && self.none.is_empty() {
+            return conditions;
+        }
+        let Some(pool) = session.as_pool().print_error() else {
+            return conditions;
+        };
+        let Some(mut conn) = pool.acquire().await.print_error() else {
+            return conditions;
+        };
+        let mut resolve = Vec::new();
+        for names in [&self.all, &self.any, &self.none] {
+            let mut paths = Vec::new();
+            for name in names {
+                paths.push(
+                    find(&mut conn, name)
+                        .await
+                        .print_error()
+                        .flatten()
+                        .map(|v| v.path),
+                );
+            }
+            resolve.push(paths);
+        }
+        let matches = |paths: &[&String]| {
+            let sql = vec![format!("({MATCH_PATH})"); paths.len()].join(" OR ");
+            let args = paths
+                .iter()
+                .flat_map(|v| [Arg::from(*v), Arg::from(like_prefix(&format!("{v}/")))])
+                .collect();
+            (tagged_sql(&sql), args)
+        };
+        for path in resolve[0].iter() {
+            match path {
+                Some(path) => conditions.push(matches(&[path])),
+                None => conditions.push((String::from("0"), Vec::new())),
+            }
+        }
+        let any: Vec<&String> = resolve[1].iter().flatten().collect();
+        if !any.is_empty() {
+            conditions.push(matches(&any));
+        } else if !self.any.is_empty() {
+            conditions.push((String::from("0"), Vec::new()));
+        }
+        for path in resolve[2].iter().flatten() {
+            let (sql, args) = matches(&[path]);
+            conditions.push((format!("NOT {sql}"), args));
+        }
+        conditions
+    }
+}
+
+/// 标签作为关键词写入sidecar，返回被其他软件修改过、没有写入的sidecar
+async fn write_tags(session: &Session, metadata_ids: &[i64]) -> Vec<String> {
+    if metadata_ids.is_empty() || !get_config().sidecar.writable() {
+        return Vec::new();
+    }
+    write_back(session, &Metadata::list_by_ids(session, metadata_ids).await).await
+}
+
+async fn get_by_id(conn: &mut SqliteConnection, id: i64) -> sqlx::Result<Option<Tag>> {
+    sqlx::query_as("SELECT * FROM tag WHERE id = ?")
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+}
+
+async fn get_by_path(conn: &mut SqliteConnection, path: &str) -> sqlx::Result<Option<Tag>> {
+    sqlx::query_as("SELECT * FROM tag WHERE path = ?")
+        .bind(path)
+        .fetch_optional(conn)
+        .await
+}
+
+/// 按完整名称或别名查找
+async fn find(conn: &mut SqliteConnection, name: &str) -> sqlx::Result<Option<Tag>> {
+    let Some(path) = normalize(name) else {
+        return Ok(None);
+    };
+    if let Some(tag) = get_by_path(conn, &path).await? {
+        return Ok(Some(tag));
+    }
+    sqlx::query_as("SELECT t.* FROM tag t JOIN tag_alias a ON a.tag_id = t.id WHERE a.alias = ?")
+        .bind(&path)
+        .fetch_optional(conn)
+        .await
+}
+
+async fn find_or_create(conn: &mut SqliteConnection, name: &str) -> sqlx::Result<Option<Tag>> {
+    if let Some(tag) = find(conn, name).await? {
+        return Ok(Some(tag));
+    }
+    let Some(path) = normalize(name) else {
+        return Ok(None);
+    };
+    let mut parent: Option<Tag> = None;
+    for name in path.split(SEPARATOR) {
+        let current = match &parent {
+            Some(parent) => format!("{}{SEPARATOR}{name}", parent.path),
+            None => name.to_string(),
+        };
+        // 上级可以使用别名
+        let tag = match find(conn, &current).await? {
+            Some(tag) => tag,
+            None => {
+                let tag = Tag {
+                    id: id(),
+                    pid: parent.as_ref().map_or(0, |v| v.id),
+                    name: name.to_string(),
+                    path: current,
+                    color: String::new(),
+                    created: now(),
+                };
+                tag.insert(conn).await?;
+                tag
+            }
+        };
+        parent = Some(tag);
+    }
+    Ok(parent)
+}
+
+/// 标签和所有子标签的id
+async fn subtree(conn: &mut SqliteConnection, tag: &Tag) -> sqlx::Result<Vec<i64>> {
+    sqlx::query_scalar("SELECT id FROM tag WHERE path = ? OR path LIKE ? ESCAPE '\\'")
+        .bind(&tag.path)
+        .bind(like_prefix(&format!("{}{SEPARATOR}", tag.path)))
+        .fetch_all(conn)
+        .await
+}
+
+/// 使用这些标签的文件
+async fn tagged(conn: &mut SqliteConnection, tag_ids: &[i64]) -> sqlx::Result<Vec<i64>> {
+    let mut ids = Vec::new();
+    for chunk in tag_ids.chunks(QUERY_CHUNK) {
+        let sql = format!(
+            "SELECT DISTINCT metadata_id FROM metadata_tag WHERE tag_id IN ({})",
+            placeholders(chunk.len())
+        );
+        let mut query = sqlx::query_scalar::<_, i64>(&sql);
+        for id in chunk {
+            query = query.bind(id);
+        }
+        ids.extend(query.fetch_all(&mut *conn).await?);
+    }
+    Ok(ids)
+}
+
+/// 修改标签和所有子标签的完整名称
+async fn move_to(conn: &mut SqliteConnection, tag: &Tag, pid: i64, path: &str) -> sqlx::Result<()> {
+    sqlx::query("UPDATE tag SET pid = ?, path = ? WHERE id = ?")
+        .bind(pid)
+        .bind(path)
+        .bind(tag.id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("UPDATE tag SET path = ? || substr(path, ?) WHERE path LIKE ? ESCAPE '\\'")
+        .bind(path)
+        .bind(tag.path.chars().count() as i64 + 1)
+        .bind(like_prefix(&format!("{}{SEPARATOR}", tag.path)))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// 重命名，返回受影响的文件，重名时返回`None`
+async fn rename(
+    conn: &mut SqliteConnection,
+    tag: &Tag,
+    name: &str,
+) -> sqlx::Result<Option<Vec<i64>>> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(SEPARATOR) {
+        return Ok(None);
+    }
+    let path = match tag.path.rsplit_once(SEPARATOR) {
+        Some((parent, _)) => format!("{parent}{SEPARATOR}{name}"),
+        None => name.to_string(),
+    };
+    if let Some(other) = get_by_path(conn, &path).await? {
+        if other.id != tag.id {
+            return Ok(None);
+        }
+    }
+    let ids = subtree(conn, tag).await?;
+    let affected = tagged(conn, &ids).await?;
+    sqlx::query("UPDATE tag SET name = ? WHERE id = ?")
+        .bind(name)
+        .bind(tag.id)
+        .execute(&mut *conn)
+        .await?;
+    move_to(conn, tag, tag.pid, &path).await?;
+    Ok(Some(affected))
+}
+
+async fn set_color(conn: &mut SqliteConnection, id: i64, color: &str) -> sqlx::Result<()> {
+    sqlx::query("UPDATE tag SET color = ? WHERE id = ?")
+        .bind(color.trim())
+        .bind(id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// 替换别名，别名已属于其他标签时改为属于当前标签
+async fn set_aliases(conn: &mut SqliteConnection, id: i64, aliases: &[String]) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM tag_alias WHERE tag_id = ?")
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    for alias in aliases.iter().filter_map(|v| normalize(v)) {
+        sqlx::query("INSERT OR REPLACE INTO tag_alias (alias, tag_id) VALUES (?, ?)")
+            .bind(alias)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// 合并标签，同名的子标签继续合并，返回受影响的文件，不能合并到自己的子标签
+async fn merge(conn: &mut SqliteConnection, source: i64, target: i64) -> Result<Vec<i64>> {
+    let source = get_by_id(conn, source)
+        .await?
+        .ok_or_else(|| format!("标签不存在：{source}"))?;
+    let target = get_by_id(conn, target)
+        .await?
+        .ok_or_else(|| format!("标签不存在：{target}"))?;
+    if target
+        .path
+        .starts_with(&format!("{}{SEPARATOR}", source.path))
+    {
+        return Err(format!("不能合并到子标签：{}", target.path).into());
+    }
+    let ids = subtree(conn, &source).await?;
+    let affected = tagged(conn, &ids).await?;
+    let mut stack = vec![(source, target)];
+    while let Some((source, target)) = stack.pop() {
+        sqlx::query("INSERT OR IGNORE INTO metadata_tag (metadata_id, tag_id) SELECT metadata_id, ? FROM metadata_tag WHERE tag_id = ?")
+            .bind(target.id)
+            .bind(source.id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM metadata_tag WHERE tag_id = ?")
+            .bind(source.id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("UPDATE tag_alias SET tag_id = ? WHERE tag_id = ?")
+            .bind(target.id)
+            .bind(source.id)
+            .execute(&mut *conn)
+            .await?;
+        let children: Vec<Tag> = sqlx::query_as("SELECT * FROM tag WHERE pid = ?")
+            .bind(source.id)
+            .fetch_all(&mut *conn)
+            .await?;
+        for child in children {
+            let path = format!("{}{SEPARATOR}{}", target.path, child.name);
+            match get_by_path(conn, &path).await? {
+                Some(existing) => stack.push((child, existing)),
+                None => move_to(conn, &child, target.id, &path).await?,
+            }
+        }
+        sqlx::query("DELETE FROM tag WHERE id = ?")
+            .bind(source.id)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("INSERT OR IGNORE INTO tag_alias (alias, tag_id) VALUES (?, ?)")
+            .bind(&source.path)
+            .bind(target.id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(affected)
+}
+
+/// 删除标签和子标签，返回受影响的文件
+async fn delete(conn: &mut SqliteConnection, id: i64) -> Result<Vec<i64>> {
+    let tag = get_by_id(conn, id)
+        .await?
+        .ok_or_else(|| format!("标签不存在：{id}"))?;
+    let ids = subtree(conn, &tag).await?;
+    let affected = tagged(conn, &ids).await?;
+    for chunk in ids.chunks(QUERY_CHUNK) {
+        for table in [
+            "DELETE FROM metadata_tag WHERE tag_id",
+            "DELETE FROM tag_alias WHERE tag_id",
+            "DELETE FROM tag WHERE id",
+        ] {
+            let sql = format!("{table} IN ({})", placeholders(chunk.len()));
+            let mut query = sqlx::query(&sql);
+            for id in chunk {
+                query = query.bind(id);
+            }
+            query.execute(&mut *conn).await?;
+        }
+    }
+    Ok(affected)
+}
+
+async fn assign(
+    conn: &mut SqliteConnection,
+    metadata_ids: &[i64],
+    add: &[String],
+    remove: &[String],
+) -> sqlx::Result<()> {
+    for name in add {
+        let Some(tag) = find_or_create(conn, name).await? else {
+            continue;
+        };
+        for metadata_id in metadata_ids {
+            sqlx::query("INSERT OR IGNORE INTO metadata_tag (metadata_id, tag_id) VALUES (?, ?)")
+                .bind(metadata_id)
+                .bind(tag.id)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+    for name in remove {
+        let Some(tag) = find(conn, name).await? else {
+            continue;
+        };
+        for chunk in metadata_ids.chunks(QUERY_CHUNK) {
+            let sql = format!(
+                "DELETE FROM metadata_tag WHERE tag_id = ? AND metadata_id IN ({})",
+                placeholders(chunk.len())
+            );
+            let mut query = sqlx::query(&sql).bind(tag.id);
+            for id in chunk {
+                query = query.bind(id);
+            }
+            query.execute(&mut *conn).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 把标签的完整名称同步到`metadata.tags`并更新搜索索引
+async fn sync(conn: &mut SqliteConnection, metadata_ids: &[i64]) -> sqlx::Result<()> {
+    let mut ids = metadata_ids.to_vec();
+    ids.sort_unstable();
+    ids.dedup();
+    sync_column(conn, &ids).await?;
+    index(conn, &ids).await
+}
+
+/// 把标签的完整名称写入`metadata.tags`
+async fn sync_column(conn: &mut SqliteConnection, ids: &[i64]) -> sqlx::Result<()> {
+    for chunk in ids.chunks(QUERY_CHUNK) {
+        let sql = format!(
+            "SELECT mt.metadata_id, t.path FROM metadata_tag mt JOIN tag t ON t.id = mt.tag_id WHERE mt.metadata_id IN ({}) ORDER BY t.path",
+            placeholders(chunk.len())
+        );
+        let mut query = sqlx::query_as::<_, (i64, String)>(&sql);
+        for id in chunk {
+            query = query.bind(id);
+        }
+        let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+        for (metadata_id, path) in query.fetch_all(&mut *conn).await? {
+            tags.entry(metadata_id).or_default().push(path);
+        }
+        for id in chunk {
+            sqlx::query("UPDATE metadata SET tags = ? WHERE id = ?")
+                .bind(tags.remove(id).unwrap_or_default().join(","))
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// 在调用者的事务中给文件添加标签，不存在的标签自动创建
+pub async fn add_tags(
+    conn: &mut SqliteConnection,
+    metadata_id: i64,
+    names: &[String],
+) -> sqlx::Result<()> {
+    assign(conn, &[metadata_id], names, &[]).await?;
+    sync(conn, &[metadata_id]).await
+}
+
+/// 把`metadata.tags`中逗号分隔的旧标签转换为标签表
+///
+/// 迁移时只能访问版本13已有的表和列，不更新搜索索引，索引由版本17的迁移重建
+pub fn migrate_tags(
+    conn: &mut SqliteConnection,
+) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+    Box::pin(async move {
+        let rows: Vec<(i64, String)> =
+            sqlx::query_as("SELECT id, tags FROM metadata WHERE tags != ''")
+                .fetch_all(&mut *conn)
+                .await?;
+        let ids: Vec<i64> = rows.iter().map(|v| v.0).collect();
+        for (id, tags) in rows {
+            let names: Vec<String> = tags.split(',').map(String::from).collect();
+            assign(conn, &[id], &names, &[]).await?;
+        }
+        sync_column(conn, &ids).await?;
+        info!("已迁移 {} 个文件的标签", ids.len());
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::entity::tag::{normalize, Tag, TagData};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+
+    #[tokio::test]
+    async fn test_tag() {
+        assert_eq!(
+            normalize(" people / alice/"),
+            Some(String::from("people/alice"))
+        );
+        assert_eq!(normalize(" / "), None);
+
+        let session = temp_session("tag").await;
+        migrate(&session).await.unwrap();
+        for id in 1..=3 {
+            session
+                .sql("INSERT INTO metadata (id) VALUES (?)")
+                .bind(id)
+                .execute()
+                .await
+                .unwrap();
+        }
+        let alice = Tag::create(&session, "people/alice", Some(String::from("#ff0000")))
+            .await
+            .unwrap();
+        assert_eq!(alice.color, "#ff0000");
+        let people = Tag::list(&session).await;
+        assert_eq!(people[0].path, "people");
+        assert_eq!(alice.pid, people[0].id);
+
+        let add = vec![String::from("People/Alice"), String::from("people/bob")];
+        Tag::assign(&session, &[1, 2], &add, &[]).await.unwrap();
+        Tag::assign(&session, &[2], &[], &[String::from("people/bob")])
+            .await
+            .unwrap();
+        let tags: Vec<String> = session
+            .sql("SELECT tags FROM metadata ORDER BY id")
+            .select_as::<(String,)>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| v.0)
+            .collect();
+        assert_eq!(tags, vec!["people/alice,people/bob", "people/alice", ""]);
+
+        // 重命名上级标签时子标签一起修改
+        let people_id = people[0].id.parse().unwrap();
+        let data = TagData {
+            name: Some(String::from("person")),
+            aliases: Some(vec![String::from("人物")]),
+            ..TagData::default()
+        };
+        let person = Tag::update(&session, people_id, &data).await.unwrap();
+        assert_eq!(person.aliases, vec!["人物"]);
+        let tags = Tag::list(&session).await;
+        assert!(tags
+            .iter()
+            .any(|v| v.path == "person/alice" && v.count == 2));
+
+        // 合并后原名称成为别名，同名子标签合并
+        Tag::assign(&session, &[3], &[String::from("team/alice")], &[])
+            .await
+            .unwrap();
+        let team = Tag::list(&session)
+            .await
+            .into_iter()
+            .find(|v| v.path == "team")
+            .unwrap();
+        Tag::merge(&session, &[team.id.parse().unwrap()], people_id)
+            .await
+            .unwrap();
+        let tags = Tag::list(&session).await;
+        let alice = tags.iter().find(|v| v.path == "person/alice").unwrap();
+        assert_eq!(alice.count, 3);
+        assert!(tags.iter().all(|v| !v.path.starts_with("team")));
+        Tag::assign(&session, &[3], &[String::from("人物/bob")], &[])
+            .await
+            .unwrap();
+        assert!(!Tag::list(&session)
+            .await
+            .iter()
+            .any(|v| v.path.starts_with("人物")));
+
+        // 标签不存在和不能合并到子标签时返回错误
+        let alice_id = alice.id.parse().unwrap();
+        assert!(Tag::merge(&session, &[people_id], alice_id).await.is_err());
+        assert!(Tag::merge(&session, &[0], people_id).await.is_err());
+        assert!(Tag::delete(&session, &[0]).await.is_err());
+        assert_eq!(Tag::get(&session, alice_id).await.unwrap().count, 3);
+
+        Tag::delete(&session, &[people_id]).await.unwrap();
+        assert!(Tag::list(&session).await.is_empty());
+        let result = session
+            .count("SELECT COUNT(*) AS count FROM metadata WHERE tags != ''")
+            .await
+            .unwrap();
+        assert_eq!(result.count, 0);
+    }
+}
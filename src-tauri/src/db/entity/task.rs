@@ -1,52 +1,304 @@
+use chrono::Local;
 use serde::{Deserialize, Serialize};
 use tokio::task::JoinHandle;
 
+use crate::config::get_config;
+use crate::db::sqlite::{placeholders, Session, Statement, Transaction, QUERY_CHUNK};
+use crate::db::writer::writer;
 use crate::util::error::ErrorHandle;
 
-#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+/// 等待执行
+pub const PENDING: u8 = 0;
+/// 多次重试后仍然失败，不再自动执行
+pub const FAILED: u8 = 1;
+
+const FAIL: &str = "UPDATE task SET status = ?, attempts = ?, last_error = ?, last_attempt = ?, next_attempt = ? WHERE id = ?";
+
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
 pub struct Task {
     pub id: i64,
     pub file_path: String,
     pub file_suffix: String,
     pub status: u8,
+    /// 已尝试次数
+    pub attempts: i64,
+    /// 最后一次失败的原因
+    pub last_error: String,
+    /// 最后一次尝试的时间
+    pub last_attempt: String,
+    /// 下次可以重试的时间戳（秒）
+    pub next_attempt: i64,
+    /// 生成任务时的文件大小和修改时间，变化后重新执行失败的任务
+    pub file_size: i64,
+    pub modified: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TaskVO {
+    pub id: String,
+    pub file_path: String,
+    pub file_suffix: String,
+    pub status: u8,
+    pub attempts: i64,
+    pub last_error: String,
+    pub last_attempt: String,
 }
 
 impl Task {
-    pub fn as_status(&self) -> TaskStatus {
-        TaskStatus {
-            id: self.id,
-            success: false,
-            handle: None,
+    /// 可以执行的任务数，重试时间未到的任务不计入
+    pub async fn count_pending(session: &Session) -> usize {
+        session
+            .sql("SELECT COUNT(*) AS count FROM task WHERE status = ? AND next_attempt <= ?")
+            .bind(PENDING)
+            .bind(Local::now().timestamp())
+            .count()
+            .await
+            .print_error()
+            .map_or(0, |v| v.count as usize)
+    }
+
+    /// 按id分页获取可以执行的任务，重试时间未到的任务跳过
+    pub async fn list_pending(session: &Session, after_id: i64, limit: i64) -> Vec<Task> {
+        session
+            .sql(
+                "SELECT * FROM task WHERE status = ? AND next_attempt <= ? AND id > ? ORDER BY id LIMIT ?",
+            )
+            .bind(PENDING)
+            .bind(Local::now().timestamp())
+            .bind(after_id)
+            .bind(limit)
+            .select_as::<Task>()
+            .await
+            .print_error()
+            .unwrap_or_default()
+    }
+
+    /// 获取指定文件可以执行的任务
+    pub async fn list_pending_by_paths(session: &Session, paths: &[String]) -> Vec<Task> {
+        let mut list = Vec::new();
+        for chunk in paths.chunks(QUERY_CHUNK) {
+            let sql = format!(
+                "SELECT * FROM task WHERE status = ? AND next_attempt <= ? AND file_path IN ({}) ORDER BY id",
+                placeholders(chunk.len())
+            );
+            list.extend(
+                session
+                    .sql(&sql)
+                    .bind(PENDING)
+                    .bind(Local::now().timestamp())
+                    .bind_all(chunk)
+                    .select_as::<Task>()
+                    .await
+                    .print_error()
+                    .unwrap_or_default(),
+            );
         }
+        list
+    }
+
+    pub async fn list_failed(session: &Session) -> Vec<Task> {
+        session
+            .sql("SELECT * FROM task WHERE status = ? ORDER BY last_attempt DESC")
+            .bind(FAILED)
+            .select_as::<Task>()
+            .await
+            .print_error()
+            .unwrap_or_default()
+    }
+
+    /// 任务完成，交给写入线程从队列中移除
+    ///
+    /// 元数据没有保存成功时写入线程按失败处理
+    pub async fn complete(&self) {
+        writer().complete_task(self.clone()).await;
+    }
+
+    /// 记录失败原因，按退避时间推迟下次执行，达到最大次数后标记为失败
+    pub async fn fail(&self, session: &Session, error: &str) {
+        self.bind_failure(session.sql(FAIL), error)
+            .execute()
+            .await
+            .print_error();
+    }
+
+    /// 在事务中记录失败，由写入线程调用
+    pub async fn fail_in(&self, tx: &mut Transaction, error: &str) -> sqlx::Result<()> {
+        self.bind_failure(tx.sql(FAIL), error).execute().await?;
+        Ok(())
+    }
+
+    fn bind_failure<'s>(&self, statement: Statement<'s>, error: &str) -> Statement<'s> {
+        let config = &get_config().task;
+        let attempts = self.attempts + 1;
+        let now = Local::now();
+        let status = if attempts >= config.max_attempts {
+            FAILED
+        } else {
+            PENDING
+        };
+        statement
+            .bind(status)
+            .bind(attempts)
+            .bind(error)
+            .bind(now.format("%Y-%m-%d %H:%M:%S").to_string())
+            .bind(now.timestamp() + config.retry_delay(attempts))
+            .bind(self.id)
+    }
+
+    /// 文件变化后重新执行任务，重试次数清零
+    pub async fn reset(session: &Session, file_path: &str, file_size: i64, modified: &str) {
+        session
+            .sql("UPDATE task SET status = ?, attempts = 0, next_attempt = 0, file_size = ?, modified = ? WHERE file_path = ?")
+            .bind(PENDING)
+            .bind(file_size)
+            .bind(modified)
+            .bind(file_path)
+            .execute()
+            .await
+            .print_error();
+    }
+
+    /// 手动重试任务，重试次数清零
+    pub async fn retry(session: &Session, ids: &[i64]) -> bool {
+        if ids.is_empty() {
+            return false;
+        }
+        session
+            .sql(&format!(
+                "UPDATE task SET status = ?, attempts = 0, next_attempt = 0 WHERE id IN ({})",
+                placeholders(ids.len())
+            ))
+            .bind(PENDING)
+            .bind_all(ids)
+            .execute()
+            .await
+            .print_error()
+            .is_some()
     }
 }
 
+impl TaskVO {
+    pub fn from(task: Task) -> Self {
+        Self {
+            id: task.id.to_string(),
+            file_path: task.file_path,
+            file_suffix: task.file_suffix,
+            status: task.status,
+            attempts: task.attempts,
+            last_error: task.last_error,
+            last_attempt: task.last_attempt,
+        }
+    }
+}
+
+/// 扫描结果，失败时为错误信息
+pub type ScanResult = Result<(), String>;
+
 pub struct TaskStatus {
     pub id: i64,
-    success: bool,
-    handle: Option<JoinHandle<bool>>,
+    handle: Option<JoinHandle<ScanResult>>,
 }
 
 impl TaskStatus {
     pub fn new(id: i64) -> Self {
-        Self {
-            id,
-            success: false,
-            handle: None,
-        }
+        Self { id, handle: None }
     }
-    pub fn handle(&mut self, handle: JoinHandle<bool>) {
+    pub fn handle(&mut self, handle: JoinHandle<ScanResult>) {
         self.handle = Some(handle);
     }
-    pub fn done(&mut self) {
-        self.success = true;
+    /// 等待扫描结果，扫描器不支持该文件时返回`None`
+    pub async fn result(self) -> Option<ScanResult> {
+        let handle = self.handle?;
+        Some(handle.await.unwrap_or_else(|e| Err(e.to_string())))
     }
-    pub async fn success(self) -> bool {
-        match self.handle {
-            Some(handle) => {
-                handle.await.print_error().is_some_and(|v| v)
-            }
-            None => self.success,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::Local;
+
+    use crate::db::entity::metadata::Metadata;
+    use crate::db::entity::task::{Task, FAILED, PENDING};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::{temp_session, Session};
+
+    async fn status(session: &Session) -> u8 {
+        session
+            .sql("SELECT * FROM task")
+            .select_one_as::<Task>()
+            .await
+            .unwrap()
+            .status
+    }
+
+    #[tokio::test]
+    async fn test_failed_task() {
+        let session = temp_session("failed_task").await;
+        migrate(&session).await.unwrap();
+        let file = std::env::temp_dir().join("pixel-basket-failed-task.jpg");
+        fs::write(&file, b"broken").unwrap();
+        let metadata = Metadata::load(&file);
+        metadata.save_task_to_db(&session).await;
+        session
+            .sql("UPDATE task SET status = ?, attempts = 5")
+            .bind(FAILED)
+            .execute()
+            .await
+            .unwrap();
+        // 文件没有变化时重新扫描不重置失败的任务
+        metadata.save_task_to_db(&session).await;
+        assert_eq!(status(&session).await, FAILED);
+
+        fs::write(&file, b"fixed image").unwrap();
+        metadata.save_task_to_db(&session).await;
+        assert_eq!(status(&session).await, PENDING);
+
+        // 手动重试
+        session
+            .sql("UPDATE task SET status = ?")
+            .bind(FAILED)
+            .execute()
+            .await
+            .unwrap();
+        let task = Task::list_failed(&session).await.remove(0);
+        assert!(Task::retry(&session, &[task.id]).await);
+        assert_eq!(status(&session).await, PENDING);
+        assert_eq!(Task::count_pending(&session).await, 1);
+        let _ = fs::remove_file(&file);
+    }
+
+    #[tokio::test]
+    async fn test_list_pending() {
+        let session = temp_session("pending_task").await;
+        migrate(&session).await.unwrap();
+        for id in 1..=4 {
+            session
+                .sql(
+                    "INSERT INTO task (id, file_path, file_suffix, status) VALUES (?, ?, 'jpg', ?)",
+                )
+                .bind(id)
+                .bind(format!("/data/{id}.jpg"))
+                .bind(if id == 4 { FAILED } else { PENDING })
+                .execute()
+                .await
+                .unwrap();
         }
+        // 重试时间未到的任务跳过
+        session
+            .sql("UPDATE task SET next_attempt = ? WHERE id = 2")
+            .bind(Local::now().timestamp() + 3600)
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(Task::count_pending(&session).await, 2);
+        let page = Task::list_pending(&session, 0, 1).await;
+        assert_eq!(page.iter().map(|v| v.id).collect::<Vec<i64>>(), vec![1]);
+        let page = Task::list_pending(&session, 1, 10).await;
+        assert_eq!(page.iter().map(|v| v.id).collect::<Vec<i64>>(), vec![3]);
+        let paths: Vec<String> = (1..=4).map(|id| format!("/data/{id}.jpg")).collect();
+        let tasks = Task::list_pending_by_paths(&session, &paths[2..]).await;
+        assert_eq!(tasks.iter().map(|v| v.id).collect::<Vec<i64>>(), vec![3]);
     }
 }
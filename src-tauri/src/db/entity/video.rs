@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::sqlite::{Session, Transaction};
+use crate::util::error::ErrorHandle;
+
+/// 视频信息，与`metadata.id`一一对应
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, sqlx::FromRow)]
+pub struct VideoInfo {
+    pub metadata_id: i64,
+    /// 封装格式，如`mov,mp4,m4a,3gp,3g2,mj2`
+    pub container: String,
+    pub video_codec: String,
+    /// 编码尺寸，未按旋转角度交换宽高
+    pub width: i64,
+    pub height: i64,
+    pub frame_rate: f64,
+    /// 总码率（bit/s）
+    pub bitrate: i64,
+    /// 播放时顺时针旋转的角度，0、90、180或270
+    pub rotation: i64,
+    /// 音轨，JSON数组
+    pub audio_tracks: String,
+    /// 拍摄或创建时间，格式为`%Y-%m-%d %H:%M:%S`
+    pub creation_time: String,
+    /// 章节，JSON数组
+    pub chapters: String,
+    /// 预览格式，见`file::preview`，没有预览时为空
+    pub preview_format: String,
+    /// 预览帧数
+    pub preview_frames: i64,
+}
+
+/// 音轨
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AudioTrack {
+    pub codec: String,
+    pub channels: i64,
+    pub sample_rate: i64,
+    pub language: String,
+}
+
+/// 章节，时间单位为毫秒
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    pub start: i64,
+    pub end: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VideoVO {
+    pub container: String,
+    pub video_codec: String,
+    pub width: i64,
+    pub height: i64,
+    pub frame_rate: f64,
+    pub bitrate: i64,
+    pub rotation: i64,
+    pub audio_tracks: Vec<AudioTrack>,
+    pub creation_time: String,
+    pub chapters: Vec<Chapter>,
+    pub preview_format: String,
+    pub preview_frames: i64,
+}
+
+/// 视频预览文件
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VideoPreviewVO {
+    pub path: String,
+    pub format: String,
+    pub frames: i64,
+}
+
+impl VideoInfo {
+    /// 在事务中保存，已存在时覆盖
+    pub async fn save(&self, tx: &mut Transaction, metadata_id: i64) -> sqlx::Result<()> {
+        tx.sql("INSERT OR REPLACE INTO video (metadata_id, container, video_codec, width, height, frame_rate, bitrate, rotation, audio_tracks, creation_time, chapters, preview_format, preview_frames) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(metadata_id)
+            .bind(&self.container)
+            .bind(&self.video_codec)
+            .bind(self.width)
+            .bind(self.height)
+            .bind(self.frame_rate)
+            .bind(self.bitrate)
+            .bind(self.rotation)
+            .bind(&self.audio_tracks)
+            .bind(&self.creation_time)
+            .bind(&self.chapters)
+            .bind(&self.preview_format)
+            .bind(self.preview_frames)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// 按`metadata.id`批量查询
+    pub async fn map_by_ids(session: &Session, ids: &[i64]) -> HashMap<i64, VideoInfo> {
+        session
+            .select_in::<VideoInfo>(ids, |v| {
+                format!("SELECT * FROM video WHERE metadata_id IN ({v})")
+            })
+            .await
+            .print_error()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| (v.metadata_id, v))
+            .collect()
+    }
+
+    pub fn set_audio_tracks(&mut self, tracks: &[AudioTrack]) {
+        self.audio_tracks = serde_json::to_string(tracks).unwrap_or_else(|_| String::from("[]"));
+    }
+
+    pub fn get_audio_tracks(&self) -> Vec<AudioTrack> {
+        serde_json::from_str(&self.audio_tracks).unwrap_or_default()
+    }
+
+    pub fn set_chapters(&mut self, chapters: &[Chapter]) {
+        self.chapters = serde_json::to_string(chapters).unwrap_or_else(|_| String::from("[]"));
+    }
+
+    pub fn get_chapters(&self) -> Vec<Chapter> {
+        serde_json::from_str(&self.chapters).unwrap_or_default()
+    }
+}
+
+impl VideoVO {
+    pub fn from(video: VideoInfo) -> Self {
+        let audio_tracks = video.get_audio_tracks();
+        let chapters = video.get_chapters();
+        Self {
+            container: video.container,
+            video_codec: video.video_codec,
+            width: video.width,
+            height: video.height,
+            frame_rate: video.frame_rate,
+            bitrate: video.bitrate,
+            rotation: video.rotation,
+            audio_tracks,
+            creation_time: video.creation_time,
+            chapters,
+            preview_format: video.preview_format,
+            preview_frames: video.preview_frames,
+        }
+    }
+}
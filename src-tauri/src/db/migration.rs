@@ -0,0 +1,421 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use chrono::Local;
+use sqlx::{query, query_as, query_scalar, Connection, SqliteConnection};
+
+use crate::db::entity::palette::backfill_palette;
+use crate::db::entity::search::rebuild_index;
+use crate::db::entity::tag::migrate_tags;
+use crate::db::sqlite::{Count, Session};
+use crate::{info, Result};
+
+/// 无法用SQL完成的数据迁移，与结构迁移在同一个事务中执行
+pub type DataMigration =
+    for<'a> fn(&'a mut SqliteConnection) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// 数据库结构迁移
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+    /// 在`sql`之前执行
+    pub before: Option<DataMigration>,
+    /// 在`sql`之后执行
+    pub after: Option<DataMigration>,
+    /// 完成后整理数据库文件，释放删除的数据占用的空间
+    pub vacuum: bool,
+}
+
+/// 所有迁移，按版本号升序排列，已发布的迁移不允许再修改
+///
+/// 数据迁移只能访问该版本已有的表和列，不能调用依赖最新表结构的代码
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "init",
+        sql: include_str!("migrations/0001_init.sql"),
+        before: Some(upgrade_baseline),
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 2,
+        name: "task_retry",
+        sql: include_str!("migrations/0002_task_retry.sql"),
+        before: None,
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 3,
+        name: "exif",
+        sql: include_str!("migrations/0003_exif.sql"),
+        before: None,
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 4,
+        name: "psd",
+        sql: include_str!("migrations/0004_psd.sql"),
+        before: None,
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 5,
+        name: "model",
+        sql: include_str!("migrations/0005_model.sql"),
+        before: None,
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 6,
+        name: "video",
+        sql: include_str!("migrations/0006_video.sql"),
+        before: None,
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 7,
+        name: "video_preview",
+        sql: include_str!("migrations/0007_video_preview.sql"),
+        before: None,
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 8,
+        name: "thumbnail_cache",
+        sql: include_str!("migrations/0008_thumbnail_cache.sql"),
+        before: None,
+        after: None,
+        vacuum: true,
+    },
+    Migration {
+        version: 9,
+        name: "search",
+        sql: include_str!("migrations/0009_search.sql"),
+        before: None,
+        // 索引包含后续版本的列，由版本17重建
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 10,
+        name: "palette",
+        sql: include_str!("migrations/0010_palette.sql"),
+        before: None,
+        after: Some(backfill_palette),
+        vacuum: false,
+    },
+    Migration {
+        version: 11,
+        name: "image_hash",
+        sql: include_str!("migrations/0011_image_hash.sql"),
+        before: None,
+        // 旧缩略图在迁移之后才移到缓存目录，启动后由`backfill_hash`计算
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 12,
+        name: "duplicate_log",
+        sql: include_str!("migrations/0012_duplicate_log.sql"),
+        before: None,
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 13,
+        name: "tag",
+        sql: include_str!("migrations/0013_tag.sql"),
+        before: None,
+        after: Some(migrate_tags),
+        vacuum: false,
+    },
+    Migration {
+        version: 14,
+        name: "annotation",
+        sql: include_str!("migrations/0014_annotation.sql"),
+        before: None,
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 15,
+        name: "sidecar",
+        sql: include_str!("migrations/0015_sidecar.sql"),
+        before: None,
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 16,
+        name: "task_stat",
+        sql: include_str!("migrations/0016_task_stat.sql"),
+        before: None,
+        after: None,
+        vacuum: false,
+    },
+    Migration {
+        version: 17,
+        name: "search_title",
+        sql: include_str!("migrations/0017_search_title.sql"),
+        before: None,
+        after: Some(rebuild_index),
+        vacuum: false,
+    },
+];
+
+/// 列名、类型和默认值
+type Column = (&'static str, &'static str, &'static str);
+
+/// 版本1各表的列，不包括主键`id`
+const BASELINE: &[(&str, &[Column])] = &[
+    (
+        "metadata",
+        &[
+            ("full_path", "TEXT", "''"),
+            ("file_path", "TEXT", "''"),
+            ("file_name", "TEXT", "''"),
+            ("file_size", "INTEGER", "0"),
+            ("file_suffix", "TEXT", "''"),
+            ("added", "TEXT", "''"),
+            ("created", "TEXT", "''"),
+            ("modified", "TEXT", "''"),
+            ("tags", "TEXT", "''"),
+            ("exegesis", "TEXT", "''"),
+            ("score", "REAL", "0"),
+            ("is_del", "INTEGER", "0"),
+            ("sha1", "TEXT", "''"),
+            ("image_width", "INTEGER", "0"),
+            ("image_height", "INTEGER", "0"),
+            ("thumbnail", "TEXT", "''"),
+            ("colors", "TEXT", "''"),
+            ("shape", "TEXT", "''"),
+            ("duration", "INTEGER", "0"),
+        ],
+    ),
+    (
+        "task",
+        &[
+            ("file_path", "TEXT", "''"),
+            ("file_suffix", "TEXT", "''"),
+            ("status", "INTEGER", "0"),
+        ],
+    ),
+    (
+        "folder",
+        &[
+            ("pid", "INTEGER", "0"),
+            ("name", "TEXT", "''"),
+            ("path", "TEXT", "''"),
+        ],
+    ),
+    ("basket", &[("name", "TEXT", "''")]),
+    (
+        "basket_folder",
+        &[("basket_id", "INTEGER", "0"), ("folder_id", "INTEGER", "0")],
+    ),
+];
+
+/// 没有版本记录的旧数据库中表已经存在，`CREATE TABLE IF NOT EXISTS`不会修改这些表，
+/// 先补齐版本1缺少的列
+fn upgrade_baseline(
+    conn: &mut SqliteConnection,
+) -> Pin<Box<dyn Future<Output = Result<()>> + Send + '_>> {
+    Box::pin(async move {
+        for (table, columns) in BASELINE {
+            let existing: Vec<String> =
+                query_scalar(&format!("SELECT name FROM pragma_table_info('{table}')"))
+                    .fetch_all(&mut *conn)
+                    .await?;
+            // 表不存在时由迁移创建
+            if existing.is_empty() {
+                continue;
+            }
+            for (column, kind, default) in columns.iter() {
+                // 旧的列允许为空，空值改为默认值
+                let sql = if existing.iter().any(|v| v.eq_ignore_ascii_case(column)) {
+                    format!("UPDATE {table} SET {column} = {default} WHERE {column} IS NULL")
+                } else {
+                    info!("旧数据库补齐列：{table}.{column}");
+                    format!(
+                        "ALTER TABLE {table} ADD COLUMN {column} {kind} NOT NULL DEFAULT {default}"
+                    )
+                };
+                query(&sql).execute(&mut *conn).await?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// 当前程序支持的最新数据库版本
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |v| v.version)
+}
+
+/// 获取数据库当前版本
+pub async fn current_version(conn: &mut SqliteConnection) -> Result<i64> {
+    query(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER PRIMARY KEY, name TEXT NOT NULL, applied TEXT NOT NULL)",
+    )
+    .execute(&mut *conn)
+    .await?;
+    let result = query_as::<_, Count>("SELECT IFNULL(MAX(version), 0) AS count FROM schema_version")
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(result.count)
+}
+
+/// 执行未应用的迁移
+///
+/// 数据库版本高于程序支持的版本时返回错误，避免旧程序写坏新数据库
+///
+/// ```rust,no_run
+/// # use pixel_basket::db::migration::migrate;
+/// # use pixel_basket::db::sqlite::Session;
+/// # async fn example() {
+/// let mut session = Session::new("test.db");
+/// session.connect().await;
+/// migrate(&session).await.expect("");
+/// # }
+/// ```
+pub async fn migrate(session: &Session) -> Result<i64> {
+    migrate_to(session, latest_version()).await
+}
+
+/// 执行到指定版本为止的迁移
+async fn migrate_to(session: &Session, target: i64) -> Result<i64> {
+    // 所有迁移在同一个连接上执行，其他连接不会缓存修改前的表结构
+    let mut conn = session.as_pool()?.acquire().await?;
+    let current = current_version(&mut conn).await?;
+    let latest = latest_version();
+    if current > latest {
+        return Err(format!("数据库版本({current})高于程序支持的版本({latest})，请升级程序").into());
+    }
+    let mut vacuum = false;
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|v| v.version > current && v.version <= target)
+    {
+        let mut tx = conn.begin().await?;
+        if let Some(before) = migration.before {
+            before(&mut tx).await?;
+        }
+        query(migration.sql).execute(&mut *tx).await?;
+        if let Some(after) = migration.after {
+            after(&mut tx).await?;
+        }
+        query("INSERT INTO schema_version (version, name, applied) VALUES (?, ?, ?)")
+            .bind(migration.version)
+            .bind(migration.name)
+            .bind(Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        info!(
+            "数据库迁移到版本 {}({})",
+            migration.version, migration.name
+        );
+        vacuum |= migration.vacuum;
+    }
+    // VACUUM不能在事务中执行
+    if vacuum {
+        query("VACUUM").execute(&mut *conn).await?;
+    }
+    Ok(target)
+}
+
+/// 连接数据库并执行迁移
+pub async fn migrate_db(url: &str) -> Result<i64> {
+    let mut session = Session::new(url);
+    session.connect().await;
+    migrate(&session).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::migration::{latest_version, migrate, migrate_to};
+    use crate::db::sqlite::temp_session;
+
+    #[tokio::test]
+    async fn test_migrate_fresh() {
+        let session = temp_session("migrate_fresh").await;
+        assert_eq!(migrate(&session).await.unwrap(), latest_version());
+        // 重复执行不会报错
+        assert_eq!(migrate(&session).await.unwrap(), latest_version());
+        for table in ["metadata", "task", "folder", "basket", "basket_folder"] {
+            let sql = format!("SELECT COUNT(*) AS count FROM {table}");
+            assert_eq!(session.count(&sql).await.unwrap().count, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrate_newer() {
+        let session = temp_session("migrate_newer").await;
+        migrate(&session).await.unwrap();
+        session
+            .execute("INSERT INTO schema_version (version, name, applied) VALUES (9999, 'future', '')")
+            .await
+            .unwrap();
+        assert!(migrate(&session).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_upgrade() {
+        let session = temp_session("migrate_upgrade").await;
+        assert_eq!(migrate_to(&session, 8).await.unwrap(), 8);
+        session
+            .execute("INSERT INTO metadata (id, file_name, tags, colors, sha1, image_width, image_height) VALUES (1, 'IMG_2034.jpg', 'people/alice,风景', '#ff0000,#00ff00', 'abc', 100, 100), (2, 'readme.psd', '', '', 'def', 0, 0)")
+            .await
+            .unwrap();
+        session
+            .execute("INSERT INTO exif (metadata_id, keywords) VALUES (1, '[\"sunset\"]')")
+            .await
+            .unwrap();
+        assert_eq!(migrate(&session).await.unwrap(), latest_version());
+        for (sql, expected) in [
+            ("SELECT COUNT(*) AS count FROM metadata_fts", 2),
+            ("SELECT COUNT(*) AS count FROM metadata_fts WHERE metadata_fts MATCH 'sunset'", 1),
+            ("SELECT COUNT(*) AS count FROM metadata_tag", 2),
+            ("SELECT COUNT(*) AS count FROM palette", 2),
+        ] {
+            assert_eq!(session.count(sql).await.unwrap().count, expected, "{sql}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrate_baseline() {
+        let session = temp_session("migrate_baseline").await;
+        // 没有版本记录、缺少部分列的旧数据库
+        session
+            .execute("CREATE TABLE metadata (id INTEGER PRIMARY KEY, full_path TEXT, file_name TEXT, tags TEXT, thumbnail TEXT)")
+            .await
+            .unwrap();
+        session
+            .execute("CREATE TABLE task (id INTEGER PRIMARY KEY, file_path TEXT)")
+            .await
+            .unwrap();
+        session
+            .execute("INSERT INTO metadata (id, full_path, file_name, thumbnail) VALUES (1, '/data/1.jpg', '1.jpg', '')")
+            .await
+            .unwrap();
+        assert_eq!(migrate(&session).await.unwrap(), latest_version());
+        let result = session
+            .count("SELECT COUNT(*) AS count FROM metadata WHERE sha1 = '' AND is_del = 0 AND tags = '' AND file_name = '1.jpg'")
+            .await
+            .unwrap();
+        assert_eq!(result.count, 1);
+        let result = session
+            .count("SELECT COUNT(*) AS count FROM metadata_fts WHERE metadata_fts MATCH '1'")
+            .await
+            .unwrap();
+        assert_eq!(result.count, 1);
+    }
+}
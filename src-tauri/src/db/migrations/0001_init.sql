@@ -0,0 +1,62 @@
+CREATE TABLE IF NOT EXISTS metadata
+(
+    id           INTEGER PRIMARY KEY,
+    full_path    TEXT    NOT NULL DEFAULT '',
+    file_path    TEXT    NOT NULL DEFAULT '',
+    file_name    TEXT    NOT NULL DEFAULT '',
+    file_size    INTEGER NOT NULL DEFAULT 0,
+    file_suffix  TEXT    NOT NULL DEFAULT '',
+    added        TEXT    NOT NULL DEFAULT '',
+    created      TEXT    NOT NULL DEFAULT '',
+    modified     TEXT    NOT NULL DEFAULT '',
+    tags         TEXT    NOT NULL DEFAULT '',
+    exegesis     TEXT    NOT NULL DEFAULT '',
+    score        REAL    NOT NULL DEFAULT 0,
+    is_del       INTEGER NOT NULL DEFAULT 0,
+    sha1         TEXT    NOT NULL DEFAULT '',
+    image_width  INTEGER NOT NULL DEFAULT 0,
+    image_height INTEGER NOT NULL DEFAULT 0,
+    thumbnail    TEXT    NOT NULL DEFAULT '',
+    colors       TEXT    NOT NULL DEFAULT '',
+    shape        TEXT    NOT NULL DEFAULT '',
+    duration     INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS idx_metadata_sha1 ON metadata (sha1);
+CREATE INDEX IF NOT EXISTS idx_metadata_full_path ON metadata (full_path);
+CREATE INDEX IF NOT EXISTS idx_metadata_file_path ON metadata (file_path);
+
+CREATE TABLE IF NOT EXISTS task
+(
+    id          INTEGER PRIMARY KEY,
+    file_path   TEXT    NOT NULL DEFAULT '',
+    file_suffix TEXT    NOT NULL DEFAULT '',
+    status      INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS idx_task_file_path ON task (file_path);
+CREATE INDEX IF NOT EXISTS idx_task_status ON task (status);
+
+CREATE TABLE IF NOT EXISTS folder
+(
+    id   INTEGER PRIMARY KEY,
+    pid  INTEGER NOT NULL DEFAULT 0,
+    name TEXT    NOT NULL DEFAULT '',
+    path TEXT    NOT NULL DEFAULT ''
+);
+CREATE INDEX IF NOT EXISTS idx_folder_pid ON folder (pid);
+CREATE INDEX IF NOT EXISTS idx_folder_path ON folder (path);
+
+CREATE TABLE IF NOT EXISTS basket
+(
+    id   INTEGER PRIMARY KEY,
+    name TEXT NOT NULL DEFAULT ''
+);
+CREATE INDEX IF NOT EXISTS idx_basket_name ON basket (name);
+
+CREATE TABLE IF NOT EXISTS basket_folder
+(
+    id        INTEGER PRIMARY KEY,
+    basket_id INTEGER NOT NULL DEFAULT 0,
+    folder_id INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS idx_basket_folder_basket_id ON basket_folder (basket_id);
+CREATE INDEX IF NOT EXISTS idx_basket_folder_folder_id ON basket_folder (folder_id);
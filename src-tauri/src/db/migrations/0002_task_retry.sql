@@ -0,0 +1,4 @@
+ALTER TABLE task ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE task ADD COLUMN last_error TEXT NOT NULL DEFAULT '';
+ALTER TABLE task ADD COLUMN last_attempt TEXT NOT NULL DEFAULT '';
+ALTER TABLE task ADD COLUMN next_attempt INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS exif
+(
+    metadata_id  INTEGER PRIMARY KEY,
+    make         TEXT    NOT NULL DEFAULT '',
+    model        TEXT    NOT NULL DEFAULT '',
+    lens         TEXT    NOT NULL DEFAULT '',
+    iso          INTEGER NOT NULL DEFAULT 0,
+    aperture     REAL    NOT NULL DEFAULT 0,
+    shutter      TEXT    NOT NULL DEFAULT '',
+    focal_length REAL    NOT NULL DEFAULT 0,
+    captured     TEXT    NOT NULL DEFAULT '',
+    orientation  INTEGER NOT NULL DEFAULT 0,
+    latitude     REAL,
+    longitude    REAL,
+    altitude     REAL,
+    copyright    TEXT    NOT NULL DEFAULT '',
+    keywords     TEXT    NOT NULL DEFAULT '[]'
+);
+CREATE INDEX IF NOT EXISTS idx_exif_captured ON exif (captured);
+CREATE INDEX IF NOT EXISTS idx_exif_camera ON exif (make, model);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS psd
+(
+    metadata_id INTEGER PRIMARY KEY,
+    color_mode  TEXT    NOT NULL DEFAULT '',
+    depth       INTEGER NOT NULL DEFAULT 0,
+    layer_count INTEGER NOT NULL DEFAULT 0,
+    group_count INTEGER NOT NULL DEFAULT 0,
+    composited  INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS psd_layer
+(
+    metadata_id INTEGER NOT NULL,
+    position    INTEGER NOT NULL,
+    name        TEXT    NOT NULL DEFAULT '',
+    is_group    INTEGER NOT NULL DEFAULT 0,
+    parent      TEXT    NOT NULL DEFAULT '',
+    visible     INTEGER NOT NULL DEFAULT 1,
+    opacity     INTEGER NOT NULL DEFAULT 255,
+    PRIMARY KEY (metadata_id, position)
+);
+CREATE INDEX IF NOT EXISTS idx_psd_layer_name ON psd_layer (name);
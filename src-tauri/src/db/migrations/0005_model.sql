@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS model
+(
+    metadata_id  INTEGER PRIMARY KEY,
+    vertex_count INTEGER NOT NULL DEFAULT 0,
+    face_count   INTEGER NOT NULL DEFAULT 0,
+    size_x       REAL    NOT NULL DEFAULT 0,
+    size_y       REAL    NOT NULL DEFAULT 0,
+    size_z       REAL    NOT NULL DEFAULT 0,
+    materials    TEXT    NOT NULL DEFAULT '[]',
+    textures     TEXT    NOT NULL DEFAULT '[]',
+    animated     INTEGER NOT NULL DEFAULT 0
+);
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS video
+(
+    metadata_id   INTEGER PRIMARY KEY,
+    container     TEXT    NOT NULL DEFAULT '',
+    video_codec   TEXT    NOT NULL DEFAULT '',
+    width         INTEGER NOT NULL DEFAULT 0,
+    height        INTEGER NOT NULL DEFAULT 0,
+    frame_rate    REAL    NOT NULL DEFAULT 0,
+    bitrate       INTEGER NOT NULL DEFAULT 0,
+    rotation      INTEGER NOT NULL DEFAULT 0,
+    audio_tracks  TEXT    NOT NULL DEFAULT '[]',
+    creation_time TEXT    NOT NULL DEFAULT '',
+    chapters      TEXT    NOT NULL DEFAULT '[]'
+);
@@ -0,0 +1,2 @@
+ALTER TABLE video ADD COLUMN preview_format TEXT NOT NULL DEFAULT '';
+ALTER TABLE video ADD COLUMN preview_frames INTEGER NOT NULL DEFAULT 0;
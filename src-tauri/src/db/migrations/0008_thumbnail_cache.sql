@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS legacy_thumbnail
+(
+    metadata_id INTEGER PRIMARY KEY,
+    sha1        TEXT NOT NULL,
+    data        TEXT NOT NULL
+);
+INSERT INTO legacy_thumbnail (metadata_id, sha1, data)
+SELECT id, sha1, thumbnail FROM metadata WHERE thumbnail LIKE 'data:%' AND sha1 != '';
+ALTER TABLE metadata DROP COLUMN thumbnail;
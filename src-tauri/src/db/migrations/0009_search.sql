@@ -0,0 +1,9 @@
+CREATE VIRTUAL TABLE IF NOT EXISTS metadata_fts USING fts5
+(
+    file_name,
+    file_path,
+    tags,
+    exegesis,
+    keywords,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
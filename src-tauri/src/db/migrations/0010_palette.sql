@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS palette
+(
+    metadata_id INTEGER NOT NULL,
+    position    INTEGER NOT NULL DEFAULT 0,
+    color       TEXT    NOT NULL DEFAULT '',
+    weight      REAL    NOT NULL DEFAULT 0,
+    l           REAL    NOT NULL DEFAULT 0,
+    a           REAL    NOT NULL DEFAULT 0,
+    b           REAL    NOT NULL DEFAULT 0,
+    PRIMARY KEY (metadata_id, position)
+);
+CREATE INDEX IF NOT EXISTS idx_palette_lab ON palette (l, a, b);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS image_hash
+(
+    metadata_id INTEGER PRIMARY KEY,
+    ahash       INTEGER NOT NULL DEFAULT 0,
+    dhash       INTEGER NOT NULL DEFAULT 0,
+    phash       INTEGER NOT NULL DEFAULT 0
+);
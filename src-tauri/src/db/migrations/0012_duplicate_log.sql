@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS duplicate_log
+(
+    id          INTEGER PRIMARY KEY,
+    sha1        TEXT    NOT NULL DEFAULT '',
+    metadata_id INTEGER NOT NULL DEFAULT 0,
+    kept_id     INTEGER NOT NULL DEFAULT 0,
+    action      TEXT    NOT NULL DEFAULT '',
+    source      TEXT    NOT NULL DEFAULT '',
+    target      TEXT    NOT NULL DEFAULT '',
+    created     TEXT    NOT NULL DEFAULT ''
+);
+CREATE INDEX IF NOT EXISTS idx_duplicate_log_sha1 ON duplicate_log (sha1);
@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS tag
+(
+    id      INTEGER PRIMARY KEY,
+    pid     INTEGER NOT NULL DEFAULT 0,
+    name    TEXT    NOT NULL DEFAULT '',
+    path    TEXT    NOT NULL DEFAULT '' COLLATE NOCASE,
+    color   TEXT    NOT NULL DEFAULT '',
+    created TEXT    NOT NULL DEFAULT ''
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_tag_path ON tag (path);
+CREATE INDEX IF NOT EXISTS idx_tag_pid ON tag (pid);
+
+CREATE TABLE IF NOT EXISTS tag_alias
+(
+    alias  TEXT    PRIMARY KEY COLLATE NOCASE,
+    tag_id INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS idx_tag_alias_tag_id ON tag_alias (tag_id);
+
+CREATE TABLE IF NOT EXISTS metadata_tag
+(
+    metadata_id INTEGER NOT NULL,
+    tag_id      INTEGER NOT NULL,
+    PRIMARY KEY (metadata_id, tag_id)
+);
+CREATE INDEX IF NOT EXISTS idx_metadata_tag_tag_id ON metadata_tag (tag_id);
@@ -0,0 +1,4 @@
+ALTER TABLE metadata ADD COLUMN label TEXT NOT NULL DEFAULT '';
+ALTER TABLE metadata ADD COLUMN source_url TEXT NOT NULL DEFAULT '';
+ALTER TABLE metadata ADD COLUMN annotated TEXT NOT NULL DEFAULT '';
+CREATE INDEX IF NOT EXISTS idx_metadata_label ON metadata (label);
@@ -0,0 +1,8 @@
+ALTER TABLE metadata ADD COLUMN title TEXT NOT NULL DEFAULT '';
+
+CREATE TABLE IF NOT EXISTS sidecar
+(
+    metadata_id INTEGER PRIMARY KEY,
+    path        TEXT    NOT NULL DEFAULT '',
+    modified    TEXT    NOT NULL DEFAULT ''
+);
@@ -0,0 +1,2 @@
+ALTER TABLE task ADD COLUMN file_size INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE task ADD COLUMN modified TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,11 @@
+DROP TABLE IF EXISTS metadata_fts;
+CREATE VIRTUAL TABLE IF NOT EXISTS metadata_fts USING fts5
+(
+    file_name,
+    file_path,
+    tags,
+    exegesis,
+    keywords,
+    title,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
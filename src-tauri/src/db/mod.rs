@@ -1,5 +1,7 @@
 pub mod sqlite;
 pub mod entity;
+pub mod migration;
+pub mod writer;
 
 use sqlx::{migrate::MigrateDatabase, Sqlite};
 use std::env;
@@ -1,6 +1,19 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
 use crate::debug;
-use sqlx::sqlite::{SqliteConnectOptions, SqliteQueryResult, SqliteRow};
-use sqlx::{query, query_as, FromRow, Pool, Sqlite, SqlitePool};
+use once_cell::sync::Lazy;
+use sqlx::sqlite::{
+    SqliteArguments, SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqliteQueryResult,
+    SqliteRow, SqliteSynchronous,
+};
+use sqlx::{
+    query, query_as, query_as_with, query_with, Arguments, FromRow, Pool, Sqlite, SqlitePool,
+};
+
+/// 已建立的连接池，同一个数据库共用一个连接池
+static POOLS: Lazy<Mutex<HashMap<String, Pool<Sqlite>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
 /// 数据库会话
 pub struct Session {
@@ -52,7 +65,9 @@ impl Session {
         Err(sqlx::Error::PoolClosed)
     }
 
-    /// 建立连接
+    /// 建立连接，已有连接池时直接复用
+    ///
+    /// 使用WAL模式，读写互不阻塞
     ///
     /// ```rust,no_run
     /// # use pixel_basket::db::sqlite::Session;
@@ -62,12 +77,43 @@ impl Session {
     /// # }
     /// ```
     pub async fn connect(&mut self) {
-        let options = SqliteConnectOptions::new().filename(&self.url);
+        if let Some(pool) = POOLS.lock().ok().and_then(|v| v.get(&self.url).cloned()) {
+            self.pool = Some(pool);
+            return;
+        }
+        let options = SqliteConnectOptions::new()
+            .filename(&self.url)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(Duration::from_secs(30));
         if let Ok(pool) = SqlitePool::connect_with(options).await {
+            if let Ok(mut pools) = POOLS.lock() {
+                pools.insert(self.url.clone(), pool.clone());
+            }
             self.pool = Some(pool);
         }
     }
 
+    /// 开始事务
+    ///
+    /// ```rust,no_run
+    /// # use pixel_basket::db::sqlite::Session;
+    /// # async fn example() {
+    /// let mut session = Session::new("test.db");
+    /// session.connect().await;
+    ///
+    /// let mut tx = session.begin().await.expect("");
+    /// tx.sql("DELETE FROM task WHERE id = ?").bind(1).execute().await.expect("");
+    /// tx.commit().await.expect("");
+    /// # }
+    /// ```
+    pub async fn begin(&self) -> Result<Transaction, sqlx::Error> {
+        Ok(Transaction {
+            tx: self.as_pool()?.begin().await?,
+        })
+    }
+
     /// 执行语句
     ///
     /// ```rust,no_run
@@ -208,13 +254,322 @@ impl Session {
         }
         Err(sqlx::Error::PoolClosed)
     }
+
+    /// 创建参数化语句，参数通过`bind`绑定，不要把外部输入拼接进SQL
+    ///
+    /// ```rust,no_run
+    /// # use pixel_basket::db::sqlite::Session;
+    /// # async fn example() {
+    /// let mut session = Session::new("test.db");
+    /// // 先建立连接
+    /// session.connect().await;
+    ///
+    /// let result = session
+    ///     .sql("SELECT COUNT(*) AS count FROM folder WHERE path = ?")
+    ///     .bind("O'Brien/photos")
+    ///     .count()
+    ///     .await
+    ///     .expect("");
+    /// # }
+    /// ```
+    pub fn sql(&self, sql: &str) -> Statement<'_> {
+        Statement {
+            executor: self.pool.as_ref().map(Executor::Pool),
+            sql: sql.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    /// 按id分批查询并合并结果，`sql`根据占位符生成`IN (...)`语句
+    ///
+    /// ```rust,no_run
+    /// # use pixel_basket::db::sqlite::Session;
+    /// # use pixel_basket::db::entity::exif::Exif;
+    /// # async fn example() {
+    /// let mut session = Session::new("test.db");
+    /// session.connect().await;
+    ///
+    /// let list = session
+    ///     .select_in::<Exif>(&[1, 2], |v| format!("SELECT * FROM exif WHERE metadata_id IN ({v})"))
+    ///     .await
+    ///     .expect("");
+    /// # }
+    /// ```
+    pub async fn select_in<T: for<'r> FromRow<'r, SqliteRow> + Send + Unpin>(
+        &self,
+        ids: &[i64],
+        sql: impl Fn(&str) -> String,
+    ) -> Result<Vec<T>, sqlx::Error> {
+        let mut list = Vec::new();
+        for chunk in ids.chunks(QUERY_CHUNK) {
+            let sql = sql(&placeholders(chunk.len()));
+            list.extend(self.sql(&sql).bind_all(chunk).select_as::<T>().await?);
+        }
+        Ok(list)
+    }
+}
+
+/// 数据库事务，未提交时释放会回滚
+pub struct Transaction {
+    tx: sqlx::Transaction<'static, Sqlite>,
+}
+
+impl Transaction {
+    /// 创建在事务中执行的参数化语句
+    pub fn sql(&mut self, sql: &str) -> Statement<'_> {
+        Statement {
+            executor: Some(Executor::Connection(&mut self.tx)),
+            sql: sql.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    /// 事务使用的连接，用于需要直接调用sqlx的场景
+    pub fn connection(&mut self) -> &mut SqliteConnection {
+        &mut self.tx
+    }
+
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        self.tx.commit().await
+    }
+}
+
+/// 绑定参数
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+macro_rules! impl_arg_from {
+    ($variant:ident as $target:ty: $($t:ty),*) => {
+        $(impl From<$t> for Arg {
+            fn from(value: $t) -> Self {
+                Arg::$variant(value as $target)
+            }
+        }
+
+        impl From<&$t> for Arg {
+            fn from(value: &$t) -> Self {
+                Arg::$variant(*value as $target)
+            }
+        })*
+    };
+}
+
+impl_arg_from!(Integer as i64: i64, i32, u32, u16, u8, usize);
+impl_arg_from!(Real as f64: f64, f32);
+
+impl From<bool> for Arg {
+    fn from(value: bool) -> Self {
+        Arg::Integer(value as i64)
+    }
+}
+
+impl From<String> for Arg {
+    fn from(value: String) -> Self {
+        Arg::Text(value)
+    }
+}
+
+impl From<&String> for Arg {
+    fn from(value: &String) -> Self {
+        Arg::Text(value.clone())
+    }
+}
+
+impl From<&str> for Arg {
+    fn from(value: &str) -> Self {
+        Arg::Text(value.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Arg {
+    fn from(value: Vec<u8>) -> Self {
+        Arg::Blob(value)
+    }
+}
+
+impl<T: Into<Arg>> From<Option<T>> for Arg {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Arg::Null, Into::into)
+    }
+}
+
+/// 语句的执行位置
+enum Executor<'s> {
+    Pool(&'s Pool<Sqlite>),
+    Connection(&'s mut SqliteConnection),
+}
+
+/// 在连接池或事务的连接上执行查询
+macro_rules! fetch {
+    ($self:ident, $query:expr, $method:ident) => {{
+        debug!("SQL ==> {} <== {:?}", $self.sql, $self.args);
+        let arguments = $self.arguments();
+        match $self.executor.ok_or(sqlx::Error::PoolClosed)? {
+            Executor::Pool(pool) => $query(&$self.sql, arguments).$method(pool).await,
+            Executor::Connection(conn) => $query(&$self.sql, arguments).$method(conn).await,
+        }
+    }};
+}
+
+/// 参数化语句
+pub struct Statement<'s> {
+    executor: Option<Executor<'s>>,
+    sql: String,
+    args: Vec<Arg>,
+}
+
+impl<'s> Statement<'s> {
+    /// 按顺序绑定一个参数
+    pub fn bind<T: Into<Arg>>(mut self, value: T) -> Self {
+        self.args.push(value.into());
+        self
+    }
+
+    /// 按顺序绑定多个参数，配合`placeholders`用于`IN (...)`
+    pub fn bind_all<T: Into<Arg>, I: IntoIterator<Item = T>>(mut self, values: I) -> Self {
+        self.args.extend(values.into_iter().map(Into::into));
+        self
+    }
+
+    fn arguments(&self) -> SqliteArguments<'static> {
+        let mut arguments = SqliteArguments::default();
+        for arg in self.args.iter() {
+            match arg.clone() {
+                Arg::Null => arguments.add(Option::<i64>::None),
+                Arg::Integer(v) => arguments.add(v),
+                Arg::Real(v) => arguments.add(v),
+                Arg::Text(v) => arguments.add(v),
+                Arg::Blob(v) => arguments.add(v),
+            }
+        }
+        arguments
+    }
+
+    /// 执行语句
+    pub async fn execute(self) -> Result<SqliteQueryResult, sqlx::Error> {
+        fetch!(self, query_with, execute)
+    }
+
+    /// 查询语句
+    pub async fn select(self) -> Result<Vec<SqliteRow>, sqlx::Error> {
+        fetch!(self, query_with, fetch_all)
+    }
+
+    /// 查询语句
+    pub async fn select_as<T: for<'r> FromRow<'r, SqliteRow> + Send + Unpin>(
+        self,
+    ) -> Result<Vec<T>, sqlx::Error> {
+        fetch!(self, query_as_with::<_, T, _>, fetch_all)
+    }
+
+    /// 查询一条，不存在时返回`RowNotFound`
+    pub async fn select_one_as<T: for<'r> FromRow<'r, SqliteRow> + Send + Unpin>(
+        self,
+    ) -> Result<T, sqlx::Error> {
+        fetch!(self, query_as_with::<_, T, _>, fetch_one)
+    }
+
+    /// 查询一条，不存在时返回`None`
+    pub async fn select_optional_as<T: for<'r> FromRow<'r, SqliteRow> + Send + Unpin>(
+        self,
+    ) -> Result<Option<T>, sqlx::Error> {
+        fetch!(self, query_as_with::<_, T, _>, fetch_optional)
+    }
+
+    /// 查询条数，语句需要返回`count`列
+    pub async fn count(self) -> Result<Count, sqlx::Error> {
+        fetch!(self, query_as_with::<_, Count, _>, fetch_one)
+    }
+}
+
+/// 每次按id查询或修改的最大数量，避免超出SQLite的参数上限
+pub const QUERY_CHUNK: usize = 500;
+
+/// 生成`n`个以逗号分隔的占位符
+///
+/// ```rust
+/// # use pixel_basket::db::sqlite::placeholders;
+/// assert_eq!(placeholders(3), "?, ?, ?");
+/// ```
+pub fn placeholders(n: usize) -> String {
+    vec!["?"; n].join(", ")
+}
+
+/// 生成`LIKE`前缀匹配参数，转义其中的`%`和`_`，语句中需要使用`ESCAPE '\'`
+///
+/// ```rust
+/// # use pixel_basket::db::sqlite::like_prefix;
+/// assert_eq!(like_prefix("100%_a"), "100\\%\\_a%");
+/// ```
+pub fn like_prefix(value: &str) -> String {
+    let mut escaped = like_escape(value);
+    escaped.push('%');
+    escaped
+}
+
+/// 生成`LIKE`包含匹配参数，转义规则同`like_prefix`
+///
+/// ```rust
+/// # use pixel_basket::db::sqlite::like_contains;
+/// assert_eq!(like_contains("100%"), "%100\\%%");
+/// ```
+pub fn like_contains(value: &str) -> String {
+    format!("%{}%", like_escape(value))
+}
+
+fn like_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    for c in value.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// 创建测试用的临时数据库会话
+#[cfg(test)]
+pub(crate) async fn temp_session(name: &str) -> Session {
+    let path = std::env::temp_dir().join(format!("pixel_basket_{name}.db"));
+    // WAL模式下残留的日志会在连接时恢复到新数据库
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+    let mut session = Session::new(path.to_str().unwrap());
+    session.connect().await;
+    session
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
     use crate::config::{get_db_path, DB};
-    use crate::db::sqlite::Session;
-    use sqlx::query;
+    use crate::db::entity::exif::Exif;
+    use crate::db::entity::folder::Folder;
+    use crate::db::entity::metadata::Metadata;
+    use crate::db::entity::psd::{PsdInfo, PsdLayerInfo};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::{like_prefix, placeholders, temp_session, Session};
+    use sqlx::{query, Row};
+
+    const PATHS: [&str; 8] = [
+        "/home/O'Brien/photos",
+        "/home/'; DROP TABLE folder; --",
+        "/data/100%_done",
+        "/data/under_score",
+        "C:\\照片\\\"quoted\"",
+        "/data/猫🐱/e\u{301}",
+        "/data/line\nbreak\ttab",
+        "/data/nul\u{0}byte",
+    ];
 
     #[derive(Debug, sqlx::FromRow)]
     struct User {
@@ -284,6 +639,152 @@ mod tests {
             .expect("err");
         println!("users:{:?}", result);
     }
+
+    #[tokio::test]
+    async fn test_bind_folder_round_trip() {
+        let session = temp_session("bind_folder").await;
+        migrate(&session).await.unwrap();
+        for path in PATHS {
+            let folder = Folder::new(Path::new(path), 0);
+            assert!(!folder.exist(&session).await);
+            folder.save(&session).await;
+            assert!(folder.exist(&session).await, "{path}");
+            let saved = Folder::get_by_path(&session, path.to_string())
+                .await
+                .expect(path);
+            assert_eq!(saved.id, folder.id);
+            assert_eq!(saved.path, path);
+            assert_eq!(saved.name, folder.name);
+        }
+        let result = session
+            .sql(&format!(
+                "SELECT COUNT(*) AS count FROM folder WHERE path IN ({})",
+                placeholders(PATHS.len())
+            ))
+            .bind_all(PATHS)
+            .count()
+            .await
+            .unwrap();
+        assert_eq!(result.count, PATHS.len() as i64);
+    }
+
+    #[tokio::test]
+    async fn test_bind_task_round_trip() {
+        let session = temp_session("bind_task").await;
+        migrate(&session).await.unwrap();
+        for path in PATHS {
+            let metadata = Metadata::load(Path::new(&format!("{path}/a'b.jpg")));
+            metadata.save_task_to_db(&session).await;
+            // 重复保存不会产生新任务
+            metadata.save_task_to_db(&session).await;
+            let result = session
+                .sql("SELECT COUNT(*) AS count FROM task WHERE file_path = ?")
+                .bind(&metadata.full_path)
+                .count()
+                .await
+                .unwrap();
+            assert_eq!(result.count, 1, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let session = temp_session("transaction").await;
+        migrate(&session).await.unwrap();
+        let mut metadata = Metadata::load(Path::new("/data/O'Brien.jpg"));
+        // 未提交的事务释放时回滚
+        {
+            let mut tx = session.begin().await.unwrap();
+            metadata.save(&mut tx).await.unwrap();
+        }
+        let result = session
+            .count("SELECT COUNT(*) AS count FROM metadata")
+            .await
+            .unwrap();
+        assert_eq!(result.count, 0);
+        let mut tx = session.begin().await.unwrap();
+        metadata.save(&mut tx).await.unwrap();
+        // 同一事务中路径已存在时更新原记录
+        metadata.exif = Some(Exif {
+            make: String::from("Nikon"),
+            ..Exif::default()
+        });
+        let layer = |position: i64, name: &str| PsdLayerInfo {
+            position,
+            name: name.to_string(),
+            ..PsdLayerInfo::default()
+        };
+        metadata.psd = Some(PsdInfo {
+            layers: vec![layer(0, "背景"), layer(1, "文字")],
+            ..PsdInfo::default()
+        });
+        metadata.save(&mut tx).await.unwrap();
+        // 重新保存时替换原来的图层
+        metadata.psd = Some(PsdInfo {
+            layer_count: 1,
+            layers: vec![layer(0, "背景")],
+            ..PsdInfo::default()
+        });
+        metadata.save(&mut tx).await.unwrap();
+        tx.commit().await.unwrap();
+        let result = session
+            .sql("SELECT id FROM metadata WHERE full_path = ?")
+            .bind(&metadata.full_path)
+            .select()
+            .await
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        let id: i64 = result[0].get("id");
+        let exif = Exif::map_by_ids(&session, &[id]).await;
+        assert_eq!(exif[&id].make, "Nikon");
+        let psd = PsdInfo::map_by_ids(&session, &[id]).await;
+        assert_eq!(psd[&id].layer_count, 1);
+        assert_eq!(psd[&id].layers.len(), 1);
+        assert_eq!(psd[&id].layers[0].name, "背景");
+
+        // 重新扫描没有的附加信息删除旧记录
+        metadata.psd = None;
+        let mut tx = session.begin().await.unwrap();
+        metadata.save(&mut tx).await.unwrap();
+        tx.commit().await.unwrap();
+        assert!(PsdInfo::map_by_ids(&session, &[id]).await.is_empty());
+        let result = session
+            .count("SELECT COUNT(*) AS count FROM psd_layer")
+            .await
+            .unwrap();
+        assert_eq!(result.count, 0);
+        assert_eq!(Exif::map_by_ids(&session, &[id]).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_like_prefix() {
+        let session = temp_session("like_prefix").await;
+        migrate(&session).await.unwrap();
+        for (id, path) in ["/a%b/", "/axb/", "/a_c/", "/abc/", "/a%b/c/"].iter().enumerate() {
+            session
+                .sql("INSERT INTO metadata (id, file_path) VALUES (?, ?)")
+                .bind(id as i64)
+                .bind(*path)
+                .execute()
+                .await
+                .unwrap();
+        }
+        let count = |prefix: &'static str| {
+            let session = &session;
+            async move {
+                session
+                    .sql("SELECT COUNT(*) AS count FROM metadata WHERE file_path LIKE ? ESCAPE '\\'")
+                    .bind(like_prefix(prefix))
+                    .count()
+                    .await
+                    .unwrap()
+                    .count
+            }
+        };
+        assert_eq!(count("/a%b").await, 2);
+        assert_eq!(count("/a_c").await, 1);
+        assert_eq!(count("/a").await, 5);
+    }
 }
 
 #[derive(Debug, sqlx::FromRow)]
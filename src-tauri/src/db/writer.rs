@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::time::{timeout_at, Instant};
+
+use crate::config::get_db_path;
+use crate::db::entity::metadata::Metadata;
+use crate::db::entity::task::Task;
+use crate::db::sqlite::Session;
+use crate::util::error::ErrorHandle;
+use crate::{debug, error};
+
+/// 写入队列长度，队列已满时扫描线程等待写入完成
+const QUEUE_SIZE: usize = 1024;
+
+/// 单个事务最多包含的写入数
+const BATCH_SIZE: usize = 256;
+
+/// 收集同一批写入的最长等待时间
+const BATCH_WAIT: Duration = Duration::from_millis(200);
+
+enum Write {
+    Metadata(Box<Metadata>),
+    CompleteTask(Box<Task>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// 数据库写入线程
+///
+/// 所有扫描结果通过同一个连接池写入，短时间内的写入合并到同一个事务中提交，
+/// 写入按提交顺序执行，元数据总是先于对应任务的删除落盘
+pub struct Writer {
+    tx: Sender<Write>,
+}
+
+static WRITER: Lazy<Writer> = Lazy::new(|| Writer::start(get_db_path().to_string()));
+
+/// 获取写入线程，第一次调用时启动
+pub fn writer() -> &'static Writer {
+    &WRITER
+}
+
+impl Writer {
+    /// 在独立线程中运行，不受扫描运行时关闭的影响
+    fn start(url: String) -> Self {
+        let (tx, rx) = channel(QUEUE_SIZE);
+        std::thread::Builder::new()
+            .name(String::from("db-writer"))
+            .spawn(move || {
+                match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(runtime) => runtime.block_on(run(url, rx)),
+                    Err(e) => error!("{e}"),
+                }
+            })
+            .print_error();
+        Self { tx }
+    }
+
+    /// 保存元数据，路径已存在时更新
+    pub async fn save_metadata(&self, metadata: Metadata) {
+        self.send(Write::Metadata(Box::new(metadata))).await;
+    }
+
+    /// 任务完成，从队列中移除，对应的元数据保存失败时改为记录失败
+    pub async fn complete_task(&self, task: Task) {
+        self.send(Write::CompleteTask(Box::new(task))).await;
+    }
+
+    /// 等待之前的写入全部提交
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.send(Write::Flush(tx)).await;
+        let _ = rx.await;
+    }
+
+    async fn send(&self, write: Write) {
+        if self.tx.send(write).await.is_err() {
+            error!("数据库写入线程已停止");
+        }
+    }
+}
+
+async fn run(url: String, mut rx: Receiver<Write>) {
+    let mut session = Session::new(&url);
+    session.connect().await;
+    let mut failed = HashMap::new();
+    while let Some(write) = rx.recv().await {
+        let mut batch = vec![write];
+        let deadline = Instant::now() + BATCH_WAIT;
+        while batch.len() < BATCH_SIZE && !matches!(batch.last(), Some(Write::Flush(_))) {
+            match timeout_at(deadline, rx.recv()).await {
+                Ok(Some(write)) => batch.push(write),
+                _ => break,
+            }
+        }
+        commit(&session, batch, &mut failed).await;
+    }
+}
+
+/// 在同一个事务中执行一批写入，单个文件保存失败不影响其他写入
+///
+/// `failed`记录保存失败的文件路径和原因，对应的任务按失败重试而不是删除，
+/// 任务可能在之后的批次中完成，所以跨批次保留
+async fn commit(session: &Session, batch: Vec<Write>, failed: &mut HashMap<String, String>) {
+    let start = std::time::Instant::now();
+    let size = batch.len();
+    let mut flushed = Vec::new();
+    if let Some(mut tx) = session.begin().await.print_error() {
+        for write in batch {
+            match write {
+                Write::Metadata(metadata) => {
+                    // 每个文件使用单独的保存点，失败时撤销该文件已经写入的部分
+                    tx.sql("SAVEPOINT metadata").execute().await.print_error();
+                    let saved = metadata.save(&mut tx).await.map_err(|e| e.to_string());
+                    if let Err(e) = saved {
+                        error!("保存失败：{} {e}", metadata.full_path);
+                        tx.sql("ROLLBACK TO metadata").execute().await.print_error();
+                        failed.insert(metadata.full_path, e);
+                    }
+                    tx.sql("RELEASE metadata").execute().await.print_error();
+                }
+                Write::CompleteTask(task) => {
+                    if let Some(e) = failed.remove(&task.file_path) {
+                        task.fail_in(&mut tx, &e).await.print_error();
+                        continue;
+                    }
+                    tx.sql("DELETE FROM task WHERE id = ?")
+                        .bind(task.id)
+                        .execute()
+                        .await
+                        .print_error();
+                }
+                Write::Flush(sender) => flushed.push(sender),
+            }
+        }
+        tx.commit().await.print_error();
+    }
+    for sender in flushed {
+        let _ = sender.send(());
+    }
+    debug!("提交{}条写入，用时{:?}", size, start.elapsed());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use crate::db::entity::image_hash::ImageHash;
+    use crate::db::entity::metadata::Metadata;
+    use crate::db::entity::task::{Task, PENDING};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+    use crate::db::writer::{commit, Write};
+
+    #[tokio::test]
+    async fn test_commit_keeps_failed_task() {
+        let session = temp_session("writer").await;
+        migrate(&session).await.unwrap();
+        let metadata = Metadata::load(Path::new("/data/1.jpg"));
+        metadata.save_task_to_db(&session).await;
+        let task = Task::list_pending(&session, 0, 10).await.remove(0);
+        session
+            .execute("CREATE TRIGGER reject BEFORE INSERT ON metadata BEGIN SELECT RAISE(ABORT, 'rejected'); END")
+            .await
+            .unwrap();
+        let mut failed = HashMap::new();
+        let batch = vec![
+            Write::Metadata(Box::new(metadata)),
+            Write::CompleteTask(Box::new(task)),
+        ];
+        commit(&session, batch, &mut failed).await;
+        assert!(failed.is_empty());
+        let tasks = session
+            .sql("SELECT * FROM task")
+            .select_as::<Task>()
+            .await
+            .unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].status, PENDING);
+        assert_eq!(tasks[0].attempts, 1);
+        assert!(tasks[0].last_error.contains("rejected"));
+
+        // 保存成功后任务从队列中移除
+        session.execute("DROP TRIGGER reject").await.unwrap();
+        let metadata = Metadata::load(Path::new("/data/1.jpg"));
+        let task = tasks.into_iter().next().unwrap();
+        let batch = vec![
+            Write::Metadata(Box::new(metadata)),
+            Write::CompleteTask(Box::new(task)),
+        ];
+        commit(&session, batch, &mut failed).await;
+        let result = session
+            .count("SELECT COUNT(*) AS count FROM task")
+            .await
+            .unwrap();
+        assert_eq!(result.count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_commit_rolls_back_file() {
+        let session = temp_session("writer_rollback").await;
+        migrate(&session).await.unwrap();
+        session
+            .execute("CREATE TRIGGER reject BEFORE INSERT ON image_hash BEGIN SELECT RAISE(ABORT, 'rejected'); END")
+            .await
+            .unwrap();
+        let mut failed = HashMap::new();
+        let mut metadata = Metadata::load(Path::new("/data/1.jpg"));
+        metadata.hash = Some(ImageHash::default());
+        let batch = vec![
+            Write::Metadata(Box::new(metadata)),
+            Write::Metadata(Box::new(Metadata::load(Path::new("/data/2.jpg")))),
+        ];
+        commit(&session, batch, &mut failed).await;
+        // 附加信息保存失败时不留下不完整的记录，同一批的其他文件正常保存
+        assert!(failed.contains_key("/data/1.jpg"));
+        let paths: Vec<String> = session
+            .sql("SELECT full_path FROM metadata")
+            .select_as::<(String,)>()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| v.0)
+            .collect();
+        assert_eq!(paths, vec!["/data/2.jpg"]);
+    }
+}
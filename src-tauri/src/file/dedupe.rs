@@ -0,0 +1,293 @@
+use std::path::{Component, Path, PathBuf};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::config::get_config;
+use crate::db::entity::duplicate::{
+    basket_directories, DuplicateAction, DuplicateFile, DuplicateLog, DuplicateLogVO,
+};
+use crate::db::entity::metadata::{directory_prefix, sha1, DELETED, NOT_DELETED};
+use crate::db::sqlite::Session;
+use crate::util::error::ErrorHandle;
+use crate::{info, Result};
+
+/// 处理一组重复文件，保留`keep`，其余副本按`action`移走
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateResolution {
+    pub sha1: String,
+    /// 保留的文件id
+    pub keep: String,
+    pub action: DuplicateAction,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ResolveFailureVO {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ResolveVO {
+    /// 已移走的文件
+    pub done: Vec<DuplicateLogVO>,
+    pub failed: Vec<ResolveFailureVO>,
+}
+
+/// 批量处理重复文件，移走前重新计算sha1，内容已变化的文件不会被移走
+///
+/// 隔离目录位于资源库中时不隔离文件
+pub async fn resolve(session: &Session, resolutions: &[DuplicateResolution]) -> ResolveVO {
+    let mut result = ResolveVO::default();
+    let quarantine = get_config().duplicate.quarantine_dir();
+    // 同一批次的隔离文件放在同一个目录下
+    let batch = quarantine.join(Local::now().format("%Y%m%d-%H%M%S").to_string());
+    let baskets = basket_directories(session).await;
+    let watched = watched_by(&quarantine, &baskets);
+    for resolution in resolutions {
+        // 隔离目录被监听时，移入的副本会被重新扫描
+        if let (DuplicateAction::Quarantine, Some(name)) = (resolution.action, watched) {
+            result.failed.push(ResolveFailureVO {
+                path: quarantine.to_string_lossy().to_string(),
+                reason: format!("隔离目录位于资源库「{name}」中"),
+            });
+            continue;
+        }
+        let files = DuplicateFile::list_by_sha1(session, &resolution.sha1).await;
+        let Some(kept) = files.iter().find(|v| v.id.to_string() == resolution.keep) else {
+            result.failed.push(ResolveFailureVO {
+                path: resolution.keep.clone(),
+                reason: String::from("保留的文件不在这组重复文件中"),
+            });
+            continue;
+        };
+        // 保留的文件不存在或内容已变化时不处理其他副本，避免全部丢失
+        if let Err(e) = verify(kept) {
+            result.failed.push(ResolveFailureVO {
+                path: kept.full_path.clone(),
+                reason: e.to_string(),
+            });
+            continue;
+        }
+        for file in files.iter().filter(|v| v.id != kept.id) {
+            match remove(file, resolution.action, &batch) {
+                Ok(target) => {
+                    mark_deleted(session, file.id).await;
+                    let log = DuplicateLog::new(resolution.action, file, kept, target);
+                    log.save(session).await;
+                    result.done.push(DuplicateLogVO::from(log));
+                }
+                Err(e) => result.failed.push(ResolveFailureVO {
+                    path: file.full_path.clone(),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+    }
+    info!(
+        "重复文件处理完成，移走 {} 个，失败 {} 个",
+        result.done.len(),
+        result.failed.len()
+    );
+    result
+}
+
+/// 目录所在的资源库名称
+fn watched_by<'a>(dir: &Path, baskets: &'a [(String, String)]) -> Option<&'a str> {
+    let dir = directory_prefix(&dir.to_string_lossy());
+    baskets
+        .iter()
+        .find(|(_, path)| dir.starts_with(path.as_str()))
+        .map(|(name, _)| name.as_str())
+}
+
+/// 检查文件存在且内容与记录一致
+fn verify(file: &DuplicateFile) -> Result<()> {
+    let hash = sha1(&file.full_path)?;
+    if hash != file.sha1 {
+        return Err(format!("文件内容已变化：{}", file.full_path).into());
+    }
+    Ok(())
+}
+
+/// 移走文件，返回隔离后的路径，移到回收站时为空
+///
+/// 隔离的文件放在本批次的目录`batch`中
+fn remove(file: &DuplicateFile, action: DuplicateAction, batch: &Path) -> Result<String> {
+    verify(file)?;
+    let source = Path::new(&file.full_path);
+    match action {
+        DuplicateAction::Trash => {
+            trash::delete(source)?;
+            Ok(String::new())
+        }
+        DuplicateAction::Quarantine => {
+            let target = quarantine_path(batch, source);
+            if target.exists() {
+                return Err(format!("隔离目录中已存在：{}", target.display()).into());
+            }
+            if let Some(dir) = target.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            // 跨磁盘时无法直接重命名，复制后删除原文件
+            if std::fs::rename(source, &target).is_err() {
+                std::fs::copy(source, &target)?;
+                std::fs::remove_file(source)?;
+            }
+            Ok(target.to_string_lossy().to_string())
+        }
+    }
+}
+
+/// 在隔离目录中保留原来的完整路径，去掉盘符和根目录
+fn quarantine_path(dir: &Path, source: &Path) -> PathBuf {
+    source
+        .components()
+        .fold(dir.to_path_buf(), |path, component| match component {
+            Component::Normal(name) => path.join(name),
+            Component::Prefix(prefix) => path.join(
+                prefix
+                    .as_os_str()
+                    .to_string_lossy()
+                    .replace([':', '\\', '?'], ""),
+            ),
+            _ => path,
+        })
+}
+
+/// 移走的文件标记为用户删除，之后的扫描不会再标记为丢失
+async fn mark_deleted(session: &Session, id: i64) {
+    session
+        .sql("UPDATE metadata SET is_del = ? WHERE id = ? AND is_del = ?")
+        .bind(DELETED)
+        .bind(id)
+        .bind(NOT_DELETED)
+        .execute()
+        .await
+        .print_error();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use sqlx::Row;
+
+    use crate::config::get_config;
+    use crate::db::entity::duplicate::{DuplicateAction, DuplicateFile};
+    use crate::db::entity::metadata::{sha1, Metadata, DELETED, NOT_DELETED};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+    use crate::file::dedupe::{quarantine_path, remove, resolve, watched_by, DuplicateResolution};
+
+    #[test]
+    fn test_quarantine_path() {
+        assert_eq!(
+            quarantine_path(Path::new("/q/batch"), Path::new("/data/a/1.jpg")),
+            Path::new("/q/batch/data/a/1.jpg")
+        );
+    }
+
+    #[test]
+    fn test_watched_by() {
+        let baskets = vec![(String::from("photo"), String::from("/data/photo/"))];
+        assert_eq!(
+            watched_by(Path::new("/data/photo/quarantine"), &baskets),
+            Some("photo")
+        );
+        assert_eq!(
+            watched_by(Path::new("/data/photo"), &baskets),
+            Some("photo")
+        );
+        assert_eq!(watched_by(Path::new("/data/photos"), &baskets), None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let dir = std::env::temp_dir().join("pixel-basket-dedupe");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::create_dir_all(dir.join("b")).unwrap();
+        let kept = dir.join("a").join("1.jpg");
+        let copy = dir.join("b").join("1.jpg");
+        fs::write(&kept, b"same").unwrap();
+        fs::write(&copy, b"same").unwrap();
+
+        let session = temp_session("dedupe").await;
+        migrate(&session).await.unwrap();
+        let mut tx = session.begin().await.unwrap();
+        for path in [&kept, &copy] {
+            let mut metadata = Metadata::load(path);
+            metadata.sha1 = sha1(path).unwrap();
+            metadata.save(&mut tx).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+        let rows = session
+            .sql("SELECT id, sha1 FROM metadata WHERE full_path = ?")
+            .bind(kept.to_str().unwrap())
+            .select()
+            .await
+            .unwrap();
+        let id: i64 = rows[0].get("id");
+        let hash: String = rows[0].get("sha1");
+
+        // 保留的文件不在组内时不处理
+        let invalid = DuplicateResolution {
+            sha1: hash.clone(),
+            keep: String::from("1"),
+            action: DuplicateAction::Quarantine,
+        };
+        let result = resolve(&session, &[invalid]).await;
+        assert!(result.done.is_empty());
+        assert!(copy.exists());
+
+        // 内容变化后不移走
+        fs::write(&copy, b"changed").unwrap();
+        let file = DuplicateFile::list_by_sha1(&session, &hash)
+            .await
+            .into_iter()
+            .find(|v| v.full_path == copy.to_str().unwrap())
+            .unwrap();
+        assert!(remove(&file, DuplicateAction::Quarantine, &dir.join("quarantine")).is_err());
+        fs::write(&copy, b"same").unwrap();
+
+        let resolution = DuplicateResolution {
+            sha1: hash.clone(),
+            keep: id.to_string(),
+            action: DuplicateAction::Quarantine,
+        };
+        let result = resolve(&session, &[resolution]).await;
+        assert_eq!(result.done.len(), 1);
+        assert!(kept.exists());
+        assert!(!copy.exists());
+        let target = Path::new(&result.done[0].target);
+        assert!(target.exists());
+        // 删除本次测试的批次目录
+        let quarantine = get_config().duplicate.quarantine_dir();
+        let batch = target
+            .strip_prefix(&quarantine)
+            .unwrap()
+            .components()
+            .next()
+            .unwrap();
+        fs::remove_dir_all(quarantine.join(batch)).unwrap();
+
+        let count = |is_del: u8| {
+            let session = &session;
+            async move {
+                session
+                    .sql("SELECT COUNT(*) AS count FROM metadata WHERE is_del = ?")
+                    .bind(is_del)
+                    .count()
+                    .await
+                    .unwrap()
+                    .count
+            }
+        };
+        assert_eq!(count(NOT_DELETED).await, 1);
+        assert_eq!(count(DELETED).await, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,491 @@
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use exif::{Context, In, Reader, Tag, Value};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::db::entity::exif::Exif;
+use crate::Result;
+
+/// IPTC-NAA，TIFF中直接保存IPTC数据的标签
+const TAG_IPTC: Tag = Tag(Context::Tiff, 0x83bb);
+
+/// 元数据一般位于文件头部，只读取前8MB
+const HEADER_SIZE: u64 = 8 * 1024 * 1024;
+
+/// XMP中`prefix:name="value"`形式的属性
+static ATTRIBUTE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"([\w.-]+:[\w.-]+)\s*=\s*"([^"]*)""#).unwrap());
+
+/// XMP列表中的一项
+static ITEM: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<rdf:li[^>]*>(.*?)</rdf:li>").unwrap());
+
+/// 读取图片的EXIF、XMP和IPTC信息，支持JPEG、TIFF、PNG、WebP和基于TIFF的RAW
+///
+/// 同一项信息以EXIF优先，其次是XMP和IPTC，关键字合并去重，都不存在时返回`None`
+pub fn read_exif(path: &Path) -> Result<Option<Exif>> {
+    let mut bytes = Vec::new();
+    File::open(path)?
+        .take(HEADER_SIZE)
+        .read_to_end(&mut bytes)?;
+    let mut exif = Exif::default();
+    let mut keywords = Vec::new();
+    let mut found = false;
+
+    let mut iptc = None;
+    if let Ok(data) = Reader::new().read_from_container(&mut Cursor::new(&bytes)) {
+        found = true;
+        read_tiff(&data, &mut exif);
+        iptc = data
+            .get_field(TAG_IPTC, In::PRIMARY)
+            .and_then(|v| value_bytes(&v.value));
+    }
+    if let Some(xmp) = find_xmp(&bytes) {
+        found = true;
+        read_xmp(&xmp, &mut exif, &mut keywords);
+    }
+    if let Some(iim) = iptc.or_else(|| find_photoshop_iptc(&bytes)) {
+        found = true;
+        read_iim(&iim, &mut exif, &mut keywords);
+    }
+
+    let mut unique = Vec::new();
+    for keyword in keywords {
+        if !keyword.is_empty() && !unique.contains(&keyword) {
+            unique.push(keyword);
+        }
+    }
+    exif.set_keywords(&unique);
+    Ok(found.then_some(exif))
+}
+
+/// 为空时才填充，保证先读取的来源优先
+fn fill(target: &mut String, value: Option<String>) {
+    if target.is_empty() {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            *target = value;
+        }
+    }
+}
+
+fn read_tiff(data: &exif::Exif, exif: &mut Exif) {
+    let ascii = |tag: Tag| match &data.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(v) => v.first().map(|v| clean(&String::from_utf8_lossy(v))),
+        _ => None,
+    };
+    let uint = |tag: Tag| data.get_field(tag, In::PRIMARY)?.value.get_uint(0);
+    let rationals = |tag: Tag| match &data.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(v) => Some(
+            v.iter()
+                .map(|v| v.to_f64())
+                .filter(|v| v.is_finite())
+                .collect::<Vec<f64>>(),
+        ),
+        Value::SRational(v) => Some(
+            v.iter()
+                .map(|v| v.to_f64())
+                .filter(|v| v.is_finite())
+                .collect(),
+        ),
+        _ => None,
+    };
+    let rational = |tag: Tag| rationals(tag)?.first().copied();
+
+    fill(&mut exif.make, ascii(Tag::Make));
+    fill(&mut exif.model, ascii(Tag::Model));
+    fill(&mut exif.lens, ascii(Tag::LensModel));
+    fill(&mut exif.copyright, ascii(Tag::Copyright));
+    fill(
+        &mut exif.captured,
+        ascii(Tag::DateTimeOriginal)
+            .or_else(|| ascii(Tag::DateTimeDigitized))
+            .or_else(|| ascii(Tag::DateTime))
+            .and_then(|v| normalize_date(&v)),
+    );
+    if let Some(iso) = uint(Tag::PhotographicSensitivity) {
+        exif.iso = iso as i64;
+    }
+    if let Some(orientation) = uint(Tag::Orientation) {
+        exif.orientation = orientation as i64;
+    }
+    if let Some(aperture) = rational(Tag::FNumber) {
+        exif.aperture = aperture;
+    }
+    if let Some(focal_length) = rational(Tag::FocalLength) {
+        exif.focal_length = focal_length;
+    }
+    fill(&mut exif.shutter, rational(Tag::ExposureTime).map(shutter));
+
+    let gps = |tag: Tag, reference: Tag, negative: &str| {
+        let value = rationals(tag)?;
+        let degree = value.first()?
+            + value.get(1).unwrap_or(&0.0) / 60.0
+            + value.get(2).unwrap_or(&0.0) / 3600.0;
+        Some(match ascii(reference) {
+            Some(v) if v.eq_ignore_ascii_case(negative) => -degree,
+            _ => degree,
+        })
+    };
+    exif.latitude = gps(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S");
+    exif.longitude = gps(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W");
+    exif.altitude = rational(Tag::GPSAltitude).map(|v| match uint(Tag::GPSAltitudeRef) {
+        Some(1) => -v,
+        _ => v,
+    });
+}
+
+/// 从文件中找到XMP数据包
+fn find_xmp(bytes: &[u8]) -> Option<String> {
+    let start = find(bytes, b"<x:xmpmeta")?;
+    let end = find(&bytes[start..], b"</x:xmpmeta>")? + start;
+    Some(String::from_utf8_lossy(&bytes[start..end]).to_string())
+}
+
+fn read_xmp(xmp: &str, exif: &mut Exif, keywords: &mut Vec<String>) {
+    fill(&mut exif.make, xmp_value(xmp, "tiff:Make"));
+    fill(&mut exif.model, xmp_value(xmp, "tiff:Model"));
+    fill(
+        &mut exif.lens,
+        xmp_value(xmp, "exifEX:LensModel").or_else(|| xmp_value(xmp, "aux:Lens")),
+    );
+    fill(&mut exif.copyright, xmp_value(xmp, "dc:rights"));
+    fill(
+        &mut exif.captured,
+        xmp_value(xmp, "exif:DateTimeOriginal")
+            .or_else(|| xmp_value(xmp, "photoshop:DateCreated"))
+            .or_else(|| xmp_value(xmp, "xmp:CreateDate"))
+            .and_then(|v| normalize_date(&v)),
+    );
+    fill(
+        &mut exif.shutter,
+        xmp_value(xmp, "exif:ExposureTime")
+            .and_then(|v| parse_fraction(&v))
+            .map(shutter),
+    );
+    if exif.iso == 0 {
+        exif.iso = xmp_value(xmp, "exif:ISOSpeedRatings")
+            .or_else(|| xmp_value(xmp, "exifEX:PhotographicSensitivity"))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+    }
+    if exif.orientation == 0 {
+        exif.orientation = xmp_value(xmp, "tiff:Orientation")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+    }
+    if exif.aperture == 0.0 {
+        exif.aperture = xmp_value(xmp, "exif:FNumber")
+            .and_then(|v| parse_fraction(&v))
+            .unwrap_or(0.0);
+    }
+    if exif.focal_length == 0.0 {
+        exif.focal_length = xmp_value(xmp, "exif:FocalLength")
+            .and_then(|v| parse_fraction(&v))
+            .unwrap_or(0.0);
+    }
+    if exif.latitude.is_none() {
+        exif.latitude = xmp_value(xmp, "exif:GPSLatitude").and_then(|v| parse_xmp_gps(&v));
+    }
+    if exif.longitude.is_none() {
+        exif.longitude = xmp_value(xmp, "exif:GPSLongitude").and_then(|v| parse_xmp_gps(&v));
+    }
+    keywords.extend(xmp_list(xmp, "dc:subject"));
+}
+
+/// 读取XMP属性，支持`name="value"`、`<name>value</name>`和`rdf:Alt`等列表的第一项
+fn xmp_value(xmp: &str, name: &str) -> Option<String> {
+    if let Some(captures) = ATTRIBUTE.captures_iter(xmp).find(|v| &v[1] == name) {
+        return Some(unescape(&captures[2]));
+    }
+    let content = xmp_element(xmp, name)?;
+    if !content.contains('<') {
+        let value = unescape(content);
+        return (!value.is_empty()).then_some(value);
+    }
+    xmp_items(content).into_iter().next()
+}
+
+/// 读取XMP列表，如`dc:subject`中的`rdf:Bag`
+fn xmp_list(xmp: &str, name: &str) -> Vec<String> {
+    xmp_element(xmp, name).map(xmp_items).unwrap_or_default()
+}
+
+/// 第一个`<name>`元素的内容
+fn xmp_element<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let start = format!("<{name}>");
+    let start = xmp.find(&start)? + start.len();
+    let end = xmp[start..].find(&format!("</{name}>"))? + start;
+    Some(&xmp[start..end])
+}
+
+fn xmp_items(content: &str) -> Vec<String> {
+    ITEM.captures_iter(content)
+        .map(|v| unescape(&v[1]))
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// 解析XMP中的GPS坐标，格式为`DDD,MM,SSk`或`DDD,MM.mmk`
+fn parse_xmp_gps(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let direction = value.chars().last()?;
+    let numbers: Vec<f64> = value[..value.len() - direction.len_utf8()]
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<std::result::Result<_, _>>()
+        .ok()?;
+    let degree = numbers.first()?
+        + numbers.get(1).unwrap_or(&0.0) / 60.0
+        + numbers.get(2).unwrap_or(&0.0) / 3600.0;
+    match direction.to_ascii_uppercase() {
+        'S' | 'W' => Some(-degree),
+        'N' | 'E' => Some(degree),
+        _ => None,
+    }
+}
+
+/// 从Photoshop图像资源（JPEG的APP13）中找到IPTC数据
+fn find_photoshop_iptc(bytes: &[u8]) -> Option<Vec<u8>> {
+    let start = find(bytes, b"8BIM\x04\x04")? + 6;
+    // 资源名称是Pascal字符串，长度补齐为偶数
+    let name = *bytes.get(start)? as usize;
+    let offset = start + (name + 2) / 2 * 2;
+    let size = u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?) as usize;
+    bytes.get(offset + 4..offset + 4 + size).map(|v| v.to_vec())
+}
+
+/// 解析IPTC-IIM，只读取应用记录（2）中的关键字、版权和创建时间
+fn read_iim(data: &[u8], exif: &mut Exif, keywords: &mut Vec<String>) {
+    let mut date = None;
+    let mut time = None;
+    let mut i = 0;
+    while i + 5 <= data.len() {
+        if data[i] != 0x1c {
+            i += 1;
+            continue;
+        }
+        let (record, dataset) = (data[i + 1], data[i + 2]);
+        let size = u16::from_be_bytes([data[i + 3], data[i + 4]]) as usize;
+        // 扩展长度的数据集不会出现在应用记录中
+        if size & 0x8000 != 0 || i + 5 + size > data.len() {
+            break;
+        }
+        let value = clean(&String::from_utf8_lossy(&data[i + 5..i + 5 + size]));
+        if record == 2 {
+            match dataset {
+                25 => keywords.push(value),
+                116 => fill(&mut exif.copyright, Some(value)),
+                55 => date = Some(value),
+                60 => time = Some(value),
+                _ => {}
+            }
+        }
+        i += 5 + size;
+    }
+    if let Some(date) = date {
+        let time = time.unwrap_or_default();
+        let time = time.get(..6).unwrap_or("000000");
+        fill(
+            &mut exif.captured,
+            NaiveDateTime::parse_from_str(&format!("{date}{time}"), "%Y%m%d%H%M%S")
+                .ok()
+                .map(|v| v.format("%Y-%m-%d %H:%M:%S").to_string()),
+        );
+    }
+}
+
+/// 统一日期格式，支持EXIF的`%Y:%m:%d %H:%M:%S`和XMP的ISO 8601
+fn normalize_date(value: &str) -> Option<String> {
+    let value = value.trim();
+    let datetime = value.get(..19).unwrap_or(value);
+    [
+        "%Y:%m:%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+    ]
+    .iter()
+    .find_map(|v| NaiveDateTime::parse_from_str(datetime, v).ok())
+    .or_else(|| {
+        let date = value.get(..10)?;
+        NaiveDateTime::parse_from_str(&format!("{date} 00:00:00"), "%Y-%m-%d %H:%M:%S").ok()
+    })
+    .map(|v| v.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// 快门速度，小于1秒时显示为分数
+fn shutter(seconds: f64) -> String {
+    if seconds > 0.0 && seconds < 1.0 {
+        format!("1/{}", (1.0 / seconds).round())
+    } else {
+        format!("{}", (seconds * 10.0).round() / 10.0)
+    }
+}
+
+fn parse_fraction(value: &str) -> Option<f64> {
+    let result = match value.split_once('/') {
+        Some((num, denom)) => num.trim().parse::<f64>().ok()? / denom.trim().parse::<f64>().ok()?,
+        None => value.trim().parse().ok()?,
+    };
+    result.is_finite().then_some(result)
+}
+
+fn value_bytes(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Undefined(v, _) | Value::Byte(v) => Some(v.clone()),
+        Value::Long(v) => Some(v.iter().flat_map(|v| v.to_be_bytes()).collect()),
+        _ => None,
+    }
+}
+
+fn find(bytes: &[u8], needle: &[u8]) -> Option<usize> {
+    bytes.windows(needle.len()).position(|v| v == needle)
+}
+
+fn clean(value: &str) -> String {
+    value
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string()
+}
+
+fn unescape(value: &str) -> String {
+    clean(
+        &value
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use exif::experimental::Writer;
+    use exif::{Field, In, Tag, Value};
+
+    use crate::db::entity::exif::Exif;
+    use crate::file::extract::{
+        normalize_date, parse_xmp_gps, read_exif, read_iim, read_xmp, shutter,
+    };
+
+    /// JPEG的APPn段
+    fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend((data.len() as u16 + 2).to_be_bytes());
+        segment.extend(data);
+        segment
+    }
+
+    #[test]
+    fn test_read_exif() {
+        let fields = [
+            Field {
+                tag: Tag::Make,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"Canon".to_vec()]),
+            },
+            Field {
+                tag: Tag::PhotographicSensitivity,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![200]),
+            },
+        ];
+        let mut writer = Writer::new();
+        fields.iter().for_each(|v| writer.push_field(v));
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+
+        let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description
+            tiff:Make="Nikon" aux:Lens="EF 50mm">
+            <dc:subject><rdf:Bag><rdf:li>cat</rdf:li></rdf:Bag></dc:subject>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let mut iim = Vec::new();
+        for (dataset, value) in [(25u8, "cat"), (25, "dog"), (116, "O'Brien")] {
+            iim.extend([0x1c, 2, dataset, 0, value.len() as u8]);
+            iim.extend(value.as_bytes());
+        }
+        let mut photoshop = b"Photoshop 3.0\08BIM\x04\x04\0\0".to_vec();
+        photoshop.extend((iim.len() as u32).to_be_bytes());
+        photoshop.extend(iim);
+
+        let mut data = vec![0xFF, 0xD8];
+        data.extend(segment(
+            0xE1,
+            &[b"Exif\0\0".as_slice(), &tiff.into_inner()].concat(),
+        ));
+        data.extend(segment(
+            0xE1,
+            &[b"http://ns.adobe.com/xap/1.0/\0".as_slice(), xmp.as_bytes()].concat(),
+        ));
+        data.extend(segment(0xED, &photoshop));
+        data.extend([0xFF, 0xD9]);
+        let path = std::env::temp_dir().join("pixel-basket-extract.jpg");
+        std::fs::write(&path, data).unwrap();
+
+        let exif = read_exif(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // EXIF优先，缺少的项由XMP和IPTC补充
+        assert_eq!(exif.make, "Canon");
+        assert_eq!(exif.iso, 200);
+        assert_eq!(exif.lens, "EF 50mm");
+        assert_eq!(exif.copyright, "O'Brien");
+        assert_eq!(exif.get_keywords(), vec!["cat", "dog"]);
+    }
+
+    #[test]
+    fn test_read_xmp() {
+        let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description
+            tiff:Make="Nikon" exif:FNumber="28/10" exif:ExposureTime="1/250"
+            exif:DateTimeOriginal="2023-05-01T10:20:30.12+08:00" exif:GPSLatitude="37,46.5S">
+            <dc:subject><rdf:Bag><rdf:li>cat</rdf:li><rdf:li>R&amp;D</rdf:li></rdf:Bag></dc:subject>
+            <dc:rights><rdf:Alt><rdf:li xml:lang="x-default">© O'Brien</rdf:li></rdf:Alt></dc:rights>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let mut exif = Exif::default();
+        let mut keywords = Vec::new();
+        read_xmp(xmp, &mut exif, &mut keywords);
+        assert_eq!(exif.make, "Nikon");
+        assert_eq!(exif.aperture, 2.8);
+        assert_eq!(exif.shutter, "1/250");
+        assert_eq!(exif.captured, "2023-05-01 10:20:30");
+        assert_eq!(exif.copyright, "© O'Brien");
+        assert_eq!(keywords, vec!["cat", "R&D"]);
+        assert!((exif.latitude.unwrap() + 37.775).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_read_iim() {
+        let mut data = Vec::new();
+        for (dataset, value) in [
+            (25u8, "cat"),
+            (25, "dog"),
+            (55, "20230501"),
+            (60, "102030+0800"),
+        ] {
+            data.extend([0x1c, 2, dataset, 0, value.len() as u8]);
+            data.extend(value.as_bytes());
+        }
+        let mut exif = Exif::default();
+        let mut keywords = Vec::new();
+        read_iim(&data, &mut exif, &mut keywords);
+        assert_eq!(keywords, vec!["cat", "dog"]);
+        assert_eq!(exif.captured, "2023-05-01 10:20:30");
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            normalize_date("2023:05:01 10:20:30").unwrap(),
+            "2023-05-01 10:20:30"
+        );
+        assert_eq!(normalize_date("2023-05-01").unwrap(), "2023-05-01 00:00:00");
+        assert_eq!(normalize_date("0000:00:00 00:00:00"), None);
+        assert_eq!(shutter(0.004), "1/250");
+        assert_eq!(shutter(2.5), "2.5");
+        assert_eq!(parse_xmp_gps("120,30E"), Some(120.5));
+    }
+}
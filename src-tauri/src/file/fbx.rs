@@ -0,0 +1,464 @@
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+use regex::Regex;
+
+use crate::file::model::{Mesh, UpAxis};
+use crate::Result;
+
+const BINARY_MAGIC: &[u8] = b"Kaydara FBX Binary  \0";
+
+/// FBX节点
+#[derive(Debug, Default)]
+struct Node {
+    name: String,
+    properties: Vec<Property>,
+    children: Vec<Node>,
+}
+
+/// 节点属性，只保留解析模型需要的字符串和数组
+#[derive(Debug)]
+enum Property {
+    String(String),
+    Integers(Vec<i64>),
+    Floats(Vec<f64>),
+    Other,
+}
+
+impl Node {
+    fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|v| v.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Node> {
+        self.children.iter().filter(move |v| v.name == name)
+    }
+
+    fn string(&self, index: usize) -> Option<&str> {
+        match self.properties.get(index)? {
+            Property::String(v) => Some(v.as_str()),
+            _ => None,
+        }
+    }
+
+    /// 对象名称，二进制格式中为`名称\0\x01类型`
+    fn object_name(&self) -> Option<&str> {
+        self.string(1)
+            .map(|v| v.split("\0\x01").next().unwrap_or(v))
+    }
+
+    fn floats(&self) -> Vec<f64> {
+        match self.properties.first() {
+            Some(Property::Floats(v)) => v.clone(),
+            Some(Property::Integers(v)) => v.iter().map(|v| *v as f64).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn integers(&self) -> Vec<i64> {
+        match self.properties.first() {
+            Some(Property::Integers(v)) => v.clone(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// 解析FBX文件，支持二进制和ASCII格式
+pub fn read_fbx(data: &[u8]) -> Result<Mesh> {
+    let mut mesh = Mesh {
+        up: Some(UpAxis::Y),
+        ..Mesh::default()
+    };
+    if data.starts_with(BINARY_MAGIC) {
+        let nodes = parse_binary(data)?;
+        let Some(objects) = nodes.iter().find(|v| v.name == "Objects") else {
+            return Ok(mesh);
+        };
+        for geometry in objects.children("Geometry") {
+            if geometry.string(2) != Some("Mesh") {
+                continue;
+            }
+            let vertices = geometry
+                .child("Vertices")
+                .map(Node::floats)
+                .unwrap_or_default();
+            let polygons = geometry
+                .child("PolygonVertexIndex")
+                .map(Node::integers)
+                .unwrap_or_default();
+            append(&mut mesh, &vertices, &polygons);
+        }
+        for material in objects.children("Material") {
+            mesh.materials
+                .extend(material.object_name().map(str::to_string));
+        }
+        for texture in objects.children("Texture") {
+            let file = ["RelativeFilename", "FileName"]
+                .iter()
+                .filter_map(|v| texture.child(v)?.string(0))
+                .find(|v| !v.is_empty());
+            mesh.textures.extend(file.map(str::to_string));
+        }
+        mesh.animated = objects.child("AnimationCurve").is_some();
+    } else {
+        read_ascii(&String::from_utf8_lossy(data), &mut mesh)?;
+    }
+    Ok(mesh)
+}
+
+/// 追加一个几何体，多边形的最后一个顶点下标按位取反存储
+fn append(mesh: &mut Mesh, vertices: &[f64], polygons: &[i64]) {
+    let positions = vertices
+        .chunks_exact(3)
+        .map(|v| [v[0] as f32, v[1] as f32, v[2] as f32]);
+    let mut indices = Vec::new();
+    let mut polygon = Vec::new();
+    for index in polygons {
+        let end = *index < 0;
+        polygon.push(if end { !*index } else { *index } as u32);
+        if end {
+            for i in 1..polygon.len().saturating_sub(1) {
+                indices.extend([polygon[0], polygon[i], polygon[i + 1]]);
+            }
+            polygon.clear();
+        }
+    }
+    mesh.append(positions, &indices);
+}
+
+/// 解析ASCII格式，只提取几何体、材质、贴图和动画
+fn read_ascii(text: &str, mesh: &mut Mesh) -> Result<()> {
+    let array = |name: &str| -> Result<Vec<Vec<f64>>> {
+        let re = Regex::new(&format!(
+            r"\b{name}:\s*(?:\*\d+\s*\{{\s*a:)?([-+0-9.eE,\s]*)"
+        ))?;
+        Ok(re
+            .captures_iter(text)
+            .map(|v| {
+                v[1].split(|c: char| c == ',' || c.is_whitespace())
+                    .filter_map(|v| v.parse().ok())
+                    .collect()
+            })
+            .collect())
+    };
+    let vertices = array("Vertices")?;
+    let polygons = array("PolygonVertexIndex")?;
+    for (vertices, polygons) in vertices.iter().zip(polygons.iter()) {
+        let polygons: Vec<i64> = polygons.iter().map(|v| *v as i64).collect();
+        append(mesh, vertices, &polygons);
+    }
+    let material = Regex::new(r#""Material::([^"]*)""#)?;
+    mesh.materials
+        .extend(material.captures_iter(text).map(|v| v[1].to_string()));
+    let texture = Regex::new(r#"\bRelativeFilename:\s*"([^"]*)""#)?;
+    mesh.textures
+        .extend(texture.captures_iter(text).map(|v| v[1].to_string()));
+    mesh.animated = text.contains("AnimationCurve:");
+    Ok(())
+}
+
+/// 二进制格式读取位置
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// 7.5及以上版本的偏移量为64位
+    wide: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).ok_or("FBX数据不完整")?;
+        let bytes = self.data.get(self.pos..end).ok_or("FBX数据不完整")?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into()?)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn offset(&mut self) -> Result<usize> {
+        Ok(if self.wide {
+            u64::from_le_bytes(self.array()?) as usize
+        } else {
+            self.u32()? as usize
+        })
+    }
+}
+
+fn parse_binary(data: &[u8]) -> Result<Vec<Node>> {
+    let mut reader = Reader {
+        data,
+        pos: BINARY_MAGIC.len() + 2,
+        wide: false,
+    };
+    reader.wide = reader.u32()? >= 7500;
+    let mut nodes = Vec::new();
+    while let Some(node) = parse_node(&mut reader)? {
+        nodes.push(node);
+    }
+    Ok(nodes)
+}
+
+/// 读取一个节点，遇到表示列表结束的空节点时返回`None`
+fn parse_node(reader: &mut Reader) -> Result<Option<Node>> {
+    if reader.pos >= reader.data.len() {
+        return Ok(None);
+    }
+    let end = reader.offset()?;
+    let count = reader.offset()?;
+    let _length = reader.offset()?;
+    let name_length = reader.bytes(1)?[0] as usize;
+    if end == 0 {
+        return Ok(None);
+    }
+    if end > reader.data.len() || end < reader.pos {
+        return Err("FBX节点偏移量错误".into());
+    }
+    let mut node = Node {
+        name: String::from_utf8_lossy(reader.bytes(name_length)?).to_string(),
+        ..Node::default()
+    };
+    for _ in 0..count {
+        node.properties.push(parse_property(reader)?);
+    }
+    while reader.pos < end {
+        match parse_node(reader)? {
+            Some(child) => node.children.push(child),
+            None => break,
+        }
+    }
+    reader.pos = end;
+    Ok(Some(node))
+}
+
+fn parse_property(reader: &mut Reader) -> Result<Property> {
+    let kind = reader.bytes(1)?[0];
+    Ok(match kind {
+        b'C' => {
+            reader.bytes(1)?;
+            Property::Other
+        }
+        b'Y' => {
+            reader.bytes(2)?;
+            Property::Other
+        }
+        b'I' | b'F' => {
+            reader.bytes(4)?;
+            Property::Other
+        }
+        b'L' | b'D' => {
+            reader.bytes(8)?;
+            Property::Other
+        }
+        b'S' => {
+            let length = reader.u32()? as usize;
+            Property::String(String::from_utf8_lossy(reader.bytes(length)?).to_string())
+        }
+        b'R' => {
+            let length = reader.u32()? as usize;
+            reader.bytes(length)?;
+            Property::Other
+        }
+        b'f' | b'd' | b'i' | b'l' | b'b' | b'c' => {
+            let count = reader.u32()? as usize;
+            let encoding = reader.u32()?;
+            let length = reader.u32()? as usize;
+            let bytes = reader.bytes(length)?;
+            let bytes = if encoding == 1 {
+                let mut buffer = Vec::new();
+                ZlibDecoder::new(bytes).read_to_end(&mut buffer)?;
+                buffer
+            } else {
+                bytes.to_vec()
+            };
+            match kind {
+                b'f' => Property::Floats(
+                    bytes
+                        .chunks_exact(4)
+                        .take(count)
+                        .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]) as f64)
+                        .collect(),
+                ),
+                b'd' => Property::Floats(
+                    bytes
+                        .chunks_exact(8)
+                        .take(count)
+                        .filter_map(|v| Some(f64::from_le_bytes(v.try_into().ok()?)))
+                        .collect(),
+                ),
+                b'i' => Property::Integers(
+                    bytes
+                        .chunks_exact(4)
+                        .take(count)
+                        .map(|v| i32::from_le_bytes([v[0], v[1], v[2], v[3]]) as i64)
+                        .collect(),
+                ),
+                b'l' => Property::Integers(
+                    bytes
+                        .chunks_exact(8)
+                        .take(count)
+                        .filter_map(|v| Some(i64::from_le_bytes(v.try_into().ok()?)))
+                        .collect(),
+                ),
+                _ => Property::Integers(bytes.iter().take(count).map(|v| *v as i64).collect()),
+            }
+        }
+        _ => return Err(format!("未知的FBX属性类型：{}", kind as char).into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    use crate::file::fbx::{read_fbx, BINARY_MAGIC};
+
+    /// 生成7.4版本的节点记录
+    fn node(name: &str, properties: &[u8], count: u32, children: &[u8], start: usize) -> Vec<u8> {
+        let header = 13 + name.len();
+        let mut end = start + header + properties.len() + children.len();
+        let null = !children.is_empty();
+        if null {
+            end += 13;
+        }
+        let mut data = Vec::new();
+        data.extend((end as u32).to_le_bytes());
+        data.extend(count.to_le_bytes());
+        data.extend((properties.len() as u32).to_le_bytes());
+        data.push(name.len() as u8);
+        data.extend(name.as_bytes());
+        data.extend(properties);
+        data.extend(children);
+        if null {
+            data.extend([0u8; 13]);
+        }
+        data
+    }
+
+    fn id(value: i64) -> Vec<u8> {
+        let mut data = vec![b'L'];
+        data.extend(value.to_le_bytes());
+        data
+    }
+
+    fn string(value: &str) -> Vec<u8> {
+        let mut data = vec![b'S'];
+        data.extend((value.len() as u32).to_le_bytes());
+        data.extend(value.as_bytes());
+        data
+    }
+
+    #[test]
+    fn test_read_binary() {
+        let mut data = BINARY_MAGIC.to_vec();
+        data.extend([0x1A, 0x00]);
+        data.extend(7400u32.to_le_bytes());
+
+        let mut vertices = vec![b'd'];
+        let values: Vec<u8> = [
+            0.0f64, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0,
+        ]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+        vertices.extend(12u32.to_le_bytes());
+        vertices.extend(0u32.to_le_bytes());
+        vertices.extend((values.len() as u32).to_le_bytes());
+        vertices.extend(values);
+
+        let mut polygons = vec![b'i'];
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        for v in [0i32, 1, 2, !3] {
+            encoder.write_all(&v.to_le_bytes()).unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+        polygons.extend(4u32.to_le_bytes());
+        polygons.extend(1u32.to_le_bytes());
+        polygons.extend((compressed.len() as u32).to_le_bytes());
+        polygons.extend(compressed);
+
+        let objects_start = data.len();
+        let geometry_properties = [id(1), string("Cube\0\x01Geometry"), string("Mesh")].concat();
+        let geometry_start = objects_start + 13 + "Objects".len();
+        let children_start = geometry_start + 13 + "Geometry".len() + geometry_properties.len();
+        let vertices = node("Vertices", &vertices, 1, &[], children_start);
+        let polygons = node(
+            "PolygonVertexIndex",
+            &polygons,
+            1,
+            &[],
+            children_start + vertices.len(),
+        );
+        let geometry = node(
+            "Geometry",
+            &geometry_properties,
+            3,
+            &[vertices, polygons].concat(),
+            geometry_start,
+        );
+        let material_start = geometry_start + geometry.len();
+        let material = node(
+            "Material",
+            &[id(2), string("Wood\0\x01Material"), string("")].concat(),
+            3,
+            &[],
+            material_start,
+        );
+        let objects = node(
+            "Objects",
+            &[],
+            0,
+            &[geometry, material].concat(),
+            objects_start,
+        );
+        data.extend(objects);
+        data.extend([0u8; 13]);
+
+        let mesh = read_fbx(&data).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.materials, vec!["Wood"]);
+        assert!(!mesh.animated);
+    }
+
+    #[test]
+    fn test_read_ascii() {
+        let text = r#"; FBX 7.4.0 project file
+Objects:  {
+	Geometry: 1, "Geometry::Cube", "Mesh" {
+		Vertices: *9 {
+			a: 0,0,0,1,0,0,
+0,1,0
+		}
+		PolygonVertexIndex: *3 {
+			a: 0,1,-3
+		}
+		Edges: *3 {
+			a: 0,1,2
+		}
+	}
+	Material: 2, "Material::Wood", "" {
+	}
+	Texture: 3, "Texture::Wood", "" {
+		FileName: "C:/textures/wood.png"
+		RelativeFilename: "textures\wood.png"
+	}
+	AnimationCurve: 4, "AnimCurve::", "" {
+	}
+}"#;
+        let mesh = read_fbx(text.as_bytes()).unwrap();
+        assert_eq!(mesh.vertices.len(), 3);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+        assert_eq!(mesh.materials, vec!["Wood"]);
+        assert_eq!(mesh.textures, vec!["textures\\wood.png"]);
+        assert!(mesh.animated);
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+
+use crate::db::entity::video::{AudioTrack, Chapter, VideoInfo};
+use crate::Result;
+
+/// `ffprobe -print_format json`的输出，只包含用到的字段
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Probe {
+    pub format: Format,
+    pub streams: Vec<Stream>,
+    pub chapters: Vec<ProbeChapter>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Format {
+    pub format_name: String,
+    pub duration: Option<String>,
+    pub bit_rate: Option<String>,
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Stream {
+    pub codec_type: String,
+    pub codec_name: String,
+    pub width: i64,
+    pub height: i64,
+    pub avg_frame_rate: String,
+    pub r_frame_rate: String,
+    pub channels: i64,
+    pub sample_rate: Option<String>,
+    pub disposition: HashMap<String, i64>,
+    pub tags: HashMap<String, String>,
+    pub side_data_list: Vec<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ProbeChapter {
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub tags: HashMap<String, String>,
+}
+
+/// 执行外部命令，命令不存在时返回明确的错误信息
+pub fn run(program: &str, command: &mut Command) -> Result<Output> {
+    let output = command.output().map_err(|e| match e.kind() {
+        ErrorKind::NotFound => format!("未找到{program}，请安装FFmpeg并添加到PATH"),
+        _ => format!("{program}执行失败：{e}"),
+    })?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{program}执行失败：{}", error.trim()).into());
+    }
+    Ok(output)
+}
+
+/// 读取视频的格式、流和章节信息
+pub fn probe(path: &Path) -> Result<Probe> {
+    let output = run(
+        "ffprobe",
+        Command::new("ffprobe")
+            .args([
+                "-v",
+                "error",
+                "-print_format",
+                "json",
+                "-show_format",
+                "-show_streams",
+                "-show_chapters",
+            ])
+            .arg(path),
+    )?;
+    Probe::parse(&output.stdout)
+}
+
+impl Probe {
+    pub fn parse(json: &[u8]) -> Result<Probe> {
+        if json.iter().all(u8::is_ascii_whitespace) {
+            return Err("ffprobe没有输出".into());
+        }
+        Ok(serde_json::from_slice(json)?)
+    }
+
+    /// 第一个视频流，跳过作为封面的图片
+    fn video(&self) -> Option<&Stream> {
+        self.streams.iter().find(|v| {
+            v.codec_type == "video" && v.disposition.get("attached_pic").copied() != Some(1)
+        })
+    }
+
+    /// 时长（毫秒）
+    pub fn duration(&self) -> i64 {
+        self.format
+            .duration
+            .as_deref()
+            .and_then(|v| v.parse::<f64>().ok())
+            .map_or(0, |v| (v * 1000f64) as i64)
+    }
+
+    /// 旋转后的显示尺寸
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        let video = self.video()?;
+        let (width, height) = (video.width as u32, video.height as u32);
+        if width == 0 || height == 0 {
+            return None;
+        }
+        Some(match rotation(video) {
+            90 | 270 => (height, width),
+            _ => (width, height),
+        })
+    }
+
+    pub fn info(&self) -> VideoInfo {
+        let mut info = VideoInfo {
+            container: self.format.format_name.clone(),
+            bitrate: self
+                .format
+                .bit_rate
+                .as_deref()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            creation_time: self
+                .format
+                .tags
+                .get("creation_time")
+                .or_else(|| self.video().and_then(|v| v.tags.get("creation_time")))
+                .map(|v| normalize_time(v))
+                .unwrap_or_default(),
+            ..VideoInfo::default()
+        };
+        if let Some(video) = self.video() {
+            info.video_codec = video.codec_name.clone();
+            info.width = video.width;
+            info.height = video.height;
+            info.frame_rate = parse_rate(&video.avg_frame_rate)
+                .or_else(|| parse_rate(&video.r_frame_rate))
+                .unwrap_or(0.0);
+            info.rotation = rotation(video);
+        }
+        let tracks: Vec<AudioTrack> = self
+            .streams
+            .iter()
+            .filter(|v| v.codec_type == "audio")
+            .map(|v| AudioTrack {
+                codec: v.codec_name.clone(),
+                channels: v.channels,
+                sample_rate: v
+                    .sample_rate
+                    .as_deref()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0),
+                language: v.tags.get("language").cloned().unwrap_or_default(),
+            })
+            .collect();
+        info.set_audio_tracks(&tracks);
+        let seconds = |v: &Option<String>| {
+            v.as_deref()
+                .and_then(|v| v.parse::<f64>().ok())
+                .map_or(0, |v| (v * 1000f64) as i64)
+        };
+        let chapters: Vec<Chapter> = self
+            .chapters
+            .iter()
+            .map(|v| Chapter {
+                title: v.tags.get("title").cloned().unwrap_or_default(),
+                start: seconds(&v.start_time),
+                end: seconds(&v.end_time),
+            })
+            .collect();
+        info.set_chapters(&chapters);
+        info
+    }
+}
+
+/// 解析`30000/1001`格式的帧率
+fn parse_rate(rate: &str) -> Option<f64> {
+    let (num, den) = rate.split_once('/')?;
+    let (num, den): (f64, f64) = (num.parse().ok()?, den.parse().ok()?);
+    if num > 0.0 && den > 0.0 {
+        Some(num / den)
+    } else {
+        None
+    }
+}
+
+/// 顺时针旋转角度，旧版本写在`rotate`标签中，新版本写在显示矩阵中（逆时针）
+fn rotation(stream: &Stream) -> i64 {
+    let degrees = stream
+        .tags
+        .get("rotate")
+        .and_then(|v| v.parse::<f64>().ok())
+        .or_else(|| {
+            stream
+                .side_data_list
+                .iter()
+                .find_map(|v| v.get("rotation")?.as_f64())
+                .map(|v| -v)
+        })
+        .unwrap_or(0.0);
+    ((degrees.round() as i64 % 360) + 360) % 360
+}
+
+/// 转换为本地时间，无法解析时保留原始值
+fn normalize_time(value: &str) -> String {
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(time) => time
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string(),
+        Err(_) => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::ffprobe::Probe;
+
+    #[test]
+    fn test_parse() {
+        let json = r#"{
+            "streams": [
+                {"codec_type": "video", "codec_name": "mjpeg", "width": 320, "height": 240, "disposition": {"attached_pic": 1}},
+                {"codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080, "avg_frame_rate": "30000/1001", "r_frame_rate": "30/1",
+                 "disposition": {"attached_pic": 0}, "side_data_list": [{"side_data_type": "Display Matrix", "rotation": -90}]},
+                {"codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000", "tags": {"language": "eng"}}
+            ],
+            "chapters": [{"start_time": "0.000000", "end_time": "12.500000", "tags": {"title": "开场"}}],
+            "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "62.040000", "bit_rate": "8000000"}
+        }"#;
+        let probe = Probe::parse(json.as_bytes()).unwrap();
+        assert_eq!(probe.duration(), 62040);
+        assert_eq!(probe.dimensions(), Some((1080, 1920)));
+        let info = probe.info();
+        assert_eq!(info.video_codec, "h264");
+        assert_eq!(info.rotation, 90);
+        assert!((info.frame_rate - 29.97).abs() < 0.01);
+        assert_eq!(info.bitrate, 8000000);
+        assert_eq!(info.get_audio_tracks()[0].sample_rate, 48000);
+        assert_eq!(info.get_chapters()[0].end, 12500);
+        assert!(Probe::parse(b"").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::Local;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// 扫描任务状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Running,
+    Paused,
+    Canceled,
+}
+
+/// 扫描任务信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: String,
+    pub state: JobState,
+    pub started: String,
+}
+
+struct JobEntry {
+    info: JobInfo,
+    state: watch::Sender<JobState>,
+}
+
+/// 正在运行的扫描任务，以`ScanJob.id`为键
+static JOBS: Lazy<Mutex<HashMap<String, JobEntry>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 扫描任务的控制令牌，释放时从注册表中移除
+pub struct JobToken {
+    id: String,
+    state: watch::Receiver<JobState>,
+}
+
+impl JobToken {
+    /// 注册扫描任务
+    pub fn register(id: &str) -> Self {
+        let (tx, rx) = watch::channel(JobState::Running);
+        if let Ok(mut jobs) = JOBS.lock() {
+            jobs.insert(
+                id.to_string(),
+                JobEntry {
+                    info: JobInfo {
+                        id: id.to_string(),
+                        kind: String::new(),
+                        state: JobState::Running,
+                        started: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                    },
+                    state: tx,
+                },
+            );
+        }
+        Self {
+            id: id.to_string(),
+            state: rx,
+        }
+    }
+
+    /// 设置任务类型，用于展示
+    pub fn set_kind(&self, kind: &str) {
+        if let Ok(mut jobs) = JOBS.lock() {
+            if let Some(job) = jobs.get_mut(&self.id) {
+                job.info.kind = kind.to_string();
+            }
+        }
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        *self.state.borrow() == JobState::Canceled
+    }
+
+    /// 检查点，暂停时等待恢复，任务被取消时返回`false`
+    pub async fn checkpoint(&mut self) -> bool {
+        loop {
+            let state = *self.state.borrow_and_update();
+            match state {
+                JobState::Running => return true,
+                JobState::Canceled => return false,
+                JobState::Paused => {}
+            }
+            if self.state.changed().await.is_err() {
+                return true;
+            }
+        }
+    }
+}
+
+impl Drop for JobToken {
+    fn drop(&mut self) {
+        if let Ok(mut jobs) = JOBS.lock() {
+            jobs.remove(&self.id);
+        }
+    }
+}
+
+/// 获取所有正在运行的扫描任务
+pub fn list() -> Vec<JobInfo> {
+    if let Ok(jobs) = JOBS.lock() {
+        let mut list: Vec<JobInfo> = jobs.values().map(|v| v.info.clone()).collect();
+        list.sort_by(|a, b| a.started.cmp(&b.started));
+        return list;
+    }
+    Vec::new()
+}
+
+pub fn get(id: &str) -> Option<JobInfo> {
+    JOBS.lock().ok()?.get(id).map(|v| v.info.clone())
+}
+
+/// 修改扫描任务状态，已取消的任务不能恢复
+pub fn set_state(id: &str, state: JobState) -> bool {
+    if let Ok(mut jobs) = JOBS.lock() {
+        if let Some(job) = jobs.get_mut(id) {
+            if job.info.state != JobState::Canceled {
+                job.info.state = state;
+                job.state.send_replace(state);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::file::job::{get, list, set_state, JobState, JobToken};
+
+    #[tokio::test]
+    async fn test_job_token() {
+        let mut token = JobToken::register("test-job");
+        token.set_kind("rescan");
+        assert_eq!(get("test-job").unwrap().kind, "rescan");
+        assert!(list().iter().any(|v| v.id == "test-job"));
+        assert!(token.checkpoint().await);
+
+        // 暂停时等待恢复
+        assert!(set_state("test-job", JobState::Paused));
+        let handle = tokio::spawn(async move {
+            let running = token.checkpoint().await;
+            (token, running)
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!handle.is_finished());
+        assert!(set_state("test-job", JobState::Running));
+        let (mut token, running) = handle.await.unwrap();
+        assert!(running);
+
+        // 取消后不能恢复
+        assert!(set_state("test-job", JobState::Canceled));
+        assert!(!set_state("test-job", JobState::Running));
+        assert!(token.is_canceled());
+        assert!(!token.checkpoint().await);
+
+        drop(token);
+        assert!(get("test-job").is_none());
+        assert!(!set_state("test-job", JobState::Running));
+    }
+}
@@ -1,6 +1,21 @@
+pub mod dedupe;
+pub mod extract;
+pub mod fbx;
+pub mod ffprobe;
 pub mod image_scanner;
+pub mod job;
+pub mod model;
 pub mod model_scanner;
+pub mod phash;
+pub mod preview;
+pub mod progress;
 pub mod scan;
+pub mod sidecar;
+pub mod thumbnail;
 pub mod video_scanner;
 pub mod raw_scanner;
 pub mod psd_scanner;
+pub mod registry;
+pub mod render;
+pub mod rescan;
+pub mod watch;
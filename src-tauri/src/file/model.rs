@@ -0,0 +1,500 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use base64::{engine::general_purpose, Engine as _};
+
+use crate::db::entity::model::ModelInfo;
+use crate::file::fbx;
+use crate::Result;
+
+/// 模型文件的向上方向
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+/// 解析后的模型，所有网格合并为一个，多边形已三角化
+#[derive(Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<[f32; 3]>,
+    pub triangles: Vec<[u32; 3]>,
+    /// 材质名称
+    pub materials: Vec<String>,
+    /// 引用的贴图路径
+    pub textures: Vec<String>,
+    /// 是否包含动画
+    pub animated: bool,
+    pub up: Option<UpAxis>,
+}
+
+impl Mesh {
+    /// 按后缀选择解析方式
+    pub fn load(path: &Path, suffix: &str) -> Result<Mesh> {
+        match suffix.to_lowercase().as_str() {
+            "obj" => read_obj(path),
+            "gltf" | "glb" => read_gltf(path),
+            "stl" => read_stl(path),
+            "ply" => read_ply(&std::fs::read(path)?),
+            "fbx" => fbx::read_fbx(&std::fs::read(path)?),
+            _ => Err(format!("不支持的模型格式：{suffix}").into()),
+        }
+    }
+
+    /// 追加一个网格，`indices`为三角形顶点在`vertices`中的下标
+    pub fn append(&mut self, vertices: impl IntoIterator<Item = [f32; 3]>, indices: &[u32]) {
+        let offset = self.vertices.len() as u32;
+        self.vertices.extend(vertices);
+        let count = self.vertices.len() as u32;
+        self.triangles.extend(
+            indices
+                .chunks_exact(3)
+                .map(|v| [v[0] + offset, v[1] + offset, v[2] + offset])
+                .filter(|v| v.iter().all(|i| *i < count)),
+        );
+    }
+
+    /// 包围盒的最小点和最大点，没有顶点时返回`None`
+    pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        let mut vertices = self
+            .vertices
+            .iter()
+            .filter(|v| v.iter().all(|x| x.is_finite()));
+        let first = *vertices.next()?;
+        Some(vertices.fold((first, first), |(mut min, mut max), v| {
+            for i in 0..3 {
+                min[i] = min[i].min(v[i]);
+                max[i] = max[i].max(v[i]);
+            }
+            (min, max)
+        }))
+    }
+
+    pub fn info(&self) -> ModelInfo {
+        let size = self.bounds().map_or([0.0; 3], |(min, max)| {
+            [max[0] - min[0], max[1] - min[1], max[2] - min[2]]
+        });
+        let mut info = ModelInfo {
+            vertex_count: self.vertices.len() as i64,
+            face_count: self.triangles.len() as i64,
+            size_x: size[0] as f64,
+            size_y: size[1] as f64,
+            size_z: size[2] as f64,
+            animated: self.animated,
+            ..ModelInfo::default()
+        };
+        info.set_materials(&dedup(&self.materials));
+        info.set_textures(&dedup(&self.textures));
+        info
+    }
+}
+
+/// 去除空值和重复值，保留原来的顺序
+fn dedup(values: &[String]) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    for value in values {
+        if !value.is_empty() && !result.contains(value) {
+            result.push(value.clone());
+        }
+    }
+    result
+}
+
+fn read_obj(path: &Path) -> Result<Mesh> {
+    let options = tobj::LoadOptions {
+        triangulate: true,
+        ignore_points: true,
+        ignore_lines: true,
+        ..tobj::LoadOptions::default()
+    };
+    let (models, materials) = tobj::load_obj(path, &options)?;
+    let mut mesh = Mesh {
+        up: Some(UpAxis::Y),
+        ..Mesh::default()
+    };
+    for model in models {
+        let positions = model
+            .mesh
+            .positions
+            .chunks_exact(3)
+            .map(|v| [v[0], v[1], v[2]]);
+        mesh.append(positions, &model.mesh.indices);
+    }
+    // 找不到mtl文件时忽略材质
+    for material in materials.unwrap_or_default() {
+        mesh.textures.extend(
+            [
+                material.diffuse_texture,
+                material.ambient_texture,
+                material.specular_texture,
+                material.normal_texture,
+                material.shininess_texture,
+                material.dissolve_texture,
+            ]
+            .into_iter()
+            .flatten(),
+        );
+        mesh.materials.push(material.name);
+    }
+    Ok(mesh)
+}
+
+fn read_gltf(path: &Path) -> Result<Mesh> {
+    let gltf = gltf::Gltf::from_slice(&std::fs::read(path)?)?;
+    let document = gltf.document;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf.blob.clone().unwrap_or_default(),
+            gltf::buffer::Source::Uri(uri) => read_uri(base, uri)?,
+        };
+        buffers.push(data);
+    }
+
+    let mut mesh = Mesh {
+        up: Some(UpAxis::Y),
+        ..Mesh::default()
+    };
+    let mut append = |node_mesh: gltf::Mesh, matrix: &[[f32; 4]; 4]| {
+        for primitive in node_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|v| buffers.get(v.index()).map(|v| v.as_slice()));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions: Vec<[f32; 3]> = positions.map(|v| transform(matrix, v)).collect();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            mesh.append(positions, &indices);
+        }
+    };
+    match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => {
+            let mut nodes: Vec<(gltf::Node, [[f32; 4]; 4])> =
+                scene.nodes().map(|v| (v, IDENTITY)).collect();
+            while let Some((node, parent)) = nodes.pop() {
+                let matrix = multiply(&parent, &node.transform().matrix());
+                if let Some(node_mesh) = node.mesh() {
+                    append(node_mesh, &matrix);
+                }
+                nodes.extend(node.children().map(|v| (v, matrix)));
+            }
+        }
+        None => document.meshes().for_each(|v| append(v, &IDENTITY)),
+    }
+
+    mesh.materials = document
+        .materials()
+        .filter_map(|v| v.name().map(str::to_string))
+        .collect();
+    mesh.textures = document
+        .images()
+        .map(|image| match image.source() {
+            gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => uri.to_string(),
+            _ => image
+                .name()
+                .map_or_else(|| format!("#{}", image.index()), str::to_string),
+        })
+        .collect();
+    mesh.animated = document.animations().next().is_some();
+    Ok(mesh)
+}
+
+/// 读取外部文件或`data:`内嵌的缓冲区
+fn read_uri(base: &Path, uri: &str) -> Result<Vec<u8>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, data) = data.split_once(";base64,").ok_or("不支持的data URI")?;
+        return Ok(general_purpose::STANDARD.decode(data)?);
+    }
+    let path = uri.replace("%20", " ");
+    Ok(std::fs::read(base.join(path))?)
+}
+
+const IDENTITY: [[f32; 4]; 4] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// 列主序矩阵相乘
+fn multiply(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let mut result = [[0.0; 4]; 4];
+    for (col, column) in result.iter_mut().enumerate() {
+        for (row, value) in column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[col][k]).sum();
+        }
+    }
+    result
+}
+
+fn transform(m: &[[f32; 4]; 4], v: [f32; 3]) -> [f32; 3] {
+    let mut result = [0.0; 3];
+    for (row, value) in result.iter_mut().enumerate() {
+        *value = m[0][row] * v[0] + m[1][row] * v[1] + m[2][row] * v[2] + m[3][row];
+    }
+    result
+}
+
+fn read_stl(path: &Path) -> Result<Mesh> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let stl = stl_io::read_stl(&mut reader)?;
+    let indices: Vec<u32> = stl
+        .faces
+        .iter()
+        .flat_map(|v| v.vertices.map(|i| i as u32))
+        .collect();
+    let mut mesh = Mesh {
+        up: Some(UpAxis::Z),
+        ..Mesh::default()
+    };
+    mesh.append(stl.vertices.iter().map(|v| v.0), &indices);
+    Ok(mesh)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    /// 列表属性的长度类型
+    count: Option<PlyType>,
+    kind: PlyType,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+/// PLY数据部分，按头部声明的类型依次读取
+enum PlyBody<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary {
+        data: &'a [u8],
+        pos: usize,
+        little: bool,
+    },
+}
+
+impl PlyBody<'_> {
+    fn read(&mut self, kind: PlyType) -> Option<f64> {
+        match self {
+            PlyBody::Ascii(tokens) => tokens.next()?.parse().ok(),
+            PlyBody::Binary { data, pos, little } => {
+                let bytes = data.get(*pos..*pos + kind.size())?;
+                *pos += kind.size();
+                let mut buffer = [0u8; 8];
+                buffer[..bytes.len()].copy_from_slice(bytes);
+                if !*little {
+                    buffer[..bytes.len()].reverse();
+                }
+                Some(match kind {
+                    PlyType::I8 => buffer[0] as i8 as f64,
+                    PlyType::U8 => buffer[0] as f64,
+                    PlyType::I16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    PlyType::U16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    PlyType::I32 => i32::from_le_bytes(buffer[..4].try_into().ok()?) as f64,
+                    PlyType::U32 => u32::from_le_bytes(buffer[..4].try_into().ok()?) as f64,
+                    PlyType::F32 => f32::from_le_bytes(buffer[..4].try_into().ok()?) as f64,
+                    PlyType::F64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+}
+
+fn read_ply(data: &[u8]) -> Result<Mesh> {
+    const END_HEADER: &[u8] = b"end_header";
+    let header_end = data
+        .windows(END_HEADER.len())
+        .position(|v| v == END_HEADER)
+        .ok_or("PLY文件缺少end_header")?;
+    let header = String::from_utf8_lossy(&data[..header_end]);
+    // 数据从end_header所在行的下一行开始
+    let body_start = data[header_end..]
+        .iter()
+        .position(|v| *v == b'\n')
+        .map_or(data.len(), |v| header_end + v + 1);
+
+    let mut format = "";
+    let mut mesh = Mesh::default();
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in header.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, ..] => format = *name,
+            ["comment", "TextureFile", file @ ..] => mesh.textures.push(file.join(" ")),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse()?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, kind, name] => {
+                if let Some(element) = elements.last_mut() {
+                    element.properties.push(PlyProperty {
+                        name: name.to_string(),
+                        count: Some(PlyType::parse(count).ok_or("未知的PLY类型")?),
+                        kind: PlyType::parse(kind).ok_or("未知的PLY类型")?,
+                    });
+                }
+            }
+            ["property", kind, name] => {
+                if let Some(element) = elements.last_mut() {
+                    element.properties.push(PlyProperty {
+                        name: name.to_string(),
+                        count: None,
+                        kind: PlyType::parse(kind).ok_or("未知的PLY类型")?,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let body = &data[body_start..];
+    let text;
+    let mut body = match format {
+        "ascii" => {
+            text = String::from_utf8_lossy(body);
+            PlyBody::Ascii(text.split_ascii_whitespace())
+        }
+        "binary_little_endian" => PlyBody::Binary {
+            data: body,
+            pos: 0,
+            little: true,
+        },
+        "binary_big_endian" => PlyBody::Binary {
+            data: body,
+            pos: 0,
+            little: false,
+        },
+        _ => return Err(format!("不支持的PLY格式：{format}").into()),
+    };
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for element in elements.iter() {
+        for _ in 0..element.count {
+            let mut vertex = [0f32; 3];
+            for property in element.properties.iter() {
+                match property.count {
+                    Some(count) => {
+                        let count = body.read(count).ok_or("PLY数据不完整")? as usize;
+                        let mut list = Vec::new();
+                        for _ in 0..count {
+                            list.push(body.read(property.kind).ok_or("PLY数据不完整")? as u32);
+                        }
+                        if element.name == "face" && property.name.starts_with("vertex_ind") {
+                            // 多边形按扇形三角化
+                            for i in 1..list.len().saturating_sub(1) {
+                                indices.extend([list[0], list[i], list[i + 1]]);
+                            }
+                        }
+                    }
+                    None => {
+                        let value = body.read(property.kind).ok_or("PLY数据不完整")?;
+                        if element.name == "vertex" {
+                            match property.name.as_str() {
+                                "x" => vertex[0] = value as f32,
+                                "y" => vertex[1] = value as f32,
+                                "z" => vertex[2] = value as f32,
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            }
+            if element.name == "vertex" {
+                vertices.push(vertex);
+            }
+        }
+    }
+    mesh.append(vertices, &indices);
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::model::{read_ply, Mesh};
+
+    #[test]
+    fn test_read_ply() {
+        let ascii = "ply\nformat ascii 1.0\ncomment TextureFile wood.png\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n2 0 0\n2 1 0\n0 1 3\n4 0 1 2 3\n";
+        let mesh = read_ply(ascii.as_bytes()).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.textures, vec!["wood.png"]);
+        assert_eq!(mesh.bounds(), Some(([0.0; 3], [2.0, 1.0, 3.0])));
+
+        let mut binary = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\nproperty double x\nproperty double y\nproperty double z\nelement face 1\nproperty list uchar uint vertex_index\nend_header\n".to_vec();
+        for v in [0.0f64, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            binary.extend(v.to_be_bytes());
+        }
+        binary.push(3);
+        for i in [0u32, 1, 2] {
+            binary.extend(i.to_be_bytes());
+        }
+        let mesh = read_ply(&binary).unwrap();
+        assert_eq!(mesh.vertices[1], [1.0, 0.0, 0.0]);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn test_info() {
+        let mut mesh = Mesh::default();
+        mesh.append(
+            [[0.0, 0.0, 0.0], [1.0, 2.0, 0.0], [1.0, 0.0, 4.0]],
+            &[0, 1, 2, 0, 1, 9],
+        );
+        mesh.materials = vec![String::from("a"), String::new(), String::from("a")];
+        let info = mesh.info();
+        assert_eq!(info.face_count, 1);
+        assert_eq!((info.size_x, info.size_y, info.size_z), (1.0, 2.0, 4.0));
+        assert_eq!(info.get_materials(), vec!["a"]);
+    }
+}
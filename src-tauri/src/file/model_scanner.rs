@@ -1,43 +1,62 @@
 use std::path::Path;
 
+use image::DynamicImage;
+
 use crate::db::entity::metadata::Metadata;
 use crate::db::entity::task::{Task, TaskStatus};
+use crate::file::model::Mesh;
+use crate::file::registry::ScannerOptions;
+use crate::file::render::render;
 use crate::file::scan::{Context, Scanner};
+use crate::file::thumbnail::{save_thumbnails, ThumbnailSize};
+use crate::Result;
 
-pub struct ModelScanner {}
+pub struct ModelScanner {
+    options: ScannerOptions,
+}
 
 impl ModelScanner {
-    pub fn wrap() -> Box<Self> {
-        Box::new(ModelScanner {})
+    pub fn wrap(options: ScannerOptions) -> Box<Self> {
+        Box::new(ModelScanner { options })
     }
 }
 
 impl Scanner for ModelScanner {
-    fn is_support(&self, suffix: &str) -> bool {
-        match suffix {
-            "obj" | "fbx" => true,
-            _ => false,
-        }
+    fn options(&self) -> &ScannerOptions {
+        &self.options
     }
 
     fn scan(&self, task: &Task, context: &Context) -> TaskStatus {
         let mut status = TaskStatus::new(task.id);
         if self.is_support(task.file_suffix.as_str()) {
             let path = task.file_path.clone();
+            let size = self.options.thumbnail_size;
             let runtime = context.runtime.handle().clone();
             status.handle(runtime.clone().spawn_blocking(move || {
                 let path = Path::new(path.as_str());
                 let mut metadata = Metadata::load(path);
-                if metadata.analyze_metadata(path).is_ok() {
-                    // 使用阻塞线程防止数据丢失！
-                    runtime.block_on(async move {
-                        metadata.save_to_db().await;
-                    });
-                    return true;
-                };
-                false
+                metadata.analyze_metadata(path).map_err(|e| e.to_string())?;
+                analyze_model_metadata(path, &mut metadata, size).map_err(|e| e.to_string())?;
+                // 使用阻塞线程防止数据丢失！
+                runtime.block_on(async move {
+                    metadata.save_to_db().await;
+                });
+                Ok(())
             }));
         }
         status
     }
 }
+
+/// 解析模型的几何信息，渲染预览图
+fn analyze_model_metadata(path: &Path, metadata: &mut Metadata, size: u32) -> Result<()> {
+    let mesh = Mesh::load(path, &metadata.file_suffix)?;
+    // 按高分屏尺寸渲染，列表尺寸由缓存缩小生成
+    if let Some(image) = render(&mesh, ThumbnailSize::Retina.width(size)) {
+        save_thumbnails(&metadata.sha1, &DynamicImage::ImageRgb8(image), size)?;
+        // 模型没有像素尺寸，宽高保持为0，只记录预览图的比例
+        metadata.shape = String::from("1:1");
+    }
+    metadata.model = Some(mesh.info());
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use std::f64::consts::PI;
+
+use image::imageops::{grayscale, resize, FilterType};
+use image::{GrayImage, RgbImage};
+
+use crate::db::entity::image_hash::ImageHash;
+
+/// pHash使用的DCT尺寸
+const DCT_SIZE: usize = 32;
+/// 哈希取低频部分的尺寸，8x8共64位
+const HASH_SIZE: usize = 8;
+
+/// 计算感知哈希，输入为缩略图即可，结果与原图基本一致
+pub fn perceptual_hash(image: &RgbImage) -> ImageHash {
+    let gray = grayscale(image);
+    ImageHash {
+        metadata_id: 0,
+        ahash: average_hash(&gray) as i64,
+        dhash: difference_hash(&gray) as i64,
+        phash: dct_hash(&gray) as i64,
+    }
+}
+
+/// aHash：缩小到8x8，与平均亮度比较
+fn average_hash(gray: &GrayImage) -> u64 {
+    let small = resize(
+        gray,
+        HASH_SIZE as u32,
+        HASH_SIZE as u32,
+        FilterType::Triangle,
+    );
+    let pixels: Vec<f64> = small.pixels().map(|p| p.0[0] as f64).collect();
+    let mean = pixels.iter().sum::<f64>() / pixels.len() as f64;
+    to_bits(pixels.iter().map(|v| *v > mean))
+}
+
+/// dHash：缩小到9x8，比较相邻像素的亮度
+fn difference_hash(gray: &GrayImage) -> u64 {
+    let small = resize(
+        gray,
+        HASH_SIZE as u32 + 1,
+        HASH_SIZE as u32,
+        FilterType::Triangle,
+    );
+    to_bits((0..HASH_SIZE as u32).flat_map(|y| {
+        let small = &small;
+        (0..HASH_SIZE as u32)
+            .map(move |x| small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0])
+    }))
+}
+
+/// pHash：缩小到32x32做二维DCT，取左上角8x8的低频系数与中位数比较
+fn dct_hash(gray: &GrayImage) -> u64 {
+    let small = resize(gray, DCT_SIZE as u32, DCT_SIZE as u32, FilterType::Triangle);
+    let pixels: Vec<f64> = small.pixels().map(|p| p.0[0] as f64).collect();
+    let cos: Vec<f64> = (0..HASH_SIZE * DCT_SIZE)
+        .map(|i| {
+            let (u, x) = (i / DCT_SIZE, i % DCT_SIZE);
+            ((2 * x + 1) as f64 * u as f64 * PI / (2 * DCT_SIZE) as f64).cos()
+        })
+        .collect();
+    // 先按行变换，只保留需要的低频部分
+    let mut rows = vec![0.0; DCT_SIZE * HASH_SIZE];
+    for y in 0..DCT_SIZE {
+        for u in 0..HASH_SIZE {
+            rows[y * HASH_SIZE + u] = (0..DCT_SIZE)
+                .map(|x| pixels[y * DCT_SIZE + x] * cos[u * DCT_SIZE + x])
+                .sum();
+        }
+    }
+    let mut coefficients = vec![0.0; HASH_SIZE * HASH_SIZE];
+    for v in 0..HASH_SIZE {
+        for u in 0..HASH_SIZE {
+            coefficients[v * HASH_SIZE + u] = (0..DCT_SIZE)
+                .map(|y| rows[y * HASH_SIZE + u] * cos[v * DCT_SIZE + y])
+                .sum();
+        }
+    }
+    // 直流分量只反映整体亮度，不参与中位数计算
+    let mut sorted: Vec<f64> = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    to_bits(coefficients.iter().map(|v| *v > median))
+}
+
+fn to_bits(bits: impl Iterator<Item = bool>) -> u64 {
+    bits.fold(0u64, |hash, bit| (hash << 1) | bit as u64)
+}
+
+/// 两个哈希不同的位数
+pub fn hamming(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::imageops::{resize, FilterType};
+    use image::{Rgb, RgbImage};
+
+    use crate::file::phash::{hamming, perceptual_hash};
+
+    #[test]
+    fn test_perceptual_hash() {
+        // 左暗右亮的渐变加一个亮块
+        let image = RgbImage::from_fn(256, 192, |x, y| {
+            let v = if (64..128).contains(&x) && (32..96).contains(&y) {
+                255
+            } else {
+                (x * 200 / 256) as u8
+            };
+            Rgb([v, v, v])
+        });
+        let resized = resize(&image, 100, 75, FilterType::Triangle);
+        let flipped = image::imageops::flip_horizontal(&image);
+
+        let origin = perceptual_hash(&image);
+        let copy = perceptual_hash(&resized);
+        let other = perceptual_hash(&flipped);
+        assert!(hamming(origin.phash, copy.phash) <= 4);
+        assert!(hamming(origin.dhash, copy.dhash) <= 4);
+        assert!(hamming(origin.ahash, copy.ahash) <= 4);
+        assert!(hamming(origin.phash, other.phash) > 10);
+        assert!(hamming(origin.dhash, other.dhash) > 10);
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use image::{imageops, RgbImage};
+
+use crate::config::{get_cache_dir, PreviewConfig};
+use crate::file::ffprobe::run;
+use crate::Result;
+
+/// 横向拼接的雪碧图
+pub const SPRITE: &str = "sprite";
+/// 动画WebP
+pub const WEBP: &str = "webp";
+
+/// 动画WebP每秒播放的帧数
+const WEBP_RATE: u32 = 2;
+
+/// 预览文件路径，以文件的sha1命名，内容相同的文件共用同一个预览
+pub fn preview_path(sha1: &str, format: &str) -> PathBuf {
+    let suffix = if format == WEBP { "webp" } else { "jpg" };
+    get_cache_dir()
+        .join("preview")
+        .join(format!("{sha1}.{suffix}"))
+}
+
+/// 按配置生成视频预览，返回格式和帧数，未启用或视频过长时返回`None`
+///
+/// 相同内容的预览已存在时直接使用
+pub fn generate(
+    path: &Path,
+    sha1: &str,
+    duration: i64,
+    config: &PreviewConfig,
+) -> Result<Option<(String, u32)>> {
+    let seconds = duration as f64 / 1000f64;
+    if !config.enabled || config.frames == 0 || seconds <= 0.0 {
+        return Ok(None);
+    }
+    if config.max_duration > 0 && seconds > config.max_duration as f64 {
+        return Ok(None);
+    }
+    let format = if config.format == WEBP { WEBP } else { SPRITE };
+    let output = preview_path(sha1, format);
+    if output.exists() {
+        return Ok(Some((format.to_string(), config.frames)));
+    }
+    if let Some(dir) = output.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    if format == WEBP {
+        webp(path, &output, seconds, config)?;
+    } else {
+        sprite(path, &output, seconds, config)?;
+    }
+    Ok(Some((format.to_string(), config.frames)))
+}
+
+/// 均匀分布的截取时间，避开开头和结尾
+fn timestamps(seconds: f64, frames: u32) -> Vec<f64> {
+    (0..frames)
+        .map(|i| seconds * (i as f64 + 0.5) / frames as f64)
+        .collect()
+}
+
+/// 逐帧定位截图后横向拼接，定位截图不需要解码整个视频
+fn sprite(path: &Path, output: &Path, seconds: f64, config: &PreviewConfig) -> Result<()> {
+    let mut frames = Vec::new();
+    for time in timestamps(seconds, config.frames) {
+        let result = run(
+            "ffmpeg",
+            Command::new("ffmpeg")
+                .args(["-v", "error", "-ss", &format!("{time:.3}"), "-i"])
+                .arg(path)
+                .args([
+                    "-frames:v",
+                    "1",
+                    "-vf",
+                    &format!("scale={}:-2", config.width),
+                    "-f",
+                    "image2pipe",
+                    "-vcodec",
+                    "mjpeg",
+                    "-",
+                ]),
+        )?;
+        if !result.stdout.is_empty() {
+            frames.push(image::load_from_memory(&result.stdout)?.to_rgb8());
+        }
+    }
+    let image = tile(&frames, config.width, config.frames).ok_or("ffmpeg没有输出预览帧")?;
+    image.save_with_format(output, image::ImageFormat::Jpeg)?;
+    Ok(())
+}
+
+/// 拼接为一行，帧数不足时用最后一帧补齐，保证每帧位置固定
+fn tile(frames: &[RgbImage], width: u32, count: u32) -> Option<RgbImage> {
+    let height = frames.first()?.height();
+    let last = frames.last()?;
+    let mut image = RgbImage::new(width * count, height);
+    for i in 0..count {
+        let frame = frames.get(i as usize).unwrap_or(last);
+        let frame = if frame.dimensions() == (width, height) {
+            frame.clone()
+        } else {
+            imageops::resize(frame, width, height, imageops::FilterType::Triangle)
+        };
+        imageops::replace(&mut image, &frame, i as i64 * width as i64, 0);
+    }
+    Some(image)
+}
+
+/// 用ffmpeg直接输出动画WebP
+fn webp(path: &Path, output: &Path, seconds: f64, config: &PreviewConfig) -> Result<()> {
+    let filter = format!(
+        "fps={}/{seconds:.3},scale={}:-2,setpts=N/({WEBP_RATE}*TB)",
+        config.frames, config.width
+    );
+    run(
+        "ffmpeg",
+        Command::new("ffmpeg")
+            .args(["-v", "error", "-y", "-i"])
+            .arg(path)
+            .args([
+                "-vf",
+                &filter,
+                "-frames:v",
+                &config.frames.to_string(),
+                "-loop",
+                "0",
+                "-c:v",
+                "libwebp",
+                "-q:v",
+                "60",
+                "-f",
+                "webp",
+            ])
+            .arg(output),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use crate::file::preview::{tile, timestamps};
+
+    #[test]
+    fn test_tile() {
+        assert_eq!(timestamps(10.0, 4), vec![1.25, 3.75, 6.25, 8.75]);
+        let frames = vec![
+            RgbImage::from_pixel(4, 2, Rgb([255, 0, 0])),
+            RgbImage::from_pixel(8, 4, Rgb([0, 0, 255])),
+        ];
+        let image = tile(&frames, 4, 3).unwrap();
+        assert_eq!(image.dimensions(), (12, 2));
+        assert_eq!(image.get_pixel(5, 1), &Rgb([0, 0, 255]));
+        // 缺少的帧用最后一帧补齐
+        assert_eq!(image.get_pixel(9, 1), &Rgb([0, 0, 255]));
+        assert!(tile(&[], 4, 3).is_none());
+    }
+}
@@ -0,0 +1,236 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::file::job;
+use crate::file::rescan::RescanSummary;
+use crate::file::scan::ScanMsg;
+use crate::util::error::ErrorHandle;
+use crate::APP_HANDLE;
+
+/// 前端监听的事件名
+pub const TASK_EVENT: &str = "task";
+
+/// 进度事件的最小间隔，文件完成和失败的消息在间隔内合并发送
+const EMIT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// 每次事件最多携带的文件路径数
+const RECENT_LIMIT: usize = 50;
+
+/// 发送给前端的扫描事件，对应前端的`TaskEvent`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanEvent {
+    pub id: String,
+    pub stage: String,
+    pub r#type: String,
+    /// 进度百分比
+    pub progress: f64,
+    pub data: ScanProgress,
+}
+
+/// 扫描进度
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanProgress {
+    pub discovered: usize,
+    pub queued: usize,
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+    /// 每秒处理的文件数
+    pub throughput: f64,
+    /// 预计剩余秒数
+    pub eta: Option<u64>,
+    /// 上次事件之后完成的文件
+    pub recent_completed: Vec<String>,
+    /// 上次事件之后失败的文件
+    pub recent_failed: Vec<String>,
+    pub rescan: Option<RescanSummary>,
+}
+
+/// 汇总扫描消息并节流发送进度事件
+pub struct ProgressReporter {
+    id: String,
+    kind: String,
+    progress: ScanProgress,
+    started: Option<Instant>,
+    emitted: Instant,
+}
+
+impl ProgressReporter {
+    pub fn new(id: String) -> Self {
+        Self {
+            id,
+            kind: String::new(),
+            progress: ScanProgress::default(),
+            started: None,
+            emitted: Instant::now(),
+        }
+    }
+
+    /// 处理扫描消息，扫描结束时返回`true`
+    pub fn handle(&mut self, msg: ScanMsg) -> bool {
+        match msg {
+            ScanMsg::Discovered { files } => {
+                self.progress.discovered = files;
+                self.emit("task_start");
+            }
+            ScanMsg::Queued { tasks } => {
+                self.progress.queued = tasks;
+                self.emit("task_start");
+            }
+            ScanMsg::Started { total } => {
+                self.progress.total = total;
+                self.started = Some(Instant::now());
+                self.emit("task_running");
+            }
+            ScanMsg::Completed { path, .. } => {
+                self.progress.completed += 1;
+                push_recent(&mut self.progress.recent_completed, path);
+                self.throttle();
+            }
+            ScanMsg::Failed { path, .. } => {
+                self.progress.failed += 1;
+                push_recent(&mut self.progress.recent_failed, path);
+                self.throttle();
+            }
+            ScanMsg::Rescanned { summary } => {
+                self.progress.rescan = Some(summary);
+                self.emit("task_running");
+            }
+            ScanMsg::Done { canceled, .. } => {
+                self.emit(if canceled {
+                    "task_canceled"
+                } else {
+                    "task_completed"
+                });
+                return true;
+            }
+        }
+        false
+    }
+
+    fn throttle(&mut self) {
+        if self.emitted.elapsed() >= EMIT_INTERVAL {
+            self.emit("task_running");
+        }
+    }
+
+    fn emit(&mut self, stage: &str) {
+        let processed = self.progress.completed + self.progress.failed;
+        if let Some(started) = self.started {
+            if let Some((throughput, eta)) =
+                estimate(processed, self.progress.total, started.elapsed())
+            {
+                self.progress.throughput = throughput;
+                self.progress.eta = eta;
+            }
+        }
+        let progress = percent(processed, self.progress.total);
+        // 任务结束后会从注册表移除，保留最后一次获取的类型
+        if let Some(job) = job::get(&self.id) {
+            self.kind = job.kind;
+        }
+        let event = ScanEvent {
+            id: self.id.clone(),
+            stage: stage.to_string(),
+            r#type: self.kind.clone(),
+            progress,
+            data: self.progress.clone(),
+        };
+        emit(event);
+        self.progress.recent_completed.clear();
+        self.progress.recent_failed.clear();
+        self.emitted = Instant::now();
+    }
+}
+
+/// 每秒处理的文件数和预计剩余秒数，还没有耗时时返回`None`，还没有处理文件时没有预计时间
+fn estimate(processed: usize, total: usize, elapsed: Duration) -> Option<(f64, Option<u64>)> {
+    let elapsed = elapsed.as_secs_f64();
+    if elapsed <= 0.0 {
+        return None;
+    }
+    let throughput = processed as f64 / elapsed;
+    let eta =
+        (throughput > 0.0).then(|| (total.saturating_sub(processed) as f64 / throughput) as u64);
+    Some((throughput, eta))
+}
+
+/// 进度百分比
+fn percent(processed: usize, total: usize) -> f64 {
+    if total > 0 {
+        (processed as f64 / total as f64 * 100.0).min(100.0)
+    } else {
+        0.0
+    }
+}
+
+fn push_recent(recent: &mut Vec<String>, path: String) {
+    if recent.len() < RECENT_LIMIT {
+        recent.push(path);
+    }
+}
+
+/// 通过`APP_HANDLE`发送事件给前端
+pub fn emit(event: ScanEvent) {
+    if let Ok(handle) = APP_HANDLE.lock() {
+        if let Some(handle) = handle.as_ref() {
+            handle.emit_all(TASK_EVENT, event).print_error();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::file::progress::{estimate, percent, ProgressReporter, RECENT_LIMIT};
+    use crate::file::scan::ScanMsg;
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(estimate(10, 100, Duration::ZERO), None);
+        assert_eq!(estimate(0, 100, Duration::from_secs(2)), Some((0.0, None)));
+        assert_eq!(
+            estimate(10, 100, Duration::from_secs(2)),
+            Some((5.0, Some(18)))
+        );
+        assert_eq!(
+            estimate(120, 100, Duration::from_secs(4)),
+            Some((30.0, Some(0)))
+        );
+        assert_eq!(percent(0, 0), 0.0);
+        assert_eq!(percent(1, 4), 25.0);
+        assert_eq!(percent(5, 4), 100.0);
+    }
+
+    #[test]
+    fn test_reporter() {
+        let mut reporter = ProgressReporter::new(String::from("test-progress"));
+        assert!(!reporter.handle(ScanMsg::Started { total: 100 }));
+        for i in 0..RECENT_LIMIT + 10 {
+            reporter.handle(ScanMsg::Completed {
+                id: i as i64,
+                path: format!("{i}.jpg"),
+            });
+        }
+        reporter.handle(ScanMsg::Failed {
+            id: 0,
+            path: String::from("broken.jpg"),
+            error: String::new(),
+        });
+        assert_eq!(reporter.progress.completed, RECENT_LIMIT + 10);
+        assert_eq!(reporter.progress.failed, 1);
+        // 间隔内没有发送的路径最多保留`RECENT_LIMIT`个
+        assert_eq!(reporter.progress.recent_completed.len(), RECENT_LIMIT);
+        assert_eq!(reporter.progress.recent_failed, vec!["broken.jpg"]);
+        assert!(reporter.handle(ScanMsg::Done {
+            completed: RECENT_LIMIT + 10,
+            canceled: false,
+        }));
+        assert!(reporter.progress.recent_completed.is_empty());
+    }
+}
@@ -1,48 +1,50 @@
+use std::panic::catch_unwind;
 use std::path::Path;
 
-use image::{DynamicImage, EncodableLayout, ImageBuffer, Rgba};
-use psd::Psd;
+use image::{DynamicImage, RgbaImage};
+use psd::{ColorMode, Psd, PsdLayer};
 
 use crate::db::entity::metadata::Metadata;
+use crate::db::entity::psd::{PsdInfo, PsdLayerInfo};
 use crate::db::entity::task::{Task, TaskStatus};
-use crate::file::image_scanner::image_to_base64;
+use crate::file::image_scanner::{calculated_shape, kmeans};
+use crate::file::phash::perceptual_hash;
+use crate::file::registry::ScannerOptions;
 use crate::file::scan::{Context, Scanner};
+use crate::file::thumbnail::save_thumbnails;
 use crate::Result;
 
-pub struct PsdScanner {}
+pub struct PsdScanner {
+    options: ScannerOptions,
+}
 
 impl PsdScanner {
-    pub fn wrap() -> Box<Self> {
-        Box::new(PsdScanner {})
+    pub fn wrap(options: ScannerOptions) -> Box<Self> {
+        Box::new(PsdScanner { options })
     }
 }
 
 impl Scanner for PsdScanner {
-    fn is_support(&self, suffix: &str) -> bool {
-        match suffix {
-            "psd" => true,
-            _ => false,
-        }
+    fn options(&self) -> &ScannerOptions {
+        &self.options
     }
 
     fn scan(&self, task: &Task, context: &Context) -> TaskStatus {
         let mut status = TaskStatus::new(task.id);
         if self.is_support(task.file_suffix.as_str()) {
             let path = task.file_path.clone();
+            let size = self.options.thumbnail_size;
             let runtime = context.runtime.handle().clone();
             status.handle(runtime.clone().spawn_blocking(move || {
                 let path = Path::new(path.as_str());
                 let mut metadata = Metadata::load(path);
-                if metadata.analyze_metadata(path).is_ok() {
-                    if analyze_psd_metadata(path, &mut metadata).is_ok() {
-                        // 使用阻塞线程防止数据丢失！
-                        runtime.block_on(async move {
-                            metadata.save_to_db().await;
-                        });
-                        return true;
-                    };
-                };
-                false
+                metadata.analyze_metadata(path).map_err(|e| e.to_string())?;
+                analyze_psd_metadata(path, &mut metadata, size).map_err(|e| e.to_string())?;
+                // 使用阻塞线程防止数据丢失！
+                runtime.block_on(async move {
+                    metadata.save_to_db().await;
+                });
+                Ok(())
             }));
         }
         status
@@ -50,36 +52,238 @@ impl Scanner for PsdScanner {
 }
 
 /// 解析图片元数据
-fn analyze_psd_metadata(path: &Path, metadata: &mut Metadata) -> Result<()> {
-    let psd = Psd::from_bytes(std::fs::read(path).unwrap().as_bytes()).unwrap();
-    metadata.image_width = psd.width();
-    metadata.image_height = psd.height();
-    println!("{}*{}", metadata.image_width, metadata.image_height);
-
-    let final_image = psd.rgba();
-    // 创建 RgbaImage 对象
-    let mut image_buffer = ImageBuffer::new(metadata.image_width, metadata.image_height);
-    // 将 RGBA 数据填充到图像中
-    for (x, y, pixel) in image_buffer.enumerate_pixels_mut() {
-        let index = (y as usize * metadata.image_width as usize + x as usize) * 4;
-        let rgba = Rgba([
-            final_image[index],
-            final_image[index + 1],
-            final_image[index + 2],
-            final_image[index + 3],
-        ]);
-        *pixel = rgba;
+fn analyze_psd_metadata(path: &Path, metadata: &mut Metadata, size: u32) -> Result<()> {
+    let bytes = std::fs::read(path)?;
+    // psd库遇到不支持的文件时可能panic，转换为普通错误
+    let (image, psd) = catch_unwind(|| read_psd(&bytes).map_err(|e| e.to_string()))
+        .map_err(|_| "不支持的PSD文件")??;
+    metadata.image_width = image.width();
+    metadata.image_height = image.height();
+    let resize_image = save_thumbnails(&metadata.sha1, &image, size)?;
+    metadata.set_palette(kmeans(&resize_image));
+    metadata.hash = Some(perceptual_hash(&resize_image));
+    metadata.shape = calculated_shape(metadata.image_width, metadata.image_height);
+    metadata.psd = Some(psd);
+    Ok(())
+}
+
+/// 读取合并图像和图层信息，文件中没有合并图像时合成可见图层
+fn read_psd(bytes: &[u8]) -> Result<(DynamicImage, PsdInfo)> {
+    let psd = Psd::from_bytes(bytes).map_err(|e| e.to_string())?;
+    let mut rgba = psd.rgba();
+    let composited = is_blank(&rgba) && !psd.layers().is_empty();
+    if composited {
+        rgba = psd
+            .flatten_layers_rgba(&|(_, layer)| is_visible(&psd, layer))
+            .map_err(|e| e.to_string())?;
     }
+    let image = RgbaImage::from_raw(psd.width(), psd.height(), rgba).ok_or("图像数据不完整")?;
+    let info = PsdInfo {
+        color_mode: color_mode(psd.color_mode()).to_string(),
+        depth: psd.depth() as i64,
+        layer_count: psd.layers().len() as i64,
+        group_count: psd.groups().len() as i64,
+        composited,
+        layers: layers(&psd),
+        ..PsdInfo::default()
+    };
+    Ok((DynamicImage::ImageRgba8(image), info))
+}
 
-    let image = DynamicImage::ImageRgba8(image_buffer);
-    let resize_image = if metadata.image_width > 200 {
-        crate::file::image_scanner::thumbnail(&image, metadata.image_width, metadata.image_height)
-    } else {
-        image.to_rgb8()
+/// 未勾选“最大兼容”保存的文件，合并图像为纯色填充
+fn is_blank(rgba: &[u8]) -> bool {
+    let mut pixels = rgba.chunks_exact(4);
+    match pixels.next() {
+        Some(first) => pixels.all(|v| v == first),
+        None => true,
+    }
+}
+
+/// 图层及其所有上级分组都可见
+fn is_visible(psd: &Psd, layer: &PsdLayer) -> bool {
+    let mut visible = layer.visible();
+    let mut parent = layer.parent_id();
+    while let (true, Some(id)) = (visible, parent) {
+        match psd.groups().get(&id) {
+            Some(group) => {
+                visible = group.visible();
+                parent = group.parent_id();
+            }
+            None => break,
+        }
+    }
+    visible
+}
+
+/// 图层和分组列表，图层按文件中的顺序在前，分组按id在后
+fn layers(psd: &Psd) -> Vec<PsdLayerInfo> {
+    let parent_name = |parent: Option<u32>| {
+        parent
+            .and_then(|id| psd.groups().get(&id))
+            .map_or_else(String::new, |v| v.name().to_string())
     };
-    if let Some(base64) = image_to_base64(&resize_image) {
-        metadata.thumbnail = base64;
+    let mut layers: Vec<PsdLayerInfo> = psd
+        .layers()
+        .iter()
+        .map(|layer| PsdLayerInfo {
+            name: layer.name().to_string(),
+            is_group: false,
+            parent: parent_name(layer.parent_id()),
+            visible: layer.visible(),
+            opacity: layer.opacity() as i64,
+            ..PsdLayerInfo::default()
+        })
+        .collect();
+    let mut groups: Vec<_> = psd.groups().values().collect();
+    groups.sort_by_key(|v| v.id());
+    layers.extend(groups.into_iter().map(|group| PsdLayerInfo {
+        name: group.name().to_string(),
+        is_group: true,
+        parent: parent_name(group.parent_id()),
+        visible: group.visible(),
+        opacity: 255,
+        ..PsdLayerInfo::default()
+    }));
+    for (position, layer) in layers.iter_mut().enumerate() {
+        layer.position = position as i64;
     }
+    layers
+}
 
-    Ok(())
+fn color_mode(mode: ColorMode) -> &'static str {
+    match mode {
+        ColorMode::Bitmap => "bitmap",
+        ColorMode::Grayscale => "grayscale",
+        ColorMode::Indexed => "indexed",
+        ColorMode::Rgb => "rgb",
+        ColorMode::Cmyk => "cmyk",
+        ColorMode::Multichannel => "multichannel",
+        ColorMode::Duotone => "duotone",
+        ColorMode::Lab => "lab",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use psd::Psd;
+
+    use crate::file::psd_scanner::{is_blank, is_visible, read_psd};
+
+    /// 测试用的图层记录，按从下到上的顺序排列
+    enum Record {
+        /// 名称、是否可见、颜色
+        Layer(&'static str, bool, [u8; 3]),
+        /// 分组的开始，在其中的图层之上
+        Group(&'static str),
+        /// 分组的结束，在其中的图层之下，psd库从这里读取分组是否可见
+        End(bool),
+    }
+
+    /// 生成2x2的RGB文档，合并图像为纯白色
+    fn psd(records: &[Record]) -> Vec<u8> {
+        let mut info = (records.len() as i16).to_be_bytes().to_vec();
+        let mut channels = Vec::new();
+        for record in records {
+            let (name, visible, divider) = match record {
+                Record::Layer(name, visible, _) => (*name, *visible, None),
+                Record::Group(name) => (*name, true, Some(1i32)),
+                Record::End(visible) => ("</Layer group>", *visible, Some(3)),
+            };
+            let size: i32 = if divider.is_some() { 0 } else { 2 };
+            for v in [0, 0, size, size] {
+                info.extend(v.to_be_bytes());
+            }
+            if let Record::Layer(_, _, rgb) = record {
+                info.extend(4u16.to_be_bytes());
+                for (id, value) in [(-1i16, 255), (0, rgb[0]), (1, rgb[1]), (2, rgb[2])] {
+                    info.extend(id.to_be_bytes());
+                    info.extend(6u32.to_be_bytes());
+                    channels.extend(0u16.to_be_bytes());
+                    channels.extend([value; 4]);
+                }
+            } else {
+                info.extend(0u16.to_be_bytes());
+            }
+            info.extend(b"8BIMnorm");
+            info.extend([255, 0, if visible { 2 } else { 0 }, 0]);
+            let mut extra = vec![0; 8];
+            extra.push(name.len() as u8);
+            extra.extend(name.as_bytes());
+            extra.resize(8 + (name.len() + 4) / 4 * 4, 0);
+            if let Some(divider) = divider {
+                extra.extend(b"8BIMlsct");
+                extra.extend(4u32.to_be_bytes());
+                extra.extend(divider.to_be_bytes());
+            }
+            info.extend((extra.len() as u32).to_be_bytes());
+            info.extend(extra);
+        }
+        info.extend(channels);
+
+        let mut bytes = b"8BPS".to_vec();
+        bytes.extend(1u16.to_be_bytes());
+        bytes.extend([0; 6]);
+        bytes.extend(3u16.to_be_bytes());
+        bytes.extend(2u32.to_be_bytes());
+        bytes.extend(2u32.to_be_bytes());
+        bytes.extend(8u16.to_be_bytes());
+        bytes.extend(3u16.to_be_bytes());
+        bytes.extend([0; 8]);
+        bytes.extend((info.len() as u32 + 8).to_be_bytes());
+        bytes.extend((info.len() as u32).to_be_bytes());
+        bytes.extend(info);
+        bytes.extend([0; 4]);
+        bytes.extend(0u16.to_be_bytes());
+        bytes.extend([255; 12]);
+        bytes
+    }
+
+    fn sample() -> Vec<u8> {
+        psd(&[
+            Record::Layer("background", true, [255, 0, 0]),
+            Record::End(false),
+            Record::Layer("text", true, [0, 0, 255]),
+            Record::Group("group"),
+        ])
+    }
+
+    #[test]
+    fn test_is_blank() {
+        assert!(is_blank(&[]));
+        assert!(is_blank(&[255, 255, 255, 255, 255, 255, 255, 255]));
+        assert!(!is_blank(&[255, 255, 255, 255, 0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_is_visible() {
+        let psd = Psd::from_bytes(&sample()).unwrap();
+        let text = psd.layer_by_name("text").unwrap();
+        // 图层本身可见，但所在分组被隐藏
+        assert!(text.visible());
+        assert!(!is_visible(&psd, text));
+        assert!(is_visible(&psd, psd.layer_by_name("background").unwrap()));
+    }
+
+    #[test]
+    fn test_read_psd() {
+        let (image, info) = read_psd(&sample()).unwrap();
+        assert_eq!((info.layer_count, info.group_count), (2, 1));
+        assert_eq!(info.color_mode, "rgb");
+        let layers: Vec<_> = info
+            .layers
+            .iter()
+            .map(|v| (v.position, v.name.as_str(), v.parent.as_str(), v.is_group))
+            .collect();
+        assert_eq!(
+            layers,
+            vec![
+                (0, "text", "group", false),
+                (1, "background", "", false),
+                (2, "group", "", true),
+            ]
+        );
+        // 合并图像为纯色时合成可见图层，隐藏分组中的图层不参与合成
+        assert!(info.composited);
+        assert_eq!(image.to_rgba8().get_pixel(1, 1).0, [255, 0, 0, 255]);
+    }
 }
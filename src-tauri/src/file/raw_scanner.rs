@@ -1,46 +1,57 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::{Cursor, Read};
 use std::path::Path;
-use std::process::Command;
+
+use image::{DynamicImage, GenericImageView, ImageFormat, RgbImage};
+use rawloader::{RawImage, RawImageData};
 
 use crate::db::entity::metadata::Metadata;
 use crate::db::entity::task::{Task, TaskStatus};
+use crate::file::extract::read_exif;
+use crate::file::image_scanner::{calculated_shape, kmeans};
+use crate::file::phash::perceptual_hash;
+use crate::file::registry::ScannerOptions;
 use crate::file::scan::{Context, Scanner};
+use crate::file::thumbnail::save_thumbnails;
+use crate::util::error::ErrorHandle;
 use crate::Result;
 
-pub struct RawScanner {}
+/// 读取RAW文件的最大字节数
+const MAX_RAW_SIZE: u64 = 512 * 1024 * 1024;
+
+pub struct RawScanner {
+    options: ScannerOptions,
+}
 
 impl RawScanner {
-    pub fn wrap() -> Box<Self> {
-        Box::new(RawScanner {})
+    pub fn wrap(options: ScannerOptions) -> Box<Self> {
+        Box::new(RawScanner { options })
     }
 }
 
 impl Scanner for RawScanner {
-    fn is_support(&self, suffix: &str) -> bool {
-        match suffix {
-            "nef" => true,
-            _ => false,
-        }
+    fn options(&self) -> &ScannerOptions {
+        &self.options
     }
 
     fn scan(&self, task: &Task, context: &Context) -> TaskStatus {
         let mut status = TaskStatus::new(task.id);
         if self.is_support(task.file_suffix.as_str()) {
             let path = task.file_path.clone();
+            let size = self.options.thumbnail_size;
             let runtime = context.runtime.handle().clone();
             status.handle(runtime.clone().spawn_blocking(move || {
                 let path = Path::new(path.as_str());
                 let mut metadata = Metadata::load(path);
-                if metadata.analyze_metadata(path).is_ok() {
-                    if analyze_raw_metadata(path, &mut metadata).is_ok() {
-                        // 使用阻塞线程防止数据丢失！
-                        runtime.block_on(async move {
-                            metadata.save_to_db().await;
-                        });
-                        return true;
-                    };
-                };
-                false
+                metadata.analyze_metadata(path).map_err(|e| e.to_string())?;
+                analyze_raw_metadata(path, &mut metadata, size).map_err(|e| e.to_string())?;
+                metadata.exif = read_exif(path).print_error().flatten();
+                // 使用阻塞线程防止数据丢失！
+                runtime.block_on(async move {
+                    metadata.save_to_db().await;
+                });
+                Ok(())
             }));
         }
         status
@@ -48,57 +59,248 @@ impl Scanner for RawScanner {
 }
 
 /// 解析图片元数据
-fn analyze_raw_metadata(path: &Path, metadata: &mut Metadata) -> Result<()> {
-    let image = rawloader::decode_file(path).expect("error loading image");
-
-    metadata.image_width = image.width as u32;
-    metadata.image_height = image.height as u32;
+///
+/// 优先使用文件中内嵌的JPEG预览图，没有预览图时解码原始数据
+fn analyze_raw_metadata(path: &Path, metadata: &mut Metadata, size: u32) -> Result<()> {
+    let data = read_raw(path)?;
+    let (image, dimensions) = match read_preview(&data) {
+        Some(image) => {
+            // 预览图通常小于传感器尺寸，只解析RAW的头信息获取原始尺寸
+            let dimensions = rawloader::decode_dummy(&mut Cursor::new(&data))
+                .map_or(image.dimensions(), |raw| dimensions(&raw));
+            (image, dimensions)
+        }
+        None => {
+            let raw = rawloader::decode(&mut Cursor::new(&data)).map_err(|e| e.to_string())?;
+            let image = develop(&raw).ok_or("不支持的RAW数据格式")?;
+            // 合并后的图片尺寸变小，记录裁切后的原始尺寸
+            (DynamicImage::ImageRgb8(image), dimensions(&raw))
+        }
+    };
+    metadata.image_width = dimensions.0;
+    metadata.image_height = dimensions.1;
+    let resize_image = save_thumbnails(&metadata.sha1, &image, size)?;
+    metadata.set_palette(kmeans(&resize_image));
+    metadata.hash = Some(perceptual_hash(&resize_image));
+    metadata.shape = calculated_shape(metadata.image_width, metadata.image_height);
+    Ok(())
+}
 
-    if let Ok(base64) = thumbnail(path) {
-        metadata.thumbnail = base64;
+/// 读取RAW文件，超过`MAX_RAW_SIZE`时返回错误
+fn read_raw(path: &Path) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path)?
+        .take(MAX_RAW_SIZE + 1)
+        .read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_RAW_SIZE {
+        return Err("RAW文件过大".into());
     }
+    Ok(data)
+}
 
-    let _ = get_exif_data(path);
+/// 裁切后的原始尺寸
+fn dimensions(raw: &RawImage) -> (u32, u32) {
+    let [top, right, bottom, left] = raw.crops;
+    (
+        raw.width.saturating_sub(left + right) as u32,
+        raw.height.saturating_sub(top + bottom) as u32,
+    )
+}
 
-    // let resize_image = thumbnail(&image, metadata.image_width, metadata.image_height);
-    // if let Some(base64) = image_to_base64(&resize_image) {
-    //     metadata.thumbnail = base64;
-    // }
-    // metadata.colors = kmeans(&resize_image);
-    // metadata.shape = calculated_shape(metadata.image_width, metadata.image_height);
-    Ok(())
+/// 内嵌的JPEG图片
+struct Preview {
+    start: usize,
+    end: usize,
+    /// 像素数
+    pixels: u64,
 }
-/// 生成图片缩咯图
-fn thumbnail(path: &Path) -> Result<String> {
-    // 调用 bin/raw2base64.exe
-    let output = Command::new("bin/raw2base64.exe")
-        .args(&[path.to_str().unwrap()])
-        .output()?;
-    // 检查命令执行是否成功
-    if !output.status.success() {
-        return Err("Failed to execute ffmpeg command".into());
+
+/// 解码文件中最大的JPEG预览图
+fn read_preview(data: &[u8]) -> Option<DynamicImage> {
+    let mut previews = find_previews(data);
+    previews.sort_by_key(|v| Reverse(v.pixels));
+    previews.iter().find_map(|v| {
+        image::load_from_memory_with_format(&data[v.start..v.end], ImageFormat::Jpeg).ok()
+    })
+}
+
+/// 查找文件中所有内嵌的JPEG图片，跳过无损JPEG压缩的原始数据
+fn find_previews(data: &[u8]) -> Vec<Preview> {
+    let mut previews = Vec::new();
+    let mut start = 0;
+    while start + 3 < data.len() {
+        if data[start] == 0xFF && data[start + 1] == 0xD8 && data[start + 2] == 0xFF {
+            if let Some(preview) = parse_jpeg(data, start) {
+                start = preview.end;
+                previews.push(preview);
+                continue;
+            }
+        }
+        start += 1;
     }
-    let buffer = output.stdout;
-    // buffer转成字符串
+    previews
+}
 
-    Ok(format!(
-        "data:image/jpg;base64,{}",
-        String::from_utf8(buffer).unwrap()
-    ))
+/// 按段结构解析从`start`开始的JPEG，返回其范围和尺寸
+fn parse_jpeg(data: &[u8], start: usize) -> Option<Preview> {
+    let mut pos = start + 2;
+    let mut pixels = None;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        match marker {
+            // 填充字节
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // 没有长度的标记
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+        let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        if length < 2 {
+            return None;
+        }
+        match marker {
+            // 基线、扩展和渐进式JPEG
+            0xC0..=0xC2 => {
+                let height = u16::from_be_bytes([*data.get(pos + 5)?, *data.get(pos + 6)?]);
+                let width = u16::from_be_bytes([*data.get(pos + 7)?, *data.get(pos + 8)?]);
+                pixels = Some(width as u64 * height as u64);
+            }
+            // 其他编码方式，如无损JPEG
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => return None,
+            // 扫描数据开始，其中的0xFF都会被转义，直接查找结束标记
+            0xDA => {
+                let pixels = pixels?;
+                let data_start = pos + 2 + length;
+                let end = data
+                    .get(data_start..)?
+                    .windows(2)
+                    .position(|v| v == [0xFF, 0xD9])?;
+                return Some(Preview {
+                    start,
+                    end: data_start + end + 2,
+                    pixels,
+                });
+            }
+            _ => {}
+        }
+        pos += 2 + length;
+    }
 }
-fn get_exif_data(path: &Path) -> serde_json::Result<String> {
-    let image = rawloader::decode_file(path.to_str().unwrap()).expect("error loading image");
 
-    let mut map: HashMap<&str, _> = HashMap::new();
-    map.insert("make", image.make);
-    map.insert("model", image.model);
-    map.insert("clean_make", image.clean_make);
-    map.insert("clean_model", image.clean_model);
-    map.insert("width", image.width.to_string());
-    map.insert("height", image.height.to_string());
-    map.insert("cpp", image.cpp.to_string());
+/// 解码原始数据
+///
+/// 每个CFA单元（拜耳阵列为2x2，X-Trans为3x3）合并为一个像素，
+/// 再做白平衡和sRGB伽马校正，不做色彩矩阵转换
+fn develop(raw: &RawImage) -> Option<RgbImage> {
+    let [top, right, bottom, left] = raw.crops;
+    let width = raw.width.checked_sub(left + right)?;
+    let height = raw.height.checked_sub(top + bottom)?;
+    let value = |index: usize| -> f32 {
+        match &raw.data {
+            RawImageData::Integer(data) => data.get(index).map_or(0.0, |v| *v as f32),
+            RawImageData::Float(data) => data.get(index).copied().unwrap_or(0.0),
+        }
+    };
 
-    // map 转json
+    // 以绿色通道为基准归一化白平衡系数
+    let green = raw.wb_coeffs[1];
+    let wb: Vec<f32> = raw
+        .wb_coeffs
+        .iter()
+        .map(|v| {
+            let v = v / green;
+            if v.is_finite() && v > 0.0 {
+                v
+            } else {
+                1.0
+            }
+        })
+        .collect();
+    let level = |v: f32, c: usize| -> f32 {
+        let black = raw.blacklevels[c] as f32;
+        let white = raw.whitelevels[c] as f32;
+        ((v - black) / (white - black).max(1.0)).clamp(0.0, 1.0) * wb[c]
+    };
+
+    if raw.cpp == 3 {
+        let mut image = RgbImage::new(width as u32, height as u32);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let index = ((y as usize + top) * raw.width + x as usize + left) * 3;
+            for c in 0..3 {
+                pixel[c] = gamma(level(value(index + c), c));
+            }
+        }
+        return Some(image);
+    }
+    if raw.cpp != 1 {
+        return None;
+    }
 
-    serde_json::to_string(&map)
+    let block = if raw.cfa.width == 6 { 3 } else { 2 };
+    let mut image = RgbImage::new((width / block) as u32, (height / block) as u32);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let mut sum = [0f32; 3];
+        let mut count = [0u32; 3];
+        for row in top + y as usize * block..top + (y as usize + 1) * block {
+            for col in left + x as usize * block..left + (x as usize + 1) * block {
+                let color = raw.cfa.color_at(row, col);
+                let c = if color == 3 { 1 } else { color.min(2) };
+                sum[c] += level(value(row * raw.width + col), color.min(3));
+                count[c] += 1;
+            }
+        }
+        for c in 0..3 {
+            if count[c] > 0 {
+                pixel[c] = gamma(sum[c] / count[c] as f32);
+            }
+        }
+    }
+    Some(image)
+}
+
+/// 线性值转换为sRGB
+fn gamma(v: f32) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    let v = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (v * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageFormat, RgbImage};
+
+    use crate::file::raw_scanner::{find_previews, read_preview};
+
+    #[test]
+    fn test_read_preview() {
+        let mut data = vec![0u8; 64];
+        // 无损JPEG，不是预览图
+        data.extend_from_slice(&[0xFF, 0xD8, 0xFF, 0xC3, 0x00, 0x02, 0xFF, 0xD9]);
+        for (w, h) in [(16, 8), (64, 32)] {
+            let mut buffer = Vec::new();
+            RgbImage::new(w, h)
+                .write_to(&mut Cursor::new(&mut buffer), ImageFormat::Jpeg)
+                .unwrap();
+            data.extend_from_slice(&buffer);
+            data.extend_from_slice(&[0u8; 32]);
+        }
+        assert_eq!(find_previews(&data).len(), 2);
+        let image = read_preview(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (64, 32));
+    }
 }
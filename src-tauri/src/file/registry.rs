@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+
+use crate::config::{get_config, ScannerConfig};
+use crate::file::image_scanner::ImageScanner;
+use crate::file::model_scanner::ModelScanner;
+use crate::file::psd_scanner::PsdScanner;
+use crate::file::raw_scanner::RawScanner;
+use crate::file::scan::Scanner;
+use crate::file::video_scanner::VideoScanner;
+
+/// 扫描器设置，由默认值和`config.toml`中的配置合并而来
+#[derive(Debug, Clone)]
+pub struct ScannerOptions {
+    pub name: &'static str,
+    /// 小写的文件后缀
+    pub suffix: HashSet<String>,
+    pub thumbnail_size: u32,
+    /// 同时执行的任务数，0表示不限制
+    pub concurrency: usize,
+}
+
+/// 注册的扫描器
+struct Registration {
+    name: &'static str,
+    suffix: &'static [&'static str],
+    thumbnail_size: u32,
+    create: fn(ScannerOptions) -> Box<dyn Scanner + Send>,
+}
+
+/// 所有扫描器，文件交给第一个支持其后缀的扫描器处理
+const REGISTRY: &[Registration] = &[
+    Registration {
+        name: "image",
+        suffix: &[
+            "avif", "bmp", "dds", "farbfeld", "gif", "hdr", "ico", "jpg", "jpeg", "exr", "png",
+            "pnm", "qoi", "tga", "tiff", "webp",
+        ],
+        thumbnail_size: 200,
+        create: |options| ImageScanner::wrap(options),
+    },
+    Registration {
+        name: "model",
+        suffix: &["obj", "fbx", "gltf", "glb", "stl", "ply"],
+        thumbnail_size: 200,
+        create: |options| ModelScanner::wrap(options),
+    },
+    Registration {
+        name: "video",
+        suffix: &[
+            "mp4", "webm", "ogg", "mov", "mkv", "avi", "m4v", "mts", "wmv",
+        ],
+        thumbnail_size: 320,
+        create: |options| VideoScanner::wrap(options),
+    },
+    Registration {
+        name: "raw",
+        suffix: &[
+            "nef", "cr2", "cr3", "arw", "raf", "orf", "rw2", "dng", "pef",
+        ],
+        thumbnail_size: 200,
+        create: |options| RawScanner::wrap(options),
+    },
+    Registration {
+        name: "psd",
+        suffix: &["psd"],
+        thumbnail_size: 200,
+        create: |options| PsdScanner::wrap(options),
+    },
+];
+
+impl Registration {
+    fn options(&self, config: &ScannerConfig) -> ScannerOptions {
+        let suffix = match &config.suffix {
+            Some(suffix) => suffix.iter().map(|v| v.to_lowercase()).collect(),
+            None => self.suffix.iter().map(|v| v.to_string()).collect(),
+        };
+        ScannerOptions {
+            name: self.name,
+            suffix,
+            thumbnail_size: config.thumbnail_size.unwrap_or(self.thumbnail_size),
+            concurrency: config.concurrency.unwrap_or(0),
+        }
+    }
+}
+
+/// 按配置创建启用的扫描器
+pub fn scanners() -> Vec<Box<dyn Scanner + Send>> {
+    let config = get_config();
+    REGISTRY
+        .iter()
+        .filter_map(|registration| {
+            let scanner = config
+                .scanner
+                .get(registration.name)
+                .cloned()
+                .unwrap_or_default();
+            if scanner.enabled == Some(false) {
+                return None;
+            }
+            Some((registration.create)(registration.options(&scanner)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ScannerConfig;
+    use crate::file::registry::REGISTRY;
+
+    #[test]
+    fn test_options() {
+        let raw = REGISTRY.iter().find(|v| v.name == "raw").unwrap();
+        let options = raw.options(&ScannerConfig::default());
+        assert!(options.suffix.contains("nef"));
+        assert_eq!(options.thumbnail_size, 200);
+        let options = raw.options(&ScannerConfig {
+            suffix: Some(vec![String::from("NEF"), String::from("CR2")]),
+            thumbnail_size: Some(400),
+            ..ScannerConfig::default()
+        });
+        assert!(options.suffix.contains("cr2"));
+        assert_eq!(options.thumbnail_size, 400);
+    }
+}
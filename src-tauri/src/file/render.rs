@@ -0,0 +1,153 @@
+use image::imageops::{resize, FilterType};
+use image::{Rgb, RgbImage};
+
+use crate::file::model::{Mesh, UpAxis};
+
+/// 背景色
+const BACKGROUND: [f32; 3] = [240.0, 240.0, 240.0];
+/// 模型颜色
+const SURFACE: [f32; 3] = [170.0, 185.0, 205.0];
+/// 超采样倍数，用于抗锯齿
+const SUPERSAMPLE: u32 = 2;
+
+/// 用CPU渲染模型预览图，正交投影，从斜上方观察，模型缩放到图片中间
+pub fn render(mesh: &Mesh, size: u32) -> Option<RgbImage> {
+    let (min, max) = mesh.bounds()?;
+    let center = [0, 1, 2].map(|i| (min[i] + max[i]) / 2.0);
+    // Z轴向上的模型先转为Y轴向上
+    let up = mesh.up.unwrap_or(UpAxis::Y);
+    let (yaw, pitch) = (35f32.to_radians(), 25f32.to_radians());
+    let view = |v: &[f32; 3]| -> [f32; 3] {
+        let [x, y, z] = [0, 1, 2].map(|i| v[i] - center[i]);
+        let (x, y, z) = match up {
+            UpAxis::Y => (x, y, z),
+            UpAxis::Z => (x, z, -y),
+        };
+        let (x, z) = (
+            x * yaw.cos() + z * yaw.sin(),
+            -x * yaw.sin() + z * yaw.cos(),
+        );
+        let (y, z) = (
+            y * pitch.cos() - z * pitch.sin(),
+            y * pitch.sin() + z * pitch.cos(),
+        );
+        [x, y, z]
+    };
+    let vertices: Vec<[f32; 3]> = mesh.vertices.iter().map(view).collect();
+
+    let extent = vertices
+        .iter()
+        .filter(|v| v.iter().all(|x| x.is_finite()))
+        .fold(0f32, |extent, v| extent.max(v[0].abs()).max(v[1].abs()));
+    if extent <= 0.0 {
+        return None;
+    }
+    let width = size * SUPERSAMPLE;
+    let scale = width as f32 * 0.45 / extent;
+    let half = width as f32 / 2.0;
+    let screen: Vec<[f32; 3]> = vertices
+        .iter()
+        .map(|v| [half + v[0] * scale, half - v[1] * scale, v[2]])
+        .collect();
+
+    let mut image = RgbImage::from_pixel(width, width, Rgb(BACKGROUND.map(|v| v as u8)));
+    let mut depth = vec![f32::NEG_INFINITY; (width * width) as usize];
+    let light = normalize([-0.3, 0.4, 0.85]);
+    for triangle in mesh.triangles.iter() {
+        let [a, b, c] = triangle.map(|i| vertices[i as usize]);
+        let mut normal = normalize(cross(sub(b, a), sub(c, a)));
+        if !normal.iter().all(|v| v.is_finite()) {
+            continue;
+        }
+        // 法线朝向不一致时统一朝向观察者
+        if normal[2] < 0.0 {
+            normal = normal.map(|v| -v);
+        }
+        let shade = 0.45 + 0.55 * dot(normal, light).max(0.0);
+        let color = Rgb(SURFACE.map(|v| (v * shade).min(255.0) as u8));
+        let [a, b, c] = triangle.map(|i| screen[i as usize]);
+        fill(&mut image, &mut depth, [a, b, c], color);
+    }
+    Some(resize(&image, size, size, FilterType::Triangle))
+}
+
+/// 用重心坐标填充三角形，深度较大（离观察者较近）的像素覆盖较远的像素
+fn fill(image: &mut RgbImage, depth: &mut [f32], [a, b, c]: [[f32; 3]; 3], color: Rgb<u8>) {
+    let area = edge(a, b, c);
+    if area.abs() < f32::EPSILON {
+        return;
+    }
+    let width = image.width();
+    let min_x = a[0].min(b[0]).min(c[0]).floor().max(0.0) as u32;
+    let min_y = a[1].min(b[1]).min(c[1]).floor().max(0.0) as u32;
+    let max_x = (a[0].max(b[0]).max(c[0]).ceil() as u32).min(width - 1);
+    let max_y = (a[1].max(b[1]).max(c[1]).ceil() as u32).min(image.height() - 1);
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            let p = [x as f32 + 0.5, y as f32 + 0.5, 0.0];
+            let w0 = edge(b, c, p) / area;
+            let w1 = edge(c, a, p) / area;
+            let w2 = edge(a, b, p) / area;
+            if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                continue;
+            }
+            let z = w0 * a[2] + w1 * b[2] + w2 * c[2];
+            let index = (y * width + x) as usize;
+            if z > depth[index] {
+                depth[index] = z;
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+fn edge(a: [f32; 3], b: [f32; 3], p: [f32; 3]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = dot(v, v).sqrt();
+    v.map(|x| x / length)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::model::Mesh;
+    use crate::file::render::{render, BACKGROUND};
+
+    #[test]
+    fn test_render() {
+        let mut mesh = Mesh::default();
+        mesh.append(
+            [
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ],
+            &[0, 1, 2, 0, 2, 3],
+        );
+        let image = render(&mesh, 64).unwrap();
+        assert_eq!(image.dimensions(), (64, 64));
+        let background = BACKGROUND.map(|v| v as u8);
+        assert_ne!(image.get_pixel(32, 32).0, background);
+        assert_eq!(image.get_pixel(0, 0).0, background);
+        assert!(render(&Mesh::default(), 64).is_none());
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::get_config;
+use crate::db::entity::folder::Folder;
+use crate::db::entity::metadata::{file_stat, sha1, Metadata, MetadataStat, MISSING, NOT_DELETED};
+use crate::db::entity::sidecar::{import, SidecarState};
+use crate::db::sqlite::Session;
+use crate::file::sidecar::{modified, read, sidecar_path};
+use crate::util::error::ErrorHandle;
+
+/// 增量扫描结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RescanSummary {
+    /// 新增文件
+    pub added: usize,
+    /// 内容变化的文件
+    pub updated: usize,
+    /// 移动过的文件
+    pub moved: usize,
+    /// 已不存在的文件
+    pub removed: usize,
+    /// 未变化的文件
+    pub unchanged: usize,
+    /// 已不存在的文件夹
+    pub removed_folders: usize,
+    /// 内容未变化、从修改过的sidecar导入标注的文件
+    pub imported: usize,
+}
+
+/// 增量扫描，比对磁盘和数据库中的文件
+///
+/// 先比较文件大小和修改时间，变化后再比较sha1，
+/// 新增和内容变化的文件生成扫描任务，移动的文件直接更新路径，不存在的文件标记为丢失
+pub struct Rescan<'a> {
+    session: &'a Session,
+    summary: RescanSummary,
+}
+
+impl<'a> Rescan<'a> {
+    pub fn new(session: &'a Session) -> Self {
+        Self {
+            session,
+            summary: RescanSummary::default(),
+        }
+    }
+
+    pub async fn run(
+        mut self,
+        directories: &[String],
+        file_list: &[PathBuf],
+        folder_list: &[Folder],
+    ) -> RescanSummary {
+        let mut catalog = Vec::new();
+        for directory in directories {
+            catalog.extend(MetadataStat::list_by_directory(self.session, directory).await);
+        }
+        self.diff_files(catalog, file_list).await;
+        self.diff_folders(directories, folder_list).await;
+        self.summary
+    }
+
+    /// 只比对受影响的记录，`catalog`中不在`file_list`里的记录视为已删除或已移走
+    pub async fn run_files(
+        mut self,
+        catalog: Vec<MetadataStat>,
+        file_list: &[PathBuf],
+    ) -> RescanSummary {
+        self.diff_files(catalog, file_list).await;
+        self.summary
+    }
+
+    async fn diff_files(&mut self, catalog: Vec<MetadataStat>, file_list: &[PathBuf]) {
+        let mut catalog: HashMap<String, MetadataStat> = catalog
+            .into_iter()
+            .map(|v| (v.full_path.clone(), v))
+            .collect();
+
+        let mut created = Vec::new();
+        for path in file_list {
+            let Some(full_path) = path.to_str() else {
+                continue;
+            };
+            match catalog.remove(full_path) {
+                Some(stat) => self.check_modified(path, stat).await,
+                None => created.push(path),
+            }
+        }
+
+        // 剩余的记录在磁盘上已找不到，按sha1索引用于识别移动的文件
+        let mut missing: HashMap<String, Vec<MetadataStat>> = HashMap::new();
+        for stat in catalog.into_values() {
+            missing.entry(stat.sha1.clone()).or_default().push(stat);
+        }
+
+        for path in created {
+            let Some(hash) = sha1(path).print_error() else {
+                continue;
+            };
+            if let Some(stat) = missing.get_mut(&hash).and_then(|v| v.pop()) {
+                stat.relocate(self.session, path).await;
+                self.summary.moved += 1;
+            } else {
+                Metadata::load(path).save_task_to_db(self.session).await;
+                self.summary.added += 1;
+            }
+        }
+
+        for stat in missing.values().flatten() {
+            if stat.is_del == NOT_DELETED {
+                stat.tombstone(self.session).await;
+                self.summary.removed += 1;
+            }
+        }
+    }
+
+    async fn check_modified(&mut self, path: &Path, stat: MetadataStat) {
+        let Some((file_size, modified)) = file_stat(path).print_error() else {
+            return;
+        };
+        if stat.file_size == file_size && stat.modified == modified && stat.is_del != MISSING {
+            self.check_sidecar(path, stat.id).await;
+            self.summary.unchanged += 1;
+            return;
+        }
+        let Some(hash) = sha1(path).print_error() else {
+            return;
+        };
+        if hash == stat.sha1 {
+            stat.touch(self.session, file_size, &modified).await;
+            self.check_sidecar(path, stat.id).await;
+            self.summary.unchanged += 1;
+        } else {
+            Metadata::load(path).save_task_to_db(self.session).await;
+            self.summary.updated += 1;
+        }
+    }
+
+    /// 内容未变化的文件不会重新扫描，单独检查sidecar是否被其他软件修改过
+    ///
+    /// 先比较修改时间，与上次同步时不一致才解析
+    async fn check_sidecar(&mut self, path: &Path, id: i64) {
+        if !get_config().sidecar.read {
+            return;
+        }
+        let sidecar = sidecar_path(path);
+        if !sidecar.is_file() {
+            return;
+        }
+        let Some(modified) = modified(&sidecar).print_error() else {
+            return;
+        };
+        let state = SidecarState::get(self.session, id).await;
+        if state.is_some_and(|v| v.synced(&sidecar.to_string_lossy(), &modified)) {
+            return;
+        }
+        let Some(Some(sidecar)) = read(path).print_error() else {
+            return;
+        };
+        let Some(mut tx) = self.session.begin().await.print_error() else {
+            return;
+        };
+        if import(&mut tx, id, &sidecar).await.print_error() == Some(true)
+            && tx.commit().await.print_error().is_some()
+        {
+            self.summary.imported += 1;
+        }
+    }
+
+    async fn diff_folders(&mut self, directories: &[String], folder_list: &[Folder]) {
+        let exists: HashSet<&str> = folder_list.iter().map(|v| v.path.as_str()).collect();
+        for directory in directories {
+            for folder in Folder::list_by_directory(self.session, directory).await {
+                if !exists.contains(folder.path.as_str()) {
+                    folder.delete(self.session).await;
+                    self.summary.removed_folders += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::db::entity::metadata::{file_stat, sha1, Metadata, MISSING};
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::{temp_session, Session};
+    use crate::file::rescan::Rescan;
+
+    async fn catalog(session: &Session, id: i64, path: &Path) {
+        let mut metadata = Metadata::load(path);
+        (metadata.file_size, metadata.modified) = file_stat(path).unwrap();
+        metadata.sha1 = sha1(path).unwrap();
+        session
+            .sql("INSERT INTO metadata (id, full_path, file_size, modified, sha1) VALUES (?, ?, ?, ?, ?)")
+            .bind(id)
+            .bind(&metadata.full_path)
+            .bind(metadata.file_size)
+            .bind(&metadata.modified)
+            .bind(&metadata.sha1)
+            .execute()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rescan() {
+        let session = temp_session("rescan").await;
+        migrate(&session).await.unwrap();
+        let dir = std::env::temp_dir().join("pixel_basket_rescan");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let file = |name: &str, content: &str| {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let unchanged = file("unchanged.jpg", "unchanged");
+        let updated = file("updated.jpg", "before");
+        let moved = file("moved.jpg", "moved");
+        let removed = file("removed.jpg", "removed");
+        for (id, path) in [&unchanged, &updated, &moved, &removed].iter().enumerate() {
+            catalog(&session, id as i64, path).await;
+        }
+
+        fs::write(&updated, "after, with another size").unwrap();
+        let relocated = dir.join("sub").join("moved.jpg");
+        fs::rename(&moved, &relocated).unwrap();
+        fs::remove_file(&removed).unwrap();
+        let added = file("added.jpg", "added");
+
+        let directories = vec![dir.to_str().unwrap().to_string()];
+        let file_list = vec![unchanged, updated, relocated.clone(), added];
+        let summary = Rescan::new(&session)
+            .run(&directories, &file_list, &[])
+            .await;
+        assert_eq!(summary.unchanged, 1);
+        assert_eq!(summary.updated, 1);
+        assert_eq!(summary.moved, 1);
+        assert_eq!(summary.removed, 1);
+        assert_eq!(summary.added, 1);
+
+        let tasks = session
+            .count("SELECT COUNT(*) AS count FROM task")
+            .await
+            .unwrap();
+        assert_eq!(tasks.count, 2);
+        let moved = session
+            .sql("SELECT COUNT(*) AS count FROM metadata WHERE id = 2 AND full_path = ?")
+            .bind(relocated.to_str().unwrap())
+            .count()
+            .await
+            .unwrap();
+        assert_eq!(moved.count, 1);
+        let removed = session
+            .sql("SELECT COUNT(*) AS count FROM metadata WHERE id = 3 AND is_del = ?")
+            .bind(MISSING)
+            .count()
+            .await
+            .unwrap();
+        assert_eq!(removed.count, 1);
+    }
+}
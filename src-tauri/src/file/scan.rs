@@ -1,37 +1,69 @@
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tauri::async_runtime::TokioRuntime;
 
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
 
 use crate::config::get_db_path;
 use crate::db::entity::basket::{Basket, BasketData};
 use crate::db::entity::folder::Folder;
 use crate::db::entity::metadata::Metadata;
-use crate::db::entity::task::{Task, TaskStatus};
+use crate::db::entity::task::{ScanResult, Task, TaskStatus};
 use crate::db::sqlite::Session;
+use crate::db::writer::writer;
+use crate::file::job::JobToken;
+use crate::file::progress::ProgressReporter;
+use crate::file::registry::ScannerOptions;
+use crate::file::rescan::{Rescan, RescanSummary};
+use crate::file::watch;
 use crate::util::error::ErrorHandle;
 use crate::util::snowflake::id_str;
-use crate::{debug, info, Result};
+use crate::{debug, error, info, Result};
+
+/// 每次从数据库读取的任务数
+const TASK_PAGE_SIZE: i64 = 500;
+
+/// 任务队列只允许一个消费者，避免同时执行的扫描重复处理同一个任务
+static CONSUMER: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
 pub struct Context {
     pub runtime: TokioRuntime,
 }
 
 pub trait Scanner {
-    fn is_support(&self, suffix: &str) -> bool;
+    fn options(&self) -> &ScannerOptions;
+    fn is_support(&self, suffix: &str) -> bool {
+        self.options().suffix.contains(&suffix.to_lowercase())
+    }
     fn scan(&self, task: &Task, context: &Context) -> TaskStatus;
 }
 
-pub struct ScanMsg {
-    pub r#type: String,
-    pub data: String,
-}
-
-impl ScanMsg {
-    pub fn new(r#type: String, data: String) -> Self {
-        Self { r#type, data }
-    }
+/// 扫描进度消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ScanMsg {
+    /// 发现的文件数
+    Discovered { files: usize },
+    /// 创建的任务数
+    Queued { tasks: usize },
+    /// 开始执行任务
+    Started { total: usize },
+    /// 单个文件扫描完成
+    Completed { id: i64, path: String },
+    /// 单个文件扫描失败
+    Failed {
+        id: i64,
+        path: String,
+        error: String,
+    },
+    /// 增量扫描结果
+    Rescanned { summary: RescanSummary },
+    /// 扫描结束
+    Done { completed: usize, canceled: bool },
 }
 
 pub struct ScanJob {
@@ -47,12 +79,17 @@ pub struct ScanJob {
     pub basket_name: String,
     pub directories: Vec<String>,
     pub cpu_nums: usize,
+    /// 只执行这些文件的任务，为空时执行整个队列
+    scope: Option<Vec<String>>,
+    token: JobToken,
 }
 
 impl ScanJob {
     pub fn new(tx: Sender<ScanMsg>) -> Self {
+        let id = id_str();
         Self {
-            id: id_str(),
+            token: JobToken::register(&id),
+            id,
             scanners: Vec::new(),
             tx,
             file_list: Vec::new(),
@@ -64,16 +101,29 @@ impl ScanJob {
             basket_name: String::new(),
             directories: Vec::new(),
             cpu_nums: num_cpus::get() / 2,
+            scope: None,
         }
     }
 
+    pub fn set_kind(&self, kind: &str) {
+        self.token.set_kind(kind);
+    }
+
     pub fn add_scanners(&mut self, scanners: Vec<Box<dyn Scanner + Send>>) {
         self.scanners = scanners;
     }
 
+    /// 只执行指定文件的任务，不处理队列中的其他任务
+    pub fn set_scope(&mut self, paths: Vec<String>) {
+        self.scope = Some(paths);
+    }
+
     pub async fn run(&mut self, directories: Vec<String>) {
         // 文件读取
         self.load_dir(directories).await;
+        if self.is_canceled().await {
+            return;
+        }
 
         let mut session = Session::new(get_db_path());
         session.connect().await;
@@ -83,6 +133,49 @@ impl ScanJob {
         self.save_basket(&session).await;
         // 扫描任务处理
         self.load_task(&session).await;
+        if self.is_canceled().await {
+            return;
+        }
+        self.run_scanner(&session).await;
+        // 扫描完成后监听文件变化
+        if let Some(basket) = Basket::get_by_name(&session, &self.basket_name).await {
+            watch::start(basket.id.to_string()).await;
+        }
+    }
+
+    /// 增量扫描篮子
+    pub async fn rescan(&mut self, basket_id: String) {
+        let mut session = Session::new(get_db_path());
+        session.connect().await;
+
+        self.directories = Basket::directories(&session, &basket_id).await;
+        self.load_dir(self.directories.clone()).await;
+        if self.is_canceled().await {
+            return;
+        }
+        self.save_folder(&session).await;
+
+        let start = Instant::now();
+        let summary = Rescan::new(&session)
+            .run(&self.directories, &self.file_list, &self.folder_list)
+            .await;
+        info!(
+            "<scan:{}> 增量扫描：新增{}个，修改{}个，移动{}个，删除{}个，未变化{}个，删除{}个文件夹，从sidecar导入{}个,代码运行时间为{:?}秒",
+            self.id,
+            summary.added,
+            summary.updated,
+            summary.moved,
+            summary.removed,
+            summary.unchanged,
+            summary.removed_folders,
+            summary.imported,
+            (Instant::now() - start).as_secs()
+        );
+        self.tx
+            .send(ScanMsg::Rescanned { summary })
+            .await
+            .print_error();
+
         self.run_scanner(&session).await;
     }
 
@@ -95,16 +188,19 @@ impl ScanJob {
 
     pub async fn load_dir(&mut self, directories: Vec<String>) {
         let start = Instant::now();
-        directories.iter().map(|v| Path::new(v)).for_each(|v| {
-            info!("<scan:{}> 路径：{:?}", self.id, v.as_os_str());
-            self.load_file_list(v).unwrap();
+        let parent_folder_id = self.parent_folder_id;
+        for path in directories.iter().map(Path::new) {
+            info!("<scan:{}> 路径：{:?}", self.id, path.as_os_str());
+            // 遍历期间被删除的文件夹跳过
+            self.load_file_list(path, parent_folder_id)
+                .await
+                .print_error();
             self.file_count = self.file_list.len();
-        });
+        }
         self.tx
-            .send(ScanMsg::new(
-                "file".to_string(),
-                self.file_count.to_string(),
-            ))
+            .send(ScanMsg::Discovered {
+                files: self.file_count,
+            })
             .await
             .print_error();
         info!(
@@ -115,23 +211,48 @@ impl ScanJob {
         );
     }
 
-    fn load_file_list(&mut self, path: &Path) -> Result<()> {
-        if path.is_dir() && !is_hidden(path) {
-            let folder = Folder::new(&path, self.parent_folder_id);
-            let folder_id = folder.id;
-            self.folder_list.push(folder);
-            let read = path.read_dir()?;
-            for entry in read.flatten() {
-                self.parent_folder_id = folder_id;
-                self.load_file_list(entry.path().as_path())?
+    /// 遍历文件夹，每读取一个文件夹前检查暂停和取消
+    async fn load_file_list(&mut self, path: &Path, parent_folder_id: i64) -> Result<()> {
+        if !self.token.checkpoint().await {
+            return Ok(());
+        }
+        if !path.is_dir() || is_hidden(path) {
+            if path.is_file() && self.is_support(path) {
+                self.file_list.push(path.to_path_buf());
+            }
+            return Ok(());
+        }
+        let mut stack = self.load_folder(path, parent_folder_id)?;
+        while let Some((path, folder_id)) = stack.pop() {
+            if !self.token.checkpoint().await {
+                break;
+            }
+            if let Some(children) = self.load_folder(&path, folder_id).print_error() {
+                stack.extend(children);
             }
-        } else if path.is_file() && self.is_support(path) {
-            self.file_list.push(path.to_path_buf());
         }
         Ok(())
     }
 
-    fn is_support(&self, path: &Path) -> bool {
+    /// 读取一个文件夹中的文件，返回子文件夹和它们的上级id
+    fn load_folder(&mut self, path: &Path, parent_folder_id: i64) -> Result<Vec<(PathBuf, i64)>> {
+        let read = path.read_dir()?;
+        let folder = Folder::new(path, parent_folder_id);
+        let folder_id = folder.id;
+        self.folder_list.push(folder);
+        let mut children = Vec::new();
+        for entry in read.flatten() {
+            let path = entry.path();
+            if path.is_dir() && !is_hidden(&path) {
+                children.push((path, folder_id));
+            } else if path.is_file() && self.is_support(&path) {
+                self.file_list.push(path);
+            }
+        }
+        Ok(children)
+    }
+
+    pub fn is_support(&self, path: &Path) -> bool {
         if let Some(suffix) = get_file_suffix(path) {
             let string = suffix.to_lowercase();
             return self.scanners.iter().any(|v| v.is_support(&string));
@@ -143,16 +264,18 @@ impl ScanJob {
         let start = Instant::now();
 
         for path in self.file_list.iter() {
+            if !self.token.checkpoint().await {
+                break;
+            }
             let metadata = Metadata::load(path);
             metadata.save_task_to_db(session).await;
             self.task_count += 1;
         }
 
         self.tx
-            .send(ScanMsg::new(
-                "task".to_string(),
-                self.task_count.to_string(),
-            ))
+            .send(ScanMsg::Queued {
+                tasks: self.task_count,
+            })
             .await
             .print_error();
         info!(
@@ -164,75 +287,180 @@ impl ScanJob {
     }
 
     pub async fn run_scanner(&mut self, session: &Session) {
+        // 其他扫描正在执行时等待，完成后再读取剩余的任务
+        let _consumer = CONSUMER.lock().await;
         let start = Instant::now();
 
-        if let Some(task_list) = session
-            .select_as::<Task>("SELECT * FROM task WHERE status = 0")
-            .await
+        if let Some(runtime) = tokio::runtime::Builder::new_multi_thread()
+            .max_blocking_threads(self.cpu_nums.max(1))
+            .enable_all()
+            .build()
             .print_error()
         {
-            if let Some(runtime) = tokio::runtime::Builder::new_multi_thread()
-                .max_blocking_threads(self.cpu_nums)
-                .enable_all()
-                .build()
-                .print_error()
-            {
-                let context = Context { runtime };
-                let mut handles = Vec::new();
-                for task in task_list.iter() {
-                    for scanner in self.scanners.iter() {
-                        handles.push(scanner.scan(task, &context));
+            let context = Context { runtime };
+            // 获得队列后再读取指定文件的任务，已被其他扫描执行的任务不再出现
+            let mut scoped = match &self.scope {
+                Some(paths) => Some(Task::list_pending_by_paths(session, paths).await),
+                None => None,
+            };
+            let total = match &scoped {
+                Some(tasks) => tasks.len(),
+                None => Task::count_pending(session).await,
+            };
+            self.tx.send(ScanMsg::Started { total }).await.print_error();
+            // 同时执行的任务数，队列已满时等待任务完成后再读取新的任务
+            let workers = self.cpu_nums.max(1) * 2;
+            let mut running: JoinSet<(Task, Option<usize>, ScanResult)> = JoinSet::new();
+            let mut dispatcher = Dispatcher::new(
+                self.scanners
+                    .iter()
+                    .map(|v| v.options().concurrency)
+                    .collect(),
+            );
+            let mut canceled = false;
+            let mut last_id = i64::MIN;
+            // 按id分页读取任务，内存占用不随队列长度增长，未执行的任务保留到下次执行
+            'page: loop {
+                let page = match scoped.as_mut() {
+                    Some(tasks) => std::mem::take(tasks),
+                    None => Task::list_pending(session, last_id, TASK_PAGE_SIZE).await,
+                };
+                let Some(last) = page.last() else {
+                    break;
+                };
+                last_id = last.id;
+                for task in page {
+                    if !self.token.checkpoint().await {
+                        info!("<scan:{}> 扫描已取消", self.id);
+                        canceled = true;
+                        break 'page;
                     }
-                }
-                for status in handles {
-                    let id = status.id;
-                    let is_success = status.success().await;
-                    if is_success {
-                        self.scan_count += 1;
-                        let sql = format!("DELETE FROM task WHERE id = {}", id);
-                        session.execute(&sql).await.print_error();
-                        debug!("<scan:{}> 执行任务<id:{}>完成", self.id, id);
+                    let index = self.scanner_index(&task.file_suffix);
+                    // 队列已满或扫描器达到并发上限时等待任务完成
+                    while running.len() >= workers || dispatcher.is_full(index) {
+                        let Some(joined) = running.join_next().await else {
+                            break;
+                        };
+                        if let Some((task, index, result)) = joined.print_error() {
+                            dispatcher.finish(index);
+                            self.finish_task(session, task, result).await;
+                        }
                     }
+                    dispatcher.start(index);
+                    let status = match index {
+                        Some(i) => self.scanners[i].scan(&task, &context),
+                        None => TaskStatus::new(task.id),
+                    };
+                    running.spawn(async move {
+                        let result = status
+                            .result()
+                            .await
+                            .unwrap_or_else(|| Err(String::from("没有支持该文件的扫描器")));
+                        (task, index, result)
+                    });
                 }
+            }
+            // 等待已经开始的任务完成
+            while let Some(result) = running.join_next().await {
+                if let Some((task, _, result)) = result.print_error() {
+                    self.finish_task(session, task, result).await;
+                }
+            }
+            // 扫描结果写入数据库后再通知前端
+            writer().flush().await;
+
+            let runtime = context.runtime;
+            tokio::task::spawn_blocking(move || runtime.shutdown_timeout(Duration::from_secs(10)))
+                .await
+                .print_error();
+
+            self.tx
+                .send(ScanMsg::Done {
+                    completed: self.scan_count,
+                    canceled,
+                })
+                .await
+                .print_error();
+            info!(
+                "<scan:{}> 执行{}个任务,代码运行时间为{:?}秒",
+                self.id,
+                self.scan_count,
+                (Instant::now() - start).as_secs()
+            );
+        }
+    }
 
-                context.runtime.shutdown_background();
-
-                self.tx
-                    .send(ScanMsg::new(
-                        "done".to_string(),
-                        self.scan_count.to_string(),
-                    ))
-                    .await
-                    .print_error();
-                info!(
-                    "<scan:{}> 执行{}个任务,代码运行时间为{:?}秒",
-                    self.id,
-                    self.scan_count,
-                    (Instant::now() - start).as_secs()
-                );
+    /// 交给第一个支持该文件的扫描器
+    fn scanner_index(&self, suffix: &str) -> Option<usize> {
+        self.scanners.iter().position(|v| v.is_support(suffix))
+    }
+
+    /// 记录任务结果，成功时移除任务，失败时记录原因等待重试
+    async fn finish_task(&mut self, session: &Session, task: Task, result: ScanResult) {
+        let msg = match result {
+            Ok(()) => {
+                self.scan_count += 1;
+                task.complete().await;
+                debug!("<scan:{}> 执行任务<id:{}>完成", self.id, task.id);
+                ScanMsg::Completed {
+                    id: task.id,
+                    path: task.file_path,
+                }
             }
+            Err(error) => {
+                task.fail(session, &error).await;
+                error!("<scan:{}> 执行任务<id:{}>失败：{}", self.id, task.id, error);
+                ScanMsg::Failed {
+                    id: task.id,
+                    path: task.file_path,
+                    error,
+                }
+            }
+        };
+        self.tx.send(msg).await.print_error();
+    }
+
+    /// 检查任务是否被取消，暂停时等待恢复
+    async fn is_canceled(&mut self) -> bool {
+        if self.token.checkpoint().await {
+            return false;
         }
+        info!("<scan:{}> 扫描已取消", self.id);
+        self.tx
+            .send(ScanMsg::Done {
+                completed: self.scan_count,
+                canceled: true,
+            })
+            .await
+            .print_error();
+        true
     }
 
     pub fn run_async(mut self, basket: BasketData) {
+        self.set_kind("create");
         self.basket_name = basket.name;
         self.directories = basket.directories;
         tokio::spawn(async move { self.run(self.directories.clone()).await });
     }
 
+    pub fn rescan_async(mut self, basket_id: String) {
+        self.set_kind("rescan");
+        tokio::spawn(async move { self.rescan(basket_id).await });
+    }
+
     pub fn run_task_async(mut self) {
+        self.set_kind("task");
         tokio::spawn(async move { self.run_task().await });
     }
 
     pub fn monitor_async(&self, mut rx: Receiver<ScanMsg>) {
         let id = self.id.clone();
         tokio::spawn(async move {
-            loop {
-                if let Some(msg) = rx.recv().await {
-                    debug!("{} -> {}", msg.r#type, msg.data);
-                    if msg.r#type == "done" {
-                        break;
-                    }
+            let mut reporter = ProgressReporter::new(id.clone());
+            while let Some(msg) = rx.recv().await {
+                debug!("<scan:{}> {:?}", id, msg);
+                if reporter.handle(msg) {
+                    break;
                 }
             }
             info!("<scan:{}> 扫描结束", id);
@@ -283,7 +511,39 @@ impl ScanJob {
     }
 }
 
-fn is_hidden(path: &Path) -> bool {
+/// 记录每个扫描器正在执行的任务数，达到扫描器的并发上限时不再分配任务
+struct Dispatcher {
+    /// 每个扫描器的并发上限，0表示不限制
+    limits: Vec<usize>,
+    busy: Vec<usize>,
+}
+
+impl Dispatcher {
+    fn new(limits: Vec<usize>) -> Self {
+        Self {
+            busy: vec![0; limits.len()],
+            limits,
+        }
+    }
+
+    fn is_full(&self, index: Option<usize>) -> bool {
+        index.is_some_and(|i| self.limits[i] > 0 && self.busy[i] >= self.limits[i])
+    }
+
+    fn start(&mut self, index: Option<usize>) {
+        if let Some(i) = index {
+            self.busy[i] += 1;
+        }
+    }
+
+    fn finish(&mut self, index: Option<usize>) {
+        if let Some(i) = index {
+            self.busy[i] = self.busy[i].saturating_sub(1);
+        }
+    }
+}
+
+pub fn is_hidden(path: &Path) -> bool {
     if let Some(file_name) = path.file_name() {
         if let Some(file_name) = file_name.to_str() {
             if file_name.starts_with('.') {
@@ -308,3 +568,98 @@ fn get_path_parent(path: &str) -> Option<String> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use tokio::sync::mpsc::channel;
+
+    use crate::db::entity::task::{Task, TaskStatus};
+    use crate::file::job::{set_state, JobState};
+    use crate::file::registry::ScannerOptions;
+    use crate::file::scan::{Context, Dispatcher, ScanJob, Scanner};
+
+    struct TestScanner {
+        options: ScannerOptions,
+    }
+
+    impl TestScanner {
+        fn wrap(suffix: &[&str], concurrency: usize) -> Box<Self> {
+            Box::new(TestScanner {
+                options: ScannerOptions {
+                    name: "test",
+                    suffix: suffix.iter().map(|v| v.to_string()).collect(),
+                    thumbnail_size: 0,
+                    concurrency,
+                },
+            })
+        }
+    }
+
+    impl Scanner for TestScanner {
+        fn options(&self) -> &ScannerOptions {
+            &self.options
+        }
+
+        fn scan(&self, task: &Task, _context: &Context) -> TaskStatus {
+            TaskStatus::new(task.id)
+        }
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let (tx, _rx) = channel(1);
+        let mut job = ScanJob::new(tx);
+        job.add_scanners(vec![
+            TestScanner::wrap(&["jpg"], 1),
+            TestScanner::wrap(&["jpg", "png"], 0),
+        ]);
+        assert_eq!(job.scanner_index("JPG"), Some(0));
+        assert_eq!(job.scanner_index("png"), Some(1));
+        assert_eq!(job.scanner_index("txt"), None);
+
+        let mut dispatcher = Dispatcher::new(vec![1, 0]);
+        dispatcher.start(Some(0));
+        assert!(dispatcher.is_full(Some(0)));
+        // 不限制并发的扫描器和没有扫描器的任务不会等待
+        for _ in 0..10 {
+            dispatcher.start(Some(1));
+        }
+        assert!(!dispatcher.is_full(Some(1)));
+        assert!(!dispatcher.is_full(None));
+        dispatcher.finish(Some(0));
+        assert!(!dispatcher.is_full(Some(0)));
+    }
+
+    #[tokio::test]
+    async fn test_load_dir_paused() {
+        let dir = std::env::temp_dir().join("pixel-basket-scan");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a").join("b")).unwrap();
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        fs::write(dir.join("a").join("1.jpg"), b"").unwrap();
+        fs::write(dir.join("a").join("b").join("2.txt"), b"").unwrap();
+
+        let (tx, _rx) = channel(1);
+        let mut job = ScanJob::new(tx);
+        job.add_scanners(vec![TestScanner::wrap(&["jpg"], 0)]);
+        let id = job.id.clone();
+        set_state(&id, JobState::Paused);
+        let directories = vec![dir.to_string_lossy().to_string()];
+        let handle = tokio::spawn(async move {
+            job.load_dir(directories).await;
+            job
+        });
+        // 暂停时不遍历文件夹
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!handle.is_finished());
+
+        set_state(&id, JobState::Running);
+        let job = handle.await.unwrap();
+        assert_eq!(job.folder_list.len(), 3);
+        assert_eq!(job.file_list, vec![dir.join("a").join("1.jpg")]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use crate::db::entity::annotation::{LABELS, MAX_SCORE};
+use crate::db::entity::metadata::Metadata;
+use crate::db::entity::tag::SEPARATOR;
+use crate::Result;
+
+const NS_XMP: &str = "http://ns.adobe.com/xap/1.0/";
+const NS_DC: &str = "http://purl.org/dc/elements/1.1/";
+const NS_LR: &str = "http://ns.adobe.com/lightroom/1.0/";
+/// Lightroom层级关键词的分隔符，如`people|alice`
+const LR_SEPARATOR: char = '|';
+/// 由目录管理的属性，写入时先删除sidecar中原有的值
+const MANAGED: [&str; 8] = [
+    "xmp:Rating",
+    "xmp:Label",
+    "xmp:MetadataDate",
+    "dc:title",
+    "dc:description",
+    "dc:source",
+    "dc:subject",
+    "lr:hierarchicalSubject",
+];
+/// 没有sidecar时使用的模板
+const TEMPLATE: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""/>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>
+"#;
+
+/// sidecar中的标注，没有的项为空
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct XmpData {
+    /// 0到5，0表示未评分，Lightroom的拒绝（-1）视为0
+    pub rating: Option<i64>,
+    /// 小写，见`LABELS`，其他软件自定义的标记忽略
+    pub label: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub source: Option<String>,
+    /// 标签的完整名称，层级以`/`分隔
+    pub keywords: Vec<String>,
+}
+
+/// 文件的XMP sidecar
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sidecar {
+    pub path: String,
+    /// 读取时sidecar的修改时间
+    pub modified: String,
+    pub data: XmpData,
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+impl XmpData {
+    /// 目录中的标注，为空的项不写入
+    pub fn from(metadata: &Metadata) -> Self {
+        Self {
+            rating: Some(metadata.score.round() as i64).filter(|v| *v > 0),
+            label: non_empty(&metadata.label),
+            title: non_empty(&metadata.title),
+            description: non_empty(&metadata.exegesis),
+            source: non_empty(&metadata.source_url),
+            keywords: metadata.tags.split(',').filter_map(non_empty).collect(),
+        }
+    }
+
+    /// 按属性名收集的值转换为标注
+    fn from_values(values: HashMap<String, Vec<String>>) -> Self {
+        let first = |name: &str| values.get(name).and_then(|v| v.first()).cloned();
+        let list = |name: &str| values.get(name).cloned().unwrap_or_default();
+        // 优先使用层级关键词，`dc:subject`中只保留不属于任何层级的关键词
+        let hierarchical: Vec<String> = list("lr:hierarchicalSubject")
+            .iter()
+            .map(|v| v.replace(LR_SEPARATOR, &SEPARATOR.to_string()))
+            .collect();
+        let components: HashSet<String> = hierarchical
+            .iter()
+            .flat_map(|v| v.split(SEPARATOR))
+            .map(|v| v.trim().to_lowercase())
+            .collect();
+        let mut keywords = hierarchical;
+        for keyword in list("dc:subject") {
+            if !components.contains(&keyword.to_lowercase()) && !keywords.contains(&keyword) {
+                keywords.push(keyword);
+            }
+        }
+        Self {
+            rating: first("xmp:Rating")
+                .and_then(|v| v.parse::<f32>().ok())
+                .map(|v| v.round().clamp(0.0, MAX_SCORE) as i64),
+            label: first("xmp:Label")
+                .map(|v| v.to_lowercase())
+                .filter(|v| LABELS.contains(&v.as_str())),
+            title: first("dc:title"),
+            description: first("dc:description"),
+            source: first("dc:source"),
+            keywords,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// 是否需要写入结构属性
+    fn has_elements(&self) -> bool {
+        self.title.is_some() || self.description.is_some() || !self.keywords.is_empty()
+    }
+
+    /// 写在`rdf:Description`上的简单属性
+    fn attributes(&self) -> Vec<(&'static str, String)> {
+        let mut attributes = Vec::new();
+        if let Some(rating) = self.rating {
+            attributes.push(("xmp:Rating", rating.to_string()));
+        }
+        if let Some(label) = &self.label {
+            // Lightroom使用首字母大写的英文名称
+            let mut label = label.clone();
+            if let Some(first) = label.get_mut(0..1) {
+                first.make_ascii_uppercase();
+            }
+            attributes.push(("xmp:Label", label));
+        }
+        if let Some(source) = &self.source {
+            attributes.push(("dc:source", source.clone()));
+        }
+        attributes.push((
+            "xmp:MetadataDate",
+            Local::now().format("%Y-%m-%dT%H:%M:%S%:z").to_string(),
+        ));
+        attributes
+    }
+}
+
+/// 优先使用已有的`file.ext.xmp`，其次`file.xmp`，都没有时为`file.ext.xmp`
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut full = path.as_os_str().to_owned();
+    full.push(".xmp");
+    let full = PathBuf::from(full);
+    let short = path.with_extension("xmp");
+    if !full.exists() && short.exists() {
+        short
+    } else {
+        full
+    }
+}
+
+/// sidecar的修改时间，精确到纳秒，用于发现其他软件的修改
+pub fn modified(path: &Path) -> Result<String> {
+    let datetime: DateTime<Local> = path.metadata()?.modified()?.into();
+    Ok(datetime.format("%Y-%m-%d %H:%M:%S%.f").to_string())
+}
+
+/// 读取文件的sidecar，没有sidecar时返回`None`
+pub fn read(path: &Path) -> Result<Option<Sidecar>> {
+    let sidecar = sidecar_path(path);
+    if !sidecar.is_file() {
+        return Ok(None);
+    }
+    let modified = modified(&sidecar)?;
+    let data = parse(&std::fs::read_to_string(&sidecar)?)?;
+    Ok(Some(Sidecar {
+        path: sidecar.to_string_lossy().to_string(),
+        modified,
+        data,
+    }))
+}
+
+/// 把标注写入sidecar，保留其他软件写入的内容，返回写入后的修改时间
+///
+/// sidecar不存在且没有标注时不创建
+pub fn write(sidecar: &Path, data: &XmpData) -> Result<Option<String>> {
+    let content = if sidecar.exists() {
+        std::fs::read_to_string(sidecar)?
+    } else if data.is_empty() {
+        return Ok(None);
+    } else {
+        TEMPLATE.to_string()
+    };
+    std::fs::write(sidecar, rewrite(&content, data)?)?;
+    Ok(Some(modified(sidecar)?))
+}
+
+fn is_description(e: &BytesStart) -> bool {
+    e.name().as_ref() == b"rdf:Description"
+}
+
+fn is_managed(name: &[u8]) -> bool {
+    MANAGED.iter().any(|v| v.as_bytes() == name)
+}
+
+/// 读取`rdf:Description`上的简单属性
+fn read_attributes(e: &BytesStart, values: &mut HashMap<String, Vec<String>>) -> Result<()> {
+    for attribute in e.attributes() {
+        let attribute = attribute?;
+        if !is_managed(attribute.key.as_ref()) {
+            continue;
+        }
+        if let Some(value) = non_empty(&attribute.unescape_value()?) {
+            let key = String::from_utf8(attribute.key.as_ref().to_vec())?;
+            values.entry(key).or_default().push(value);
+        }
+    }
+    Ok(())
+}
+
+/// 解析XMP，属性可以写在`rdf:Description`上，也可以是子元素，
+/// `rdf:Alt`和`rdf:Bag`中的每一项分别读取
+pub fn parse(xml: &str) -> Result<XmpData> {
+    let mut reader = Reader::from_str(xml);
+    let mut values: HashMap<String, Vec<String>> = HashMap::new();
+    let mut depth = 0;
+    // 当前`rdf:Description`所在的层级
+    let mut description = None;
+    // 正在读取的属性和所在的层级
+    let mut property: Option<(String, usize)> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Eof => break,
+            Event::Start(e) => {
+                depth += 1;
+                if property.is_some() {
+                    continue;
+                }
+                if is_description(&e) {
+                    read_attributes(&e, &mut values)?;
+                    description = Some(depth);
+                } else if description == Some(depth - 1) {
+                    let name = String::from_utf8(e.name().as_ref().to_vec())?;
+                    property = Some((name, depth));
+                }
+            }
+            Event::Empty(e) if property.is_none() && is_description(&e) => {
+                read_attributes(&e, &mut values)?;
+            }
+            Event::Text(text) => {
+                if let Some((name, _)) = &property {
+                    if let Some(value) = non_empty(&text.unescape()?) {
+                        values.entry(name.clone()).or_default().push(value);
+                    }
+                }
+            }
+            Event::End(_) => {
+                if property.as_ref().is_some_and(|(_, level)| *level == depth) {
+                    property = None;
+                }
+                if description == Some(depth) {
+                    description = None;
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(XmpData::from_values(values))
+}
+
+/// 去掉由目录管理的属性，`data`不为空时写入新值
+fn description_start(e: &BytesStart, data: Option<&XmpData>) -> Result<BytesStart<'static>> {
+    let mut start = BytesStart::new(String::from_utf8(e.name().as_ref().to_vec())?);
+    for attribute in e.attributes() {
+        let attribute = attribute?;
+        if !is_managed(attribute.key.as_ref()) {
+            start.push_attribute(attribute);
+        }
+    }
+    if let Some(data) = data {
+        for (prefix, namespace) in [
+            ("xmlns:xmp", NS_XMP),
+            ("xmlns:dc", NS_DC),
+            ("xmlns:lr", NS_LR),
+        ] {
+            if e.try_get_attribute(prefix)?.is_none() {
+                start.push_attribute((prefix, namespace));
+            }
+        }
+        for (key, value) in data.attributes() {
+            start.push_attribute((key, value.as_str()));
+        }
+    }
+    Ok(start)
+}
+
+/// 写入`rdf:Alt`或`rdf:Bag`结构的属性
+fn write_list(
+    writer: &mut Writer<Vec<u8>>,
+    name: &str,
+    container: &str,
+    items: &[String],
+) -> Result<()> {
+    writer.write_event(Event::Text(BytesText::new("\n   ")))?;
+    writer.create_element(name).write_inner_content(|writer| {
+        writer
+            .create_element(container)
+            .write_inner_content(|writer| {
+                for item in items {
+                    let li = writer.create_element("rdf:li");
+                    // 多语言文本只写默认语言
+                    let li = if container == "rdf:Alt" {
+                        li.with_attribute(("xml:lang", "x-default"))
+                    } else {
+                        li
+                    };
+                    li.write_text_content(BytesText::new(item))?;
+                }
+                Ok(())
+            })?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// 写在`rdf:Description`中的结构属性
+fn write_elements(writer: &mut Writer<Vec<u8>>, data: &XmpData) -> Result<()> {
+    if let Some(title) = &data.title {
+        write_list(writer, "dc:title", "rdf:Alt", std::slice::from_ref(title))?;
+    }
+    if let Some(description) = &data.description {
+        write_list(
+            writer,
+            "dc:description",
+            "rdf:Alt",
+            std::slice::from_ref(description),
+        )?;
+    }
+    if !data.keywords.is_empty() {
+        // 其他软件通常只读取`dc:subject`，写入最后一级的名称
+        let mut subject: Vec<String> = Vec::new();
+        for keyword in &data.keywords {
+            let name = keyword
+                .rsplit(SEPARATOR)
+                .next()
+                .unwrap_or(keyword)
+                .to_string();
+            if !subject.contains(&name) {
+                subject.push(name);
+            }
+        }
+        write_list(writer, "dc:subject", "rdf:Bag", &subject)?;
+        let hierarchical: Vec<String> = data
+            .keywords
+            .iter()
+            .map(|v| v.replace(SEPARATOR, &LR_SEPARATOR.to_string()))
+            .collect();
+        write_list(writer, "lr:hierarchicalSubject", "rdf:Bag", &hierarchical)?;
+    }
+    Ok(())
+}
+
+/// 在第一个`rdf:Description`中写入标注，其他`rdf:Description`中的同名属性会被删除
+pub fn rewrite(xml: &str, data: &XmpData) -> Result<String> {
+    let mut reader = Reader::from_str(xml);
+    let mut writer = Writer::new(Vec::new());
+    let mut depth = 0;
+    // 当前顶层`rdf:Description`所在的层级，结构类型属性中嵌套的`rdf:Description`不计入
+    let mut description = None;
+    // 正在跳过的元素层数
+    let mut skip = 0;
+    // 缓存`rdf:Description`中的空白，跳过的元素前的空白一起删除
+    let mut pending = None;
+    let mut written = false;
+    loop {
+        let event = reader.read_event()?;
+        if skip > 0 {
+            match event {
+                Event::Start(_) => skip += 1,
+                Event::End(_) => skip -= 1,
+                Event::Eof => break,
+                _ => {}
+            }
+            continue;
+        }
+        let inside = description.is_some() && description == Some(depth);
+        match &event {
+            Event::Text(text) if inside && text.iter().all(u8::is_ascii_whitespace) => {
+                pending = Some(event.into_owned());
+                continue;
+            }
+            Event::Start(e) | Event::Empty(e) if inside && is_managed(e.name().as_ref()) => {
+                if matches!(event, Event::Start(_)) {
+                    skip = 1;
+                }
+                pending = None;
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(text) = pending.take() {
+            writer.write_event(text)?;
+        }
+        match event {
+            Event::Eof => break,
+            Event::Start(e) if description.is_none() && is_description(&e) => {
+                let start = description_start(&e, (!written).then_some(data))?;
+                writer.write_event(Event::Start(start))?;
+                if !written {
+                    write_elements(&mut writer, data)?;
+                    written = true;
+                }
+                depth += 1;
+                description = Some(depth);
+            }
+            Event::Empty(e) if description.is_none() && is_description(&e) => {
+                let start = description_start(&e, (!written).then_some(data))?;
+                if !written && data.has_elements() {
+                    let end = start.to_end().into_owned();
+                    writer.write_event(Event::Start(start))?;
+                    write_elements(&mut writer, data)?;
+                    writer.write_event(Event::Text(BytesText::new("\n  ")))?;
+                    writer.write_event(Event::End(end))?;
+                } else {
+                    writer.write_event(Event::Empty(start))?;
+                }
+                written = true;
+            }
+            Event::Start(e) => {
+                depth += 1;
+                writer.write_event(Event::Start(e))?;
+            }
+            Event::End(e) => {
+                if description == Some(depth) {
+                    description = None;
+                }
+                depth -= 1;
+                writer.write_event(Event::End(e))?;
+            }
+            event => writer.write_event(event)?,
+        }
+    }
+    if !written {
+        return Err("sidecar中没有rdf:Description".into());
+    }
+    Ok(String::from_utf8(writer.into_inner())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::sidecar::{parse, rewrite, XmpData, TEMPLATE};
+
+    #[test]
+    fn test_parse() {
+        // Lightroom的写法
+        let xml = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF>
+            <rdf:Description rdf:about="" xmp:Rating="3" xmp:Label="Green" xmp:CreatorTool="Lightroom"/>
+            <rdf:Description rdf:about="">
+             <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Sunset</rdf:li></rdf:Alt></dc:title>
+             <dc:description><rdf:Alt><rdf:li xml:lang="x-default">at the beach &amp; pier</rdf:li></rdf:Alt></dc:description>
+             <dc:subject><rdf:Bag><rdf:li>alice</rdf:li><rdf:li>beach</rdf:li></rdf:Bag></dc:subject>
+             <lr:hierarchicalSubject><rdf:Bag><rdf:li>people|alice</rdf:li></rdf:Bag></lr:hierarchicalSubject>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let data = parse(xml).unwrap();
+        assert_eq!(data.rating, Some(3));
+        assert_eq!(data.label.as_deref(), Some("green"));
+        assert_eq!(data.title.as_deref(), Some("Sunset"));
+        assert_eq!(data.description.as_deref(), Some("at the beach & pier"));
+        assert_eq!(data.keywords, vec!["people/alice", "beach"]);
+        assert_eq!(data.source, None);
+
+        // darktable把评分写成子元素，拒绝为-1，自定义标记忽略
+        let xml = r#"<x:xmpmeta><rdf:RDF><rdf:Description xmp:Label="To Print">
+            <xmp:Rating>-1</xmp:Rating></rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let data = parse(xml).unwrap();
+        assert_eq!(data.rating, Some(0));
+        assert_eq!(data.label, None);
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let data = XmpData {
+            rating: Some(4),
+            label: Some(String::from("red")),
+            description: Some(String::from("a < b")),
+            keywords: vec![String::from("people/alice"), String::from("beach")],
+            ..Default::default()
+        };
+        let xml = rewrite(TEMPLATE, &data).unwrap();
+        assert!(xml.contains(r#"xmp:Rating="4""#));
+        assert!(xml.contains(r#"xmp:Label="Red""#));
+        assert!(xml.contains(r#"<rdf:li xml:lang="x-default">a &lt; b</rdf:li>"#));
+        assert!(xml.contains("<rdf:li>people|alice</rdf:li>"));
+        assert!(!xml.contains("dc:source"));
+        assert_eq!(parse(&xml).unwrap(), data);
+
+        // 保留其他软件写入的内容，重复写入不会产生多余的属性
+        let other = xml.replace(
+            "xmp:Rating=\"4\"",
+            "xmp:Rating=\"4\" darktable:history_end=\"3\"",
+        );
+        let data = XmpData {
+            rating: None,
+            description: Some(String::from("note")),
+            keywords: Vec::new(),
+            ..data
+        };
+        let xml = rewrite(&rewrite(&other, &data).unwrap(), &data).unwrap();
+        assert!(xml.contains(r#"darktable:history_end="3""#));
+        assert!(!xml.contains("xmp:Rating"));
+        assert!(!xml.contains("dc:subject"));
+        assert_eq!(xml.matches("dc:description>").count(), 2);
+        assert_eq!(xml.matches("xmp:MetadataDate").count(), 1);
+        assert_eq!(parse(&xml).unwrap(), data);
+    }
+
+    #[test]
+    fn test_rewrite_nested() {
+        let xml = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description rdf:about="">
+  <exif:Flash><rdf:Description exif:Fired="True"><exif:Mode>2</exif:Mode></rdf:Description></exif:Flash>
+  <dc:subject><rdf:Bag><rdf:li>old</rdf:li></rdf:Bag></dc:subject>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let data = XmpData {
+            keywords: vec![String::from("new")],
+            ..Default::default()
+        };
+        let xml = rewrite(xml, &data).unwrap();
+        // 嵌套的`rdf:Description`结束后仍然在顶层`rdf:Description`中
+        assert!(xml.contains(r#"<exif:Flash><rdf:Description exif:Fired="True"><exif:Mode>2</exif:Mode></rdf:Description></exif:Flash>"#));
+        assert!(!xml.contains("old"));
+        assert_eq!(xml.matches("<dc:subject>").count(), 1);
+        assert_eq!(parse(&xml).unwrap().keywords, data.keywords);
+    }
+}
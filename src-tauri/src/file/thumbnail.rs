@@ -0,0 +1,282 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use base64::{engine::general_purpose, Engine as _};
+use image::{DynamicImage, ImageFormat, RgbImage};
+use tauri::http::{Request, Response, ResponseBuilder};
+
+use crate::config::get_cache_dir;
+use crate::db::sqlite::Session;
+use crate::file::image_scanner::thumbnail;
+use crate::{error, info, Result};
+
+/// 缩略图协议名称，地址为`thumb://localhost/{sha1}/{size}`
+pub const SCHEME: &str = "thumb";
+
+/// 详情预览的宽度
+const PREVIEW_WIDTH: u32 = 1024;
+/// 迁移旧缩略图时每次读取的行数，避免一次读出全部base64数据
+const LEGACY_CHUNK: i64 = 200;
+
+/// 迁移时从`metadata.thumbnail`保留的旧缩略图
+#[derive(Debug, sqlx::FromRow)]
+struct LegacyThumbnail {
+    metadata_id: i64,
+    sha1: String,
+    /// `data:image/jpeg;base64,`开头的base64数据
+    data: String,
+}
+
+/// 缩略图尺寸
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThumbnailSize {
+    /// 列表，宽度为扫描器配置的`thumbnail_size`
+    Grid,
+    /// 高分屏列表，宽度为`Grid`的两倍
+    Retina,
+    /// 详情预览
+    Preview,
+}
+
+impl ThumbnailSize {
+    /// 从大到小排列，生成时依次缩小
+    pub const ALL: [ThumbnailSize; 3] = [Self::Preview, Self::Retina, Self::Grid];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Grid => "grid",
+            Self::Retina => "retina",
+            Self::Preview => "preview",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.name() == name)
+    }
+
+    /// 实际宽度，`size`为扫描器配置的缩略图宽度
+    pub fn width(&self, size: u32) -> u32 {
+        match self {
+            Self::Grid => size,
+            Self::Retina => size * 2,
+            Self::Preview => PREVIEW_WIDTH.max(size * 2),
+        }
+    }
+}
+
+/// 缓存路径，以sha1命名，内容相同的文件共用缩略图，按前两位分目录避免单个目录文件过多
+pub fn thumbnail_path(sha1: &str, size: ThumbnailSize) -> PathBuf {
+    get_cache_dir()
+        .join("thumbnail")
+        .join(sha1.get(..2).unwrap_or("00"))
+        .join(format!("{sha1}-{}.jpg", size.name()))
+}
+
+/// 前端访问缩略图的地址，Windows下自定义协议需要使用`https://{scheme}.localhost`
+///
+/// 该尺寸和更小的尺寸都没有缓存时返回空字符串
+pub fn thumbnail_url(sha1: &str, size: ThumbnailSize) -> String {
+    if sha1.is_empty() || !is_cached(sha1, size) {
+        return String::new();
+    }
+    if cfg!(windows) {
+        format!("https://{SCHEME}.localhost/{sha1}/{}", size.name())
+    } else {
+        format!("{SCHEME}://localhost/{sha1}/{}", size.name())
+    }
+}
+
+/// 生成所有尺寸的缩略图，图片小于目标尺寸时不放大，返回列表尺寸的图片
+pub fn save_thumbnails(sha1: &str, image: &DynamicImage, size: u32) -> Result<RgbImage> {
+    if sha1.is_empty() {
+        return Err("文件sha1为空，无法缓存缩略图".into());
+    }
+    let mut source = image.clone();
+    for thumbnail_size in ThumbnailSize::ALL {
+        let width = thumbnail_size.width(size);
+        if source.width() > width {
+            source =
+                DynamicImage::ImageRgb8(thumbnail(&source, source.width(), source.height(), width));
+        }
+        let path = thumbnail_path(sha1, thumbnail_size);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        source.to_rgb8().save_with_format(path, ImageFormat::Jpeg)?;
+    }
+    Ok(source.to_rgb8())
+}
+
+/// 保存已编码的JPEG缩略图，用于迁移数据库中的旧缩略图
+pub fn save_encoded(sha1: &str, size: ThumbnailSize, bytes: &[u8]) -> Result<()> {
+    let path = thumbnail_path(sha1, size);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// 把迁移时保留的base64格式旧缩略图移到缓存目录，作为列表尺寸，返回迁移和失败的数量
+///
+/// 迁移成功的行从`legacy_thumbnail`中删除，失败的行保留，下次启动时重试
+pub async fn move_legacy(session: &Session) -> Result<(usize, usize)> {
+    let mut last = i64::MIN;
+    let (mut moved, mut failed) = (0, 0);
+    loop {
+        let rows = session
+            .sql("SELECT metadata_id, sha1, data FROM legacy_thumbnail WHERE metadata_id > ? ORDER BY metadata_id LIMIT ?")
+            .bind(last)
+            .bind(LEGACY_CHUNK)
+            .select_as::<LegacyThumbnail>()
+            .await?;
+        let Some(row) = rows.last() else {
+            break;
+        };
+        last = row.metadata_id;
+        for row in rows {
+            let data = row.data.split_once(',').map_or("", |(_, v)| v);
+            let saved = general_purpose::STANDARD
+                .decode(data)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| {
+                    save_encoded(&row.sha1, ThumbnailSize::Grid, &bytes).map_err(|e| e.to_string())
+                });
+            if let Err(e) = saved {
+                error!("旧缩略图迁移失败：{} {e}", row.metadata_id);
+                failed += 1;
+                continue;
+            }
+            session
+                .sql("DELETE FROM legacy_thumbnail WHERE metadata_id = ?")
+                .bind(row.metadata_id)
+                .execute()
+                .await?;
+            moved += 1;
+        }
+    }
+    if moved + failed > 0 {
+        info!("已迁移 {moved} 个缩略图到缓存目录，{failed} 个失败");
+    }
+    Ok((moved, failed))
+}
+
+/// 是否有该尺寸或更小尺寸的缩略图
+fn is_cached(sha1: &str, size: ThumbnailSize) -> bool {
+    ThumbnailSize::ALL
+        .into_iter()
+        .skip_while(|v| *v != size)
+        .any(|v| thumbnail_path(sha1, v).exists())
+}
+
+/// 读取缩略图，缺少该尺寸时依次使用更小的尺寸
+pub fn read_thumbnail(sha1: &str, size: ThumbnailSize) -> Option<Vec<u8>> {
+    // sha1只包含十六进制字符，防止路径穿越
+    if sha1.is_empty() || !sha1.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    ThumbnailSize::ALL
+        .into_iter()
+        .skip_while(|v| *v != size)
+        .find_map(|v| std::fs::read(thumbnail_path(sha1, v)).ok())
+}
+
+/// 处理缩略图协议请求，内容按sha1寻址，可以长期缓存
+pub fn protocol(request: &Request) -> std::result::Result<Response, Box<dyn Error>> {
+    let found = parse_uri(request.uri()).and_then(|(sha1, size)| read_thumbnail(sha1, size));
+    match found {
+        Some(bytes) => ResponseBuilder::new()
+            .mimetype("image/jpeg")
+            .header("Cache-Control", "max-age=31536000, immutable")
+            .body(bytes),
+        None => ResponseBuilder::new().status(404).body(Vec::new()),
+    }
+}
+
+/// 解析`{sha1}/{size}`，忽略协议和主机名
+fn parse_uri(uri: &str) -> Option<(&str, ThumbnailSize)> {
+    let path = uri.split_once("://").map_or(uri, |(_, v)| v);
+    let (_, path) = path.split_once('/')?;
+    let path = path.split(['?', '#']).next()?;
+    let (sha1, size) = path.trim_end_matches('/').split_once('/')?;
+    Some((sha1, ThumbnailSize::parse(size)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use base64::{engine::general_purpose, Engine as _};
+    use image::{DynamicImage, ImageFormat, RgbImage};
+
+    use crate::db::migration::migrate;
+    use crate::db::sqlite::temp_session;
+    use crate::file::thumbnail::{
+        move_legacy, parse_uri, save_thumbnails, thumbnail_path, thumbnail_url, ThumbnailSize,
+    };
+
+    #[test]
+    fn test_parse_uri() {
+        assert_eq!(
+            parse_uri("thumb://localhost/ab12/grid"),
+            Some(("ab12", ThumbnailSize::Grid))
+        );
+        assert_eq!(
+            parse_uri("https://thumb.localhost/ab12/retina?v=1"),
+            Some(("ab12", ThumbnailSize::Retina))
+        );
+        assert_eq!(parse_uri("thumb://localhost/ab12/huge"), None);
+        assert_eq!(parse_uri("thumb://localhost/ab12"), None);
+        assert_eq!(ThumbnailSize::Preview.width(200), 1024);
+        assert_eq!(ThumbnailSize::Retina.width(200), 400);
+    }
+
+    #[test]
+    fn test_save_thumbnails() {
+        let sha1 = "fe01";
+        for size in ThumbnailSize::ALL {
+            let _ = std::fs::remove_file(thumbnail_path(sha1, size));
+        }
+        assert!(thumbnail_url(sha1, ThumbnailSize::Grid).is_empty());
+        let image = DynamicImage::ImageRgb8(RgbImage::new(1200, 600));
+        let grid = save_thumbnails(sha1, &image, 200).unwrap();
+        assert_eq!(grid.dimensions(), (200, 100));
+        let preview = image::open(thumbnail_path(sha1, ThumbnailSize::Preview)).unwrap();
+        assert_eq!(preview.width(), 1024);
+        assert!(!thumbnail_url(sha1, ThumbnailSize::Preview).is_empty());
+        assert!(save_thumbnails("", &image, 200).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_move_legacy() {
+        let session = temp_session("legacy_thumbnail").await;
+        migrate(&session).await.unwrap();
+        let mut bytes = Vec::new();
+        RgbImage::new(4, 4)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+            .unwrap();
+        let data = format!(
+            "data:image/jpeg;base64,{}",
+            general_purpose::STANDARD.encode(&bytes)
+        );
+        for (id, sha1, data) in [(1, "fe02", data.as_str()), (2, "fe03", "data:,%%%")] {
+            session
+                .sql("INSERT INTO legacy_thumbnail (metadata_id, sha1, data) VALUES (?, ?, ?)")
+                .bind(id)
+                .bind(sha1)
+                .bind(data)
+                .execute()
+                .await
+                .unwrap();
+        }
+        assert_eq!(move_legacy(&session).await.unwrap(), (1, 1));
+        let saved = std::fs::read(thumbnail_path("fe02", ThumbnailSize::Grid)).unwrap();
+        assert_eq!(saved, bytes);
+        // 失败的缩略图保留，下次重试
+        let result = session
+            .count("SELECT COUNT(*) AS count FROM legacy_thumbnail WHERE metadata_id = 2")
+            .await
+            .unwrap();
+        assert_eq!(result.count, 1);
+    }
+}
@@ -1,100 +1,98 @@
 use std::path::Path;
 use std::process::Command;
 
-use base64::Engine;
-use base64::engine::general_purpose;
+use image::DynamicImage;
 
+use crate::config::get_config;
 use crate::db::entity::metadata::Metadata;
 use crate::db::entity::task::{Task, TaskStatus};
+use crate::file::ffprobe::{probe, run};
+use crate::file::image_scanner::calculated_shape;
+use crate::file::preview;
+use crate::file::registry::ScannerOptions;
 use crate::file::scan::{Context, Scanner};
+use crate::file::thumbnail::{save_thumbnails, ThumbnailSize};
+use crate::util::error::ErrorHandle;
 use crate::Result;
 
-pub struct VideoScanner {}
+pub struct VideoScanner {
+    options: ScannerOptions,
+}
 
 impl VideoScanner {
-    pub fn wrap() -> Box<Self> {
-        Box::new(VideoScanner {})
+    pub fn wrap(options: ScannerOptions) -> Box<Self> {
+        Box::new(VideoScanner { options })
     }
 }
 
 impl Scanner for VideoScanner {
-    fn is_support(&self, suffix: &str) -> bool {
-        match suffix {
-            "mp4" | "webm" | "ogg" => true,
-            _ => false,
-        }
+    fn options(&self) -> &ScannerOptions {
+        &self.options
     }
 
     fn scan(&self, task: &Task, context: &Context) -> TaskStatus {
         let mut status = TaskStatus::new(task.id);
         if self.is_support(task.file_suffix.as_str()) {
             let path = task.file_path.clone();
+            let size = self.options.thumbnail_size;
             let runtime = context.runtime.handle().clone();
             status.handle(runtime.clone().spawn_blocking(move || {
                 let path = Path::new(path.as_str());
                 let mut metadata = Metadata::load(path);
-                if metadata.analyze_metadata(path).is_ok() {
-                    if analyze_video_metadata(path, &mut metadata).is_ok() {
-                        // 使用阻塞线程防止数据丢失！
-                        runtime.block_on(async move {
-                            metadata.save_to_db().await;
-                        });
-                        return true;
-                    };
-                };
-                false
+                metadata.analyze_metadata(path).map_err(|e| e.to_string())?;
+                analyze_video_metadata(path, &mut metadata, size).map_err(|e| e.to_string())?;
+                // 使用阻塞线程防止数据丢失！
+                runtime.block_on(async move {
+                    metadata.save_to_db().await;
+                });
+                Ok(())
             }));
         }
         status
     }
 }
-fn analyze_video_metadata(path: &Path, metadata: &mut Metadata) -> Result<()> {
-    metadata.duration = duration(path)?;
-    metadata.thumbnail = thumbnail(path)?;
 
+/// 解析视频元数据
+fn analyze_video_metadata(path: &Path, metadata: &mut Metadata, size: u32) -> Result<()> {
+    let probe = probe(path)?;
+    metadata.duration = probe.duration();
+    if let Some((width, height)) = probe.dimensions() {
+        metadata.image_width = width;
+        metadata.image_height = height;
+        metadata.shape = calculated_shape(width, height);
+    }
+    let mut info = probe.info();
+    // 预览生成失败不影响扫描结果
+    let config = &get_config().preview;
+    if let Some(Some((format, frames))) =
+        preview::generate(path, &metadata.sha1, metadata.duration, config).print_error()
+    {
+        info.preview_format = format;
+        info.preview_frames = frames as i64;
+    }
+    metadata.video = Some(info);
+    let image = thumbnail(path, ThumbnailSize::Preview.width(size))?;
+    save_thumbnails(&metadata.sha1, &image, size)?;
     Ok(())
 }
 
-
-fn duration(path: &Path) -> Result<i64>{
-    // 定义 FFmpeg 命令
-    let output = Command::new("ffprobe")
-        .args(&[
-            "-v", "error",             // 设置输出日志级别为 error，以避免输出额外信息
-            "-show_entries", "format=duration",  // 指定要显示的信息为视频时长
-            "-of", "default=noprint_wrappers=1:nokey=1",  // 设置输出格式为纯文本
-            path.to_str().unwrap()               // 输入视频文件
-        ])
-        .output()?;         // 执行 FFmpeg 命令并获取输出
-
-    let duration_str = String::from_utf8_lossy(&output.stdout);
-    let duration: f64 = duration_str.trim().parse().unwrap();
-    Ok((duration * 1000f64) as i64)
-}
-/// 生成缩咯图
-fn thumbnail(path: &Path) -> Result<String> {
-    // 定义 ffmpeg 命令
-    let output = Command::new("ffmpeg")
-        .args(&[
-            "-i",
-            path.to_str().unwrap(),
+/// 截取有代表性的一帧，宽度不超过`size`
+fn thumbnail(path: &Path, size: u32) -> Result<DynamicImage> {
+    let output = run(
+        "ffmpeg",
+        Command::new("ffmpeg").arg("-i").arg(path).args([
             "-vf",
-            "thumbnail,scale=320:-1",
+            // 不放大分辨率较低的视频
+            &format!("thumbnail,scale=w='min({size},iw)':h=-2"),
             "-frames:v",
             "1",
             "-f",
             "image2pipe",
             "-",
-        ])
-        .output()?;
-    // 检查命令执行是否成功
-    if !output.status.success() {
-        return Err("Failed to execute ffmpeg command".into());
+        ]),
+    )?;
+    if output.stdout.is_empty() {
+        return Err("ffmpeg没有输出缩略图".into());
     }
-    let buffer = output.stdout;
-
-    // 将输出转换为 Base64 编码的字符串
-    let base64 = general_purpose::STANDARD.encode(&buffer);
-
-    Ok(format!("data:image/jpg;base64,{}", base64))
+    Ok(image::load_from_memory(&output.stdout)?)
 }
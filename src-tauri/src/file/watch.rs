@@ -0,0 +1,265 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use once_cell::sync::Lazy;
+use tokio::sync::mpsc::{channel, unbounded_channel, UnboundedReceiver};
+
+use crate::config::get_db_path;
+use crate::db::entity::basket::Basket;
+use crate::db::entity::folder::Folder;
+use crate::db::entity::metadata::MetadataStat;
+use crate::db::sqlite::Session;
+use crate::file::registry::scanners;
+use crate::file::rescan::Rescan;
+use crate::file::scan::{is_hidden, ScanJob, ScanMsg};
+use crate::util::error::ErrorHandle;
+use crate::{error, info};
+
+/// 事件防抖时间，同一时间段内的事件合并处理
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// 正在监听的篮子，移除时停止监听
+static WATCHERS: Lazy<Mutex<HashMap<String, Debouncer<RecommendedWatcher>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 监听所有篮子，程序启动时调用
+pub async fn start_all() {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    if let Some(baskets) = session
+        .select_as::<Basket>("SELECT * FROM basket")
+        .await
+        .print_error()
+    {
+        for basket in baskets {
+            start(basket.id.to_string()).await;
+        }
+    }
+}
+
+/// 监听篮子关联的所有目录，已在监听时重新监听
+pub async fn start(basket_id: String) {
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+    let directories = Basket::directories(&session, &basket_id).await;
+    if directories.is_empty() {
+        return;
+    }
+
+    let (tx, rx) = unbounded_channel::<Vec<PathBuf>>();
+    let debouncer = new_debouncer(DEBOUNCE, move |result: DebounceEventResult| match result {
+        Ok(events) => {
+            tx.send(events.into_iter().map(|v| v.path).collect())
+                .print_error();
+        }
+        Err(e) => error!("{e}"),
+    });
+    if let Some(mut debouncer) = debouncer.print_error() {
+        for directory in directories.iter() {
+            if debouncer
+                .watcher()
+                .watch(Path::new(directory), RecursiveMode::Recursive)
+                .print_error()
+                .is_some()
+            {
+                info!("<watch:{}> 监听路径：{}", basket_id, directory);
+            }
+        }
+        tokio::spawn(handle_events(basket_id.clone(), directories, rx));
+        // 替换旧的监听，旧的事件处理随之结束
+        if let Ok(mut watchers) = WATCHERS.lock() {
+            watchers.insert(basket_id, debouncer);
+        }
+    }
+}
+
+/// 停止监听篮子
+pub fn stop(basket_id: &str) {
+    if let Ok(mut watchers) = WATCHERS.lock() {
+        watchers.remove(basket_id);
+    }
+}
+
+async fn handle_events(
+    basket_id: String,
+    directories: Vec<String>,
+    mut rx: UnboundedReceiver<Vec<PathBuf>>,
+) {
+    while let Some(mut paths) = rx.recv().await {
+        // 合并处理期间堆积的事件
+        while let Ok(more) = rx.try_recv() {
+            paths.extend(more);
+        }
+        let paths = coalesce(paths, &directories);
+        if !paths.is_empty() {
+            handle_paths(&basket_id, &directories, paths).await;
+        }
+    }
+    info!("<watch:{}> 停止监听", basket_id);
+}
+
+/// 处理变化的路径
+///
+/// 新增或修改的文件生成扫描任务交给扫描器处理，移动的文件更新路径，删除的文件标记为丢失
+async fn handle_paths(basket_id: &str, directories: &[String], paths: Vec<PathBuf>) {
+    let start = Instant::now();
+    let mut session = Session::new(get_db_path());
+    session.connect().await;
+
+    let (tx, rx) = channel::<ScanMsg>(16);
+    let mut job = ScanJob::new(tx);
+    job.set_kind("watch");
+    job.add_scanners(scanners());
+    job.monitor_async(rx);
+
+    let mut catalog = Vec::new();
+    let mut file_list = Vec::new();
+    for path in paths.iter() {
+        let Some(full_path) = path.to_str() else {
+            continue;
+        };
+        if path.is_file() {
+            if job.is_support(path) {
+                catalog.extend(
+                    MetadataStat::get_by_path(&session, full_path)
+                        .await
+                        .print_error()
+                        .flatten(),
+                );
+                file_list.push(path.clone());
+            }
+        } else if path.is_dir() {
+            // 新建或移入的文件夹，遍历其中的文件
+            job.parent_folder_id = match path.parent().and_then(|v| v.to_str()) {
+                Some(parent) => Folder::get_by_path(&session, parent.to_string())
+                    .await
+                    .map_or(0, |v| v.id),
+                None => 0,
+            };
+            job.load_dir(vec![full_path.to_string()]).await;
+            catalog.extend(MetadataStat::list_by_directory(&session, full_path).await);
+        } else {
+            // 已删除或移走的文件和文件夹
+            catalog.extend(
+                MetadataStat::get_by_path(&session, full_path)
+                    .await
+                    .print_error()
+                    .flatten(),
+            );
+            catalog.extend(MetadataStat::list_by_directory(&session, full_path).await);
+            remove_folders(&session, directories, full_path).await;
+        }
+    }
+    job.save_folder(&session).await;
+    file_list.append(&mut job.file_list);
+
+    let summary = Rescan::new(&session).run_files(catalog, &file_list).await;
+    info!(
+        "<watch:{}> 文件变化：新增{}个，修改{}个，移动{}个，删除{}个,代码运行时间为{:?}秒",
+        basket_id,
+        summary.added,
+        summary.updated,
+        summary.moved,
+        summary.removed,
+        (Instant::now() - start).as_secs()
+    );
+    if summary.added + summary.updated > 0 {
+        // 只执行这批文件的任务，队列中的其他任务由各自的扫描执行
+        job.set_scope(
+            file_list
+                .iter()
+                .filter_map(|v| v.to_str())
+                .map(String::from)
+                .collect(),
+        );
+        job.run_scanner(&session).await;
+    }
+}
+
+async fn remove_folders(session: &Session, directories: &[String], path: &str) {
+    // 篮子的根目录保留，重新出现时可以继续使用
+    if !directories.iter().any(|v| v == path) {
+        if let Some(folder) = Folder::get_by_path(session, path.to_string()).await {
+            folder.delete(session).await;
+        }
+    }
+    for folder in Folder::list_by_directory(session, path).await {
+        folder.delete(session).await;
+    }
+}
+
+/// 合并同一批事件的路径，去掉重复和被忽略的路径
+///
+/// 上级路径也有变化时，下级路径会随上级的遍历或删除一起处理，不再单独处理
+fn coalesce(paths: Vec<PathBuf>, directories: &[String]) -> Vec<PathBuf> {
+    let paths: BTreeSet<PathBuf> = paths
+        .into_iter()
+        .filter(|v| !is_ignored(v, directories))
+        .collect();
+    paths
+        .iter()
+        .filter(|v| !v.ancestors().skip(1).any(|parent| paths.contains(parent)))
+        .cloned()
+        .collect()
+}
+
+/// 忽略根目录下的隐藏文件和文件夹
+fn is_ignored(path: &Path, directories: &[String]) -> bool {
+    directories
+        .iter()
+        .filter_map(|v| path.strip_prefix(v).ok())
+        .any(|v| v.components().any(|v| is_hidden(Path::new(v.as_os_str()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::file::watch::{coalesce, is_ignored};
+
+    #[test]
+    fn test_is_ignored() {
+        let directories = vec![String::from("/data/.pictures")];
+        // 根目录本身是隐藏文件夹时不忽略
+        assert!(!is_ignored(
+            Path::new("/data/.pictures/a.jpg"),
+            &directories
+        ));
+        assert!(is_ignored(
+            Path::new("/data/.pictures/.cache/a.jpg"),
+            &directories
+        ));
+        assert!(is_ignored(
+            Path::new("/data/.pictures/a/.b.jpg"),
+            &directories
+        ));
+        assert!(!is_ignored(Path::new("/data/other/.b.jpg"), &directories));
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let directories = vec![String::from("/data")];
+        let paths = [
+            "/data/b.jpg",
+            "/data/new/sub/1.jpg",
+            "/data/new",
+            "/data/b.jpg",
+            "/data/.git/index",
+            "/data/new/sub",
+            "/data/a.jpg",
+        ];
+        let paths = coalesce(paths.into_iter().map(PathBuf::from).collect(), &directories);
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/data/a.jpg"),
+                PathBuf::from("/data/b.jpg"),
+                PathBuf::from("/data/new"),
+            ]
+        );
+    }
+}
@@ -1,7 +1,11 @@
 use dotenv::dotenv;
 use tauri::Manager;
 
-use pixel_basket::config::set_db_path;
+use pixel_basket::config::{get_db_path, set_cache_dir, set_config, set_db_path};
+use pixel_basket::db::entity::image_hash::backfill_hash;
+use pixel_basket::db::migration::migrate_db;
+use pixel_basket::db::sqlite::Session;
+use pixel_basket::file::{thumbnail, watch};
 use pixel_basket::util::error::ErrorHandle;
 use pixel_basket::{basket, APP_HANDLE};
 
@@ -16,18 +20,57 @@ async fn main() {
             basket::create_basket,
             basket::get_metadata,
             basket::del_metadata,
+            basket::query_metadata,
+            basket::search_metadata,
+            basket::search_by_color,
+            basket::find_similar,
+            basket::get_duplicates,
+            basket::resolve_duplicates,
+            basket::get_duplicate_log,
+            basket::get_tags,
+            basket::create_tag,
+            basket::update_tag,
+            basket::merge_tags,
+            basket::delete_tags,
+            basket::tag_metadata,
+            basket::annotate_metadata,
             basket::get_metadata_by_id,
             basket::get_metadata_like_path,
+            basket::get_metadata_by_layer,
+            basket::get_video_preview,
             basket::get_basket,
             basket::del_basket,
             basket::get_folder,
-            basket::run_task
+            basket::run_task,
+            basket::rescan_basket,
+            basket::get_failed_tasks,
+            basket::retry_tasks,
+            basket::list_jobs,
+            basket::pause_job,
+            basket::resume_job,
+            basket::cancel_job
         ])
+        .register_uri_scheme_protocol(thumbnail::SCHEME, |_app, request| {
+            thumbnail::protocol(request)
+        })
         .setup(move |app| {
             // 设置 AppHandle 的值
             let mut handle = APP_HANDLE.lock().unwrap();
             *handle = Some(app.app_handle());
             set_db_path(app.app_handle());
+            set_config(app.app_handle());
+            set_cache_dir(app.app_handle());
+            // 启动前完成数据库迁移，数据库版本过高时拒绝启动
+            tokio::task::block_in_place(|| {
+                tokio::runtime::Handle::current().block_on(migrate_db(get_db_path()))
+            })?;
+            tokio::spawn(async {
+                let mut session = Session::new(get_db_path());
+                session.connect().await;
+                thumbnail::move_legacy(&session).await.print_error();
+                backfill_hash(&session).await.print_error();
+            });
+            tokio::spawn(watch::start_all());
             Ok(())
         })
         .run(tauri::generate_context!())
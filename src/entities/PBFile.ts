@@ -11,9 +11,16 @@ export default class PBFile {
   imageWidth = ""
 
   score = 0
+  title = ""
+  label = ""
+  exegesis = ""
+  sourceUrl = ""
+  annotated = ""
   shape = ""
   tags = ""
   thumbnail = ""
+  thumbnailRetina = ""
+  thumbnailPreview = ""
 
   added = ""
   created = ""
@@ -1,5 +1,18 @@
 type EventType = "task_start" | "task_running" | "task_failed" | "task_completed" | "task_canceled"
+export interface ScanProgress {
+  discovered: number
+  queued: number
+  total: number
+  completed: number
+  failed: number
+  throughput: number
+  eta?: number
+  recentCompleted: string[]
+  recentFailed: string[]
+  rescan?: any
+}
 export default class TaskEvent {
+  id?: string
   stage:EventType = "task_start"
   type = ""
   progress = 0
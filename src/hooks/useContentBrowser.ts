@@ -2,17 +2,105 @@ import {ref} from "vue";
 import PBFile from "../entities/PBFile.ts";
 import {invoke} from "@tauri-apps/api";
 
+interface Cursor {
+  key: number | string
+  id: string
+}
+
+interface SearchResult {
+  total: number
+  items: { metadata: PBFile }[]
+}
+
+interface PageResult {
+  total: number
+  items: PBFile[]
+  next?: Cursor
+}
+
+const PAGE_SIZE = 200
+
 const files = ref<PBFile[]>([])
+const total = ref(0)
+// 当前查询条件和下一页游标
+let query: Record<string, unknown> = {}
+let next: Cursor | undefined
+let loading = false
 
 const useContentBrowser = () => {
   return {
     load,
-    files
+    loadMore,
+    search,
+    searchColor,
+    files,
+    total
   }
 }
 
+const fetchPage = (after?: Cursor) => {
+  return invoke<PageResult>("query_metadata", {
+    query: {...query, page: {size: PAGE_SIZE, current: 1}, after}
+  })
+}
+
 const load = async (path:string,like=true) => {
-  files.value = await invoke<PBFile[]>("get_metadata_like_path",{path,like})
+  query = {folder: path, recursive: like}
+  await reload()
+}
+
+const reload = async () => {
+  const result = await fetchPage()
+  files.value = result.items
+  total.value = result.total
+  next = result.next
+}
+
+// 全文搜索，关键字为空时恢复当前目录
+const search = async (keyword: string) => {
+  if (!keyword.trim()) {
+    await reload()
+    return
+  }
+  const current = query
+  const result = await invoke<SearchResult>("search_metadata", {
+    keyword,
+    page: {size: PAGE_SIZE, current: 1}
+  })
+  if (current !== query) return
+  files.value = result.items.map(v => v.metadata)
+  total.value = result.total
+  next = undefined
+}
+
+// 按主题色搜索，颜色越接近、占比越大越靠前
+const searchColor = async (color: string) => {
+  const current = query
+  const result = await invoke<SearchResult>("search_by_color", {
+    filter: {color},
+    page: {size: PAGE_SIZE, current: 1}
+  })
+  if (current !== query) return
+  files.value = result.items.map(v => v.metadata)
+  total.value = result.total
+  next = undefined
+}
+
+// 滚动到底部时加载下一页
+const loadMore = async () => {
+  if (!next || loading) return
+  loading = true
+  const current = query
+  try {
+    const result = await fetchPage(next)
+    // 加载期间切换了目录，丢弃旧结果
+    if (current !== query) return
+    files.value.push(...result.items)
+    total.value = result.total
+    next = result.next
+  } finally {
+    loading = false
+  }
 }
 
 export default useContentBrowser